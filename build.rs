@@ -0,0 +1,24 @@
+use std::{
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Record the commit and time the server was built at, for `GET /version`.
+fn main() {
+    // Deployments may be built without the repository around, so don't fail.
+    let sha = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|sha| sha.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let built_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("the clock is before 1970")
+        .as_secs();
+
+    println!("cargo:rustc-env=GIT_SHA={}", sha);
+    println!("cargo:rustc-env=BUILT_AT={}", built_at);
+}
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS pastes
+(
+    id      uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    content TEXT NOT NULL
+);
@@ -0,0 +1,2 @@
+ALTER TABLE pastes
+    ADD COLUMN IF NOT EXISTS created_at timestamptz NOT NULL DEFAULT now();
@@ -0,0 +1,2 @@
+ALTER TABLE pastes
+    ADD COLUMN IF NOT EXISTS burn BOOLEAN NOT NULL DEFAULT false;
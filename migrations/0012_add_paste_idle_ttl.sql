@@ -0,0 +1,3 @@
+ALTER TABLE pastes
+    ADD COLUMN IF NOT EXISTS idle_ttl INTERVAL,
+    ADD COLUMN IF NOT EXISTS last_accessed_at timestamptz NOT NULL DEFAULT now();
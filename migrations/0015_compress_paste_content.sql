@@ -0,0 +1,12 @@
+-- Content is stored zstd compressed where that makes it smaller, so it's kept
+-- as bytes, along with whether they're compressed and the size of the content
+-- they hold.
+ALTER TABLE pastes
+    ALTER COLUMN content TYPE BYTEA USING convert_to(content, 'UTF8'),
+    ADD COLUMN IF NOT EXISTS compressed BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN IF NOT EXISTS size INTEGER;
+
+UPDATE pastes SET size = octet_length(content);
+
+ALTER TABLE pastes
+    ALTER COLUMN size SET NOT NULL;
@@ -0,0 +1,4 @@
+ALTER TABLE pastes
+    ADD COLUMN IF NOT EXISTS owner_key_hash BYTEA;
+
+CREATE INDEX IF NOT EXISTS pastes_owner_key_hash_idx ON pastes (owner_key_hash);
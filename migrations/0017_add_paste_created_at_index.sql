@@ -0,0 +1,2 @@
+-- Pastes are listed and searched newest first, a page or window at a time.
+CREATE INDEX IF NOT EXISTS pastes_created_at_idx ON pastes (created_at DESC, id);
@@ -2,6 +2,26 @@ DROP TABLE IF EXISTS pastes;
 
 CREATE TABLE pastes
 (
-    id      uuid PRIMARY KEY DEFAULT gen_random_uuid(),
-    content TEXT NOT NULL
-);
\ No newline at end of file
+    id            uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    content       BYTEA NOT NULL,
+    compressed    BOOLEAN NOT NULL DEFAULT false,
+    size          INTEGER NOT NULL,
+    expires_at    timestamptz,
+    created_at    timestamptz NOT NULL DEFAULT now(),
+    slug          TEXT UNIQUE,
+    password_hash TEXT,
+    burn          BOOLEAN NOT NULL DEFAULT false,
+    views         BIGINT NOT NULL DEFAULT 0,
+    language      TEXT,
+    content_hash  BYTEA UNIQUE,
+    delete_token_hash BYTEA,
+    idempotency_key TEXT UNIQUE,
+    idle_ttl      INTERVAL,
+    last_accessed_at timestamptz NOT NULL DEFAULT now(),
+    title         TEXT,
+    public        BOOLEAN NOT NULL DEFAULT false,
+    owner_key_hash BYTEA
+);
+
+CREATE INDEX pastes_owner_key_hash_idx ON pastes (owner_key_hash);
+CREATE INDEX pastes_created_at_idx ON pastes (created_at DESC, id);
@@ -1,9 +1,53 @@
-use std::sync::Arc;
+use std::{path::Path, sync::Arc, time::Duration};
 
-use sqlx::PgPool;
-use syntect::{highlighting::ThemeSet, parsing::SyntaxSet};
+use anyhow::Context;
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use syntect::{
+    highlighting::{Theme, ThemeSet},
+    parsing::SyntaxSet,
+};
 
-use crate::paste::PasteStore;
+use crate::{
+    cache::HighlightCache,
+    error::Result,
+    filter::ContentFilter,
+    highlight::{
+        self, HighlightErrorPolicy, LangAllowlist, UnknownLangPolicy, DEFAULT_THEME,
+    },
+    markdown::MarkdownAllowlist,
+    paste::{self, retry::RetryingStore, PasteStore, PgPasteStore},
+    rate_limit::RateLimiter,
+};
+
+/// The default maximum size of a paste, in bytes.
+pub const DEFAULT_MAX_PASTE_SIZE: usize = 1024 * 1024;
+
+/// The default number of pastes each search reads through, see
+/// [App::search_window].
+pub const DEFAULT_SEARCH_WINDOW: u32 = 10_000;
+
+/// The default total size of the highlighted output cached, in bytes.
+pub const DEFAULT_HIGHLIGHT_CACHE_SIZE: usize = 64 * 1024 * 1024;
+
+/// The default time highlighting a paste may take before it's served as is.
+pub const DEFAULT_HIGHLIGHT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The default most lines a paste may have to be highlighted, rather than
+/// served as is.
+pub const DEFAULT_MAX_HIGHLIGHT_LINES: usize = 50_000;
+
+/// The default number of uploads each client may make per
+/// [DEFAULT_UPLOAD_WINDOW].
+pub const DEFAULT_UPLOAD_LIMIT: u32 = 30;
+
+/// The default window of time [DEFAULT_UPLOAD_LIMIT] applies to.
+pub const DEFAULT_UPLOAD_WINDOW: Duration = Duration::from_secs(60);
+
+/// The default most connections to the database at once, as Shuttle uses.
+const DEFAULT_MAX_CONNECTIONS: u32 = 5;
+
+/// The default time to wait for a connection to the database to free up.
+const DEFAULT_ACQUIRE_TIMEOUT: Duration = Duration::from_secs(30);
 
 /// Application state.
 ///
@@ -18,15 +62,578 @@ pub struct App {
     pub pastes: Arc<dyn PasteStore>,
     pub syntax_set: Arc<SyntaxSet>,
     pub theme_set: Arc<ThemeSet>,
+    /// Name of the theme to highlight with when none is requested, which is
+    /// always in [App::theme_set].
+    pub default_theme: String,
+    /// Recently highlighted pastes.
+    pub highlight_cache: Arc<HighlightCache>,
+    /// How long highlighting a paste may take, see [highlight::with_timeout].
+    pub highlight_timeout: Duration,
+    /// The most lines a paste may have to be highlighted, see
+    /// [highlight::too_many_lines]. Longer ones are served as is, like those
+    /// that take too long.
+    pub max_highlight_lines: usize,
+    /// What to do when a line of a paste can't be highlighted.
+    pub highlight_errors: HighlightErrorPolicy,
+    /// What to do when a paste is asked for highlighted as a language there's
+    /// no syntax for.
+    pub unknown_lang: UnknownLangPolicy,
+    /// The languages pastes may be highlighted as when asked for, any others
+    /// being treated as if there's no syntax for them.
+    pub allowed_langs: Arc<LangAllowlist>,
+    /// The largest paste, in bytes, that can be uploaded.
+    ///
+    /// Note that axum's `DefaultBodyLimit` (2 MB) still applies on top of this
+    /// to bodies other than raw ones, which are streamed in rather than
+    /// buffered by axum.
+    pub max_paste_size: usize,
+    /// The most pastes there may be at once, if there's a limit.
+    pub max_pastes: Option<u64>,
+    /// How many pastes each search goes through, newest first, older ones
+    /// being searched a window at a time by carrying on from where the last
+    /// search stopped. Compressed pastes in the window have their content read
+    /// and decompressed, so this bounds the cost of a search.
+    pub search_window: u32,
+    /// Limits how often each client can upload pastes.
+    pub upload_limiter: Arc<RateLimiter>,
+    /// The bearer token that grants access to the admin routes, which are
+    /// disabled if there is none.
+    pub admin_token: Option<String>,
+    /// What pastes mustn't contain.
+    pub content_filter: Arc<ContentFilter>,
+    /// What rendered Markdown is sanitized by, built from a
+    /// [MarkdownAllowlist].
+    pub markdown_sanitizer: Arc<ammonia::Builder<'static>>,
+    /// The HTML page served at `/` in place of the usage text, if any.
+    pub landing_page: Option<String>,
 }
 
-impl App {
-    // Construct application state with a postgres connection pool.
-    pub fn postgres(pool: PgPool) -> Self {
+/// Pick the default theme from the one configured, if it exists.
+///
+/// A missing theme is only logged rather than failing startup, since pastes are
+/// still perfectly readable with [DEFAULT_THEME].
+fn default_theme(theme_set: &ThemeSet, configured: Option<String>) -> String {
+    match configured {
+        Some(name) if theme_set.themes.contains_key(&name) => name,
+        Some(name) => {
+            tracing::warn!(
+                "default theme {:?} doesn't exist, using {:?} instead",
+                name,
+                DEFAULT_THEME
+            );
+            DEFAULT_THEME.to_string()
+        }
+        None => DEFAULT_THEME.to_string(),
+    }
+}
+
+/// How the database connection pool is sized.
+#[derive(Debug, PartialEq, Eq)]
+struct PoolConfig {
+    max_connections: u32,
+    acquire_timeout: Duration,
+}
+
+impl PoolConfig {
+    /// Read the config with `var`, from `DB_MAX_CONNECTIONS` and
+    /// `DB_ACQUIRE_TIMEOUT` (in seconds), falling back to the defaults for
+    /// those that aren't set.
+    ///
+    /// Unlike the default theme, a bad value fails startup, since guessing
+    /// wrong could take the whole app down under load.
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        let max_connections = match var("DB_MAX_CONNECTIONS") {
+            Some(max) => max.parse().context("Invalid DB_MAX_CONNECTIONS")?,
+            None => DEFAULT_MAX_CONNECTIONS,
+        };
+        let acquire_timeout = match var("DB_ACQUIRE_TIMEOUT") {
+            Some(secs) => {
+                Duration::from_secs(secs.parse().context("Invalid DB_ACQUIRE_TIMEOUT")?)
+            }
+            None => DEFAULT_ACQUIRE_TIMEOUT,
+        };
+
+        Ok(Self {
+            max_connections,
+            acquire_timeout,
+        })
+    }
+
+    /// Make a pool like `pool`, connecting to the same database, but sized by
+    /// this config.
+    ///
+    /// Shuttle connects the pool it hands us itself, with no way to size it.
+    fn resize(&self, pool: &PgPool) -> PgPool {
+        PgPoolOptions::new()
+            .max_connections(self.max_connections)
+            .acquire_timeout(self.acquire_timeout)
+            .connect_lazy_with((*pool.connect_options()).clone())
+    }
+}
+
+/// Builds an [App] from the defaults, with whatever they're overridden by, so
+/// that the app and the one tests use are put together the same way.
+pub struct AppBuilder {
+    pastes: Arc<dyn PasteStore>,
+    syntax_set: SyntaxSet,
+    theme_set: ThemeSet,
+    default_theme: Option<String>,
+    max_paste_size: usize,
+    max_pastes: Option<u64>,
+    search_window: u32,
+    admin_token: Option<String>,
+    content_filter: ContentFilter,
+    unknown_lang: UnknownLangPolicy,
+    allowed_langs: LangAllowlist,
+    max_highlight_lines: usize,
+    highlight_cache_size: usize,
+    highlight_timeout: Duration,
+    highlight_errors: HighlightErrorPolicy,
+    markdown: MarkdownAllowlist,
+    landing_page: Option<String>,
+    upload_limit: u32,
+    upload_window: Duration,
+}
+
+impl AppBuilder {
+    /// Start building an app that keeps its pastes in `pastes`.
+    pub fn new(pastes: Arc<dyn PasteStore>) -> Self {
+        Self {
+            pastes,
+            syntax_set: SyntaxSet::load_defaults_newlines(),
+            theme_set: ThemeSet::load_defaults(),
+            default_theme: None,
+            max_paste_size: DEFAULT_MAX_PASTE_SIZE,
+            max_pastes: None,
+            search_window: DEFAULT_SEARCH_WINDOW,
+            admin_token: None,
+            content_filter: ContentFilter::default(),
+            unknown_lang: UnknownLangPolicy::default(),
+            allowed_langs: LangAllowlist::default(),
+            max_highlight_lines: DEFAULT_MAX_HIGHLIGHT_LINES,
+            highlight_cache_size: DEFAULT_HIGHLIGHT_CACHE_SIZE,
+            highlight_timeout: DEFAULT_HIGHLIGHT_TIMEOUT,
+            highlight_errors: HighlightErrorPolicy::default(),
+            markdown: MarkdownAllowlist::default(),
+            landing_page: None,
+            upload_limit: DEFAULT_UPLOAD_LIMIT,
+            upload_window: DEFAULT_UPLOAD_WINDOW,
+        }
+    }
+
+    /// Add the `.sublime-syntax` syntaxes and `.tmTheme` themes in `dir`, and
+    /// any directories in it, to those pastes can be highlighted with.
+    ///
+    /// If there's a `dump`, the syntaxes are loaded from it rather than `dir`
+    /// if it exists, and dumped to it otherwise (see
+    /// [highlight::cached_syntax_set]).
+    pub fn assets(
+        mut self,
+        dir: impl AsRef<Path>,
+        dump: Option<&Path>,
+    ) -> anyhow::Result<Self> {
+        let dir = dir.as_ref();
+        let defaults = std::mem::take(&mut self.syntax_set);
+        let build = || {
+            let mut syntaxes = defaults.into_builder();
+            syntaxes.add_from_folder(dir, true)?;
+            Ok(syntaxes.build())
+        };
+        self.syntax_set = match dump {
+            Some(dump) => highlight::cached_syntax_set(dump, build)?,
+            None => build()?,
+        };
+        self.theme_set.add_from_folder(dir)?;
+        tracing::info!("loaded syntaxes and themes from {}", dir.display());
+
+        Ok(self)
+    }
+
+    /// Highlight with the theme `name` when none is requested, if it exists
+    /// once the app is built (see [default_theme]).
+    pub fn default_theme(self, name: impl Into<String>) -> Self {
+        Self {
+            default_theme: Some(name.into()),
+            ..self
+        }
+    }
+
+    /// Allow uploading pastes of at most `max` bytes.
+    pub fn max_paste_size(self, max: usize) -> Self {
+        Self {
+            max_paste_size: max,
+            ..self
+        }
+    }
+
+    /// Allow at most `max` pastes at once.
+    pub fn max_pastes(self, max: u64) -> Self {
+        Self {
+            max_pastes: Some(max),
+            ..self
+        }
+    }
+
+    /// Search through `window` pastes at a time, see [App::search_window].
+    pub fn search_window(self, window: u32) -> Self {
         Self {
-            pastes: Arc::new(pool),
-            syntax_set: Arc::new(SyntaxSet::load_defaults_newlines()),
-            theme_set: Arc::new(ThemeSet::load_defaults()),
+            search_window: window,
+            ..self
         }
     }
+
+    /// Enable the admin routes, for requests bearing `token`.
+    pub fn admin_token(self, token: impl Into<String>) -> Self {
+        Self {
+            admin_token: Some(token.into()),
+            ..self
+        }
+    }
+
+    /// Refuse pastes that `filter` blocks.
+    pub fn content_filter(self, filter: ContentFilter) -> Self {
+        Self {
+            content_filter: filter,
+            ..self
+        }
+    }
+
+    /// Do as `policy` says when a paste is asked for highlighted as a language
+    /// there's no syntax for.
+    pub fn unknown_lang(self, policy: UnknownLangPolicy) -> Self {
+        Self {
+            unknown_lang: policy,
+            ..self
+        }
+    }
+
+    /// Only keep what `allowlist` allows of rendered Markdown.
+    pub fn markdown_allowlist(self, allowlist: MarkdownAllowlist) -> Self {
+        Self {
+            markdown: allowlist,
+            ..self
+        }
+    }
+
+    /// Do as `policy` says when a line of a paste can't be highlighted.
+    pub fn highlight_errors(self, policy: HighlightErrorPolicy) -> Self {
+        Self {
+            highlight_errors: policy,
+            ..self
+        }
+    }
+
+    /// Only highlight pastes as the languages `allowlist` allows when asked.
+    pub fn allowed_langs(self, allowlist: LangAllowlist) -> Self {
+        Self {
+            allowed_langs: allowlist,
+            ..self
+        }
+    }
+
+    /// Only highlight pastes of at most `max` lines.
+    pub fn max_highlight_lines(self, max: usize) -> Self {
+        Self {
+            max_highlight_lines: max,
+            ..self
+        }
+    }
+
+    /// Cache at most `max` bytes of highlighted output.
+    pub fn highlight_cache_size(self, max: usize) -> Self {
+        Self {
+            highlight_cache_size: max,
+            ..self
+        }
+    }
+
+    /// Give up highlighting a paste after `timeout`, serving it as is.
+    pub fn highlight_timeout(self, timeout: Duration) -> Self {
+        Self {
+            highlight_timeout: timeout,
+            ..self
+        }
+    }
+
+    /// Allow each client `limit` uploads per [upload
+    /// window](AppBuilder::upload_window).
+    pub fn upload_limit(self, limit: u32) -> Self {
+        Self {
+            upload_limit: limit,
+            ..self
+        }
+    }
+
+    /// Count each client's uploads over `window`, starting with its first.
+    pub fn upload_window(self, window: Duration) -> Self {
+        Self {
+            upload_window: window,
+            ..self
+        }
+    }
+
+    /// Serve the HTML page `html` at `/`, rather than the usage text.
+    pub fn landing_page(self, html: impl Into<String>) -> Self {
+        Self {
+            landing_page: Some(html.into()),
+            ..self
+        }
+    }
+
+    /// Serve the HTML page in the file at `path` at `/`, as in
+    /// [AppBuilder::landing_page].
+    ///
+    /// The page is read once, here, so changes to it need a restart.
+    pub fn landing_page_file(self, path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let html = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        tracing::info!("loaded the landing page from {}", path.display());
+
+        Ok(self.landing_page(html))
+    }
+
+    /// Build the app, with the defaults for anything not overridden.
+    pub fn build(self) -> App {
+        App {
+            pastes: self.pastes,
+            default_theme: default_theme(&self.theme_set, self.default_theme),
+            syntax_set: Arc::new(self.syntax_set),
+            theme_set: Arc::new(self.theme_set),
+            highlight_cache: Arc::new(HighlightCache::new(self.highlight_cache_size)),
+            highlight_timeout: self.highlight_timeout,
+            max_highlight_lines: self.max_highlight_lines,
+            highlight_errors: self.highlight_errors,
+            unknown_lang: self.unknown_lang,
+            allowed_langs: Arc::new(self.allowed_langs),
+            max_paste_size: self.max_paste_size,
+            max_pastes: self.max_pastes,
+            search_window: self.search_window,
+            upload_limiter: Arc::new(RateLimiter::new(
+                self.upload_limit,
+                self.upload_window,
+            )),
+            admin_token: self.admin_token,
+            content_filter: Arc::new(self.content_filter),
+            markdown_sanitizer: Arc::new(self.markdown.sanitizer()),
+            landing_page: self.landing_page,
+        }
+    }
+}
+
+impl App {
+    // Start building application state with a postgres connection pool,
+    // configured by these environment variables:
+    //
+    // - `ASSETS_DIR`: a directory to load extra syntaxes and themes from, if any
+    //   (see `AppBuilder::assets`).
+    // - `SYNTAX_DUMP`: a file to cache the syntaxes from `ASSETS_DIR` in, if any.
+    // - `DEFAULT_THEME`: the theme to highlight with when none is asked for.
+    // - `UNKNOWN_LANG_POLICY`: what to do when asked to highlight as a language
+    //   there's no syntax for, one of `plain-text` (the default), `not-found`, or
+    //   `detect`.
+    // - `ALLOWED_LANGUAGES`: the comma-separated languages pastes may be
+    //   highlighted as, if it's set, any other being treated like one there's no
+    //   syntax for (see `LangAllowlist`).
+    // - `MAX_HIGHLIGHT_LINES`: the most lines a paste may have to be highlighted,
+    //   if it's set, longer ones being served as is.
+    // - `HIGHLIGHT_TIMEOUT`: how many seconds highlighting a paste may take, if
+    //   it's set, slower ones being served as is.
+    // - `HIGHLIGHT_CACHE_SIZE`: the most bytes of highlighted output to cache, if
+    //   it's set.
+    // - `MAX_PASTE_SIZE`: the most bytes a paste may be uploaded with, if it's set.
+    // - `SEARCH_WINDOW`: how many pastes each search reads through, if it's set.
+    // - `HIGHLIGHT_ERROR_POLICY`: what to do with lines that can't be highlighted,
+    //   one of `raw` (the default), `fail`, or `comment`.
+    // - `MARKDOWN_ALLOW`: the comma-separated things rendered Markdown may have, or
+    //   `none`, if it's set, an empty list being refused (see `MarkdownAllowlist`).
+    // - `BLOCKED_PATTERNS_FILE`: a file of patterns that pastes matching any of are
+    //   refused, if any (see `ContentFilter::parse`).
+    // - `LANDING_PAGE_FILE`: a file with an HTML page to serve at `/` in place of
+    //   the usage text, if any.
+    // - `UPLOAD_LIMIT` and `UPLOAD_WINDOW`: how many uploads each client may make
+    //   in how many seconds, if they're set.
+    // - `DB_MAX_CONNECTIONS` and `DB_ACQUIRE_TIMEOUT`: how the pool is sized (see
+    //   `PoolConfig::from_vars`).
+    // - `PASTES_TABLE`: the table pastes are kept in, `pastes` by default.
+    //
+    // Operations failing for a transient reason are retried (see
+    // `RetryingStore`).
+    pub fn postgres(pool: PgPool) -> anyhow::Result<AppBuilder> {
+        let pool =
+            PoolConfig::from_vars(|name| std::env::var(name).ok())?.resize(&pool);
+        let table = std::env::var("PASTES_TABLE")
+            .unwrap_or_else(|_| paste::DEFAULT_TABLE.to_string());
+        let store = PgPasteStore::new(pool, &table)?;
+        let mut builder = AppBuilder::new(Arc::new(RetryingStore::new(store)));
+        if let Ok(dir) = std::env::var("ASSETS_DIR") {
+            let dump = std::env::var("SYNTAX_DUMP").ok();
+            builder = builder.assets(dir, dump.as_deref().map(Path::new))?;
+        }
+        if let Ok(path) = std::env::var("BLOCKED_PATTERNS_FILE") {
+            builder = builder.content_filter(ContentFilter::from_file(path)?);
+        }
+        if let Ok(path) = std::env::var("LANDING_PAGE_FILE") {
+            builder = builder.landing_page_file(path)?;
+        }
+        if let Ok(name) = std::env::var("DEFAULT_THEME") {
+            builder = builder.default_theme(name);
+        }
+        if let Ok(policy) = std::env::var("UNKNOWN_LANG_POLICY") {
+            let policy = policy.parse().context("Invalid UNKNOWN_LANG_POLICY")?;
+            builder = builder.unknown_lang(policy);
+        }
+        if let Ok(langs) = std::env::var("ALLOWED_LANGUAGES") {
+            let allowlist = langs.parse().context("Invalid ALLOWED_LANGUAGES")?;
+            builder = builder.allowed_langs(allowlist);
+        }
+        if let Ok(max) = std::env::var("MAX_HIGHLIGHT_LINES") {
+            let max = max.parse().context("Invalid MAX_HIGHLIGHT_LINES")?;
+            builder = builder.max_highlight_lines(max);
+        }
+        if let Ok(secs) = std::env::var("HIGHLIGHT_TIMEOUT") {
+            let secs = secs.parse().context("Invalid HIGHLIGHT_TIMEOUT")?;
+            builder = builder.highlight_timeout(Duration::from_secs(secs));
+        }
+        if let Ok(max) = std::env::var("HIGHLIGHT_CACHE_SIZE") {
+            let max = max.parse().context("Invalid HIGHLIGHT_CACHE_SIZE")?;
+            builder = builder.highlight_cache_size(max);
+        }
+        if let Ok(max) = std::env::var("MAX_PASTE_SIZE") {
+            let max = max.parse().context("Invalid MAX_PASTE_SIZE")?;
+            builder = builder.max_paste_size(max);
+        }
+        if let Ok(window) = std::env::var("SEARCH_WINDOW") {
+            let window = window.parse().context("Invalid SEARCH_WINDOW")?;
+            builder = builder.search_window(window);
+        }
+        if let Ok(list) = std::env::var("MARKDOWN_ALLOW") {
+            let allowlist = list.parse().context("Invalid MARKDOWN_ALLOW")?;
+            builder = builder.markdown_allowlist(allowlist);
+        }
+        if let Ok(policy) = std::env::var("HIGHLIGHT_ERROR_POLICY") {
+            let policy = policy.parse().context("Invalid HIGHLIGHT_ERROR_POLICY")?;
+            builder = builder.highlight_errors(policy);
+        }
+        if let Ok(limit) = std::env::var("UPLOAD_LIMIT") {
+            let limit = limit.parse().context("Invalid UPLOAD_LIMIT")?;
+            builder = builder.upload_limit(limit);
+        }
+        if let Ok(secs) = std::env::var("UPLOAD_WINDOW") {
+            let secs = secs.parse().context("Invalid UPLOAD_WINDOW")?;
+            builder = builder.upload_window(Duration::from_secs(secs));
+        }
+
+        Ok(builder)
+    }
+
+    /// Look up a theme by name, or [App::default_theme] if none is given.
+    pub fn theme(&self, name: Option<&str>) -> Result<&Theme> {
+        highlight::theme(&self.theme_set, name, &self.default_theme)
+    }
+
+    /// The name of the theme [App::theme] looks up, once it's known to exist.
+    pub fn theme_name<'a>(&'a self, name: Option<&'a str>) -> Result<&'a str> {
+        self.theme(name)?;
+
+        Ok(name.unwrap_or(&self.default_theme))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paste::mock::MockPasteStore;
+
+    #[test]
+    fn test_default_theme() {
+        let theme_set = ThemeSet::load_defaults();
+
+        // Test that a theme that exists is used.
+        let configured = Some("InspiredGitHub".to_string());
+        assert_eq!(default_theme(&theme_set, configured), "InspiredGitHub");
+
+        // Test that a theme that doesn't, or none at all, falls back.
+        let configured = Some("not-a-theme".to_string());
+        assert_eq!(default_theme(&theme_set, configured), DEFAULT_THEME);
+        assert_eq!(default_theme(&theme_set, None), DEFAULT_THEME);
+    }
+
+    #[test]
+    fn test_builder() -> anyhow::Result<()> {
+        // Test that an app built from nothing has the defaults.
+        let app = AppBuilder::new(MockPasteStore::arc()).build();
+        assert_eq!(app.default_theme, DEFAULT_THEME);
+        assert_eq!(app.max_paste_size, DEFAULT_MAX_PASTE_SIZE);
+        assert_eq!(app.highlight_timeout, DEFAULT_HIGHLIGHT_TIMEOUT);
+        assert_eq!(app.max_highlight_lines, DEFAULT_MAX_HIGHLIGHT_LINES);
+        assert_eq!(app.search_window, DEFAULT_SEARCH_WINDOW);
+        assert_eq!(app.max_pastes, None);
+        assert_eq!(app.admin_token, None);
+        assert!(app.content_filter.check("anything").is_ok());
+        assert_eq!(app.landing_page, None);
+
+        // Test that anything overridden is.
+        let app = AppBuilder::new(MockPasteStore::arc())
+            .default_theme("InspiredGitHub")
+            .max_pastes(10)
+            .admin_token("hunter2")
+            .content_filter(ContentFilter::parse("nope")?)
+            .max_highlight_lines(100)
+            .highlight_timeout(Duration::ZERO)
+            .max_paste_size(16)
+            .search_window(2)
+            .upload_limit(1)
+            .upload_window(Duration::from_secs(1))
+            .build();
+        assert_eq!(app.default_theme, "InspiredGitHub");
+        assert_eq!(app.max_pastes, Some(10));
+        assert_eq!(app.admin_token.as_deref(), Some("hunter2"));
+        assert!(app.content_filter.check("nope").is_err());
+        assert_eq!(app.max_highlight_lines, 100);
+        assert_eq!(app.highlight_timeout, Duration::ZERO);
+        assert_eq!(app.max_paste_size, 16);
+        assert_eq!(app.search_window, 2);
+        let ip = "203.0.113.7".parse()?;
+        assert!(app.upload_limiter.check(ip).is_ok());
+        assert!(app.upload_limiter.check(ip).is_err());
+
+        // Test that a default theme that doesn't exist falls back.
+        let app = AppBuilder::new(MockPasteStore::arc())
+            .default_theme("not-a-theme")
+            .build();
+        assert_eq!(app.default_theme, DEFAULT_THEME);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pool_config() -> anyhow::Result<()> {
+        // Test that unset variables fall back to the defaults.
+        let config = PoolConfig::from_vars(|_| None)?;
+        assert_eq!(config.max_connections, DEFAULT_MAX_CONNECTIONS);
+        assert_eq!(config.acquire_timeout, DEFAULT_ACQUIRE_TIMEOUT);
+
+        // Test that set variables are used.
+        let config = PoolConfig::from_vars(|name| match name {
+            "DB_MAX_CONNECTIONS" => Some("20".to_string()),
+            "DB_ACQUIRE_TIMEOUT" => Some("5".to_string()),
+            _ => None,
+        })?;
+        assert_eq!(
+            config,
+            PoolConfig {
+                max_connections: 20,
+                acquire_timeout: Duration::from_secs(5),
+            }
+        );
+
+        // Test that nonsense is rejected rather than ignored.
+        let config = PoolConfig::from_vars(|name| {
+            (name == "DB_MAX_CONNECTIONS").then(|| "lots".to_string())
+        });
+        assert!(config.is_err());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,184 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+use lru::LruCache;
+use uuid::Uuid;
+
+use crate::highlight::LineRanges;
+
+/// How a paste was highlighted, which together with its id determines the
+/// output.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HighlightKey {
+    pub id: Uuid,
+    /// Extension of the language the paste was highlighted as, or its name if
+    /// it wasn't given one.
+    pub lang: String,
+    /// Name of the theme the paste was highlighted with.
+    pub theme: String,
+    pub format: Format,
+    /// Lines that were drawn attention to, if any.
+    pub lines: Option<LineRanges>,
+    /// How many columns tabs were expanded to.
+    pub tabs: usize,
+}
+
+/// What a paste was highlighted for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    Terminal,
+    Html,
+    HtmlWithLinenos,
+    HtmlClassed,
+}
+
+/// A least recently used cache of highlighted pastes, so that popular pastes
+/// aren't highlighted over and over.
+///
+/// The cache is bounded by the total size of the output it holds, rather than
+/// by how many entries, since a single big paste can outweigh thousands of
+/// small ones. Pastes must be evicted when their content changes or they're
+/// removed.
+pub struct HighlightCache {
+    max_size: usize,
+    inner: Mutex<Inner>,
+    misses: AtomicU64,
+}
+
+struct Inner {
+    entries: LruCache<HighlightKey, String>,
+    /// Total length of the entries, in bytes.
+    size: usize,
+}
+
+impl HighlightCache {
+    /// Make a cache holding at most `max_size` bytes of output.
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            inner: Mutex::new(Inner {
+                entries: LruCache::unbounded(),
+                size: 0,
+            }),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the output cached for `key`, if any.
+    ///
+    /// The lock isn't held while highlighting after a miss, so concurrent
+    /// misses for the same key may both highlight. That's wasteful, but
+    /// never wrong.
+    pub fn get(&self, key: &HighlightKey) -> Option<String> {
+        let output = self.inner.lock().unwrap().entries.get(key).cloned();
+        if output.is_none() {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+
+        output
+    }
+
+    /// Cache `output` for `key`, evicting the least recently used output to
+    /// make room for it.
+    pub fn insert(&self, key: HighlightKey, output: String) {
+        // Output that could never fit would just flush everything else out.
+        if output.len() > self.max_size {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.size += output.len();
+        if let Some((_, replaced)) = inner.entries.push(key, output) {
+            inner.size -= replaced.len();
+        }
+        while inner.size > self.max_size {
+            match inner.entries.pop_lru() {
+                Some((_, evicted)) => inner.size -= evicted.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Forget everything cached for the paste with `id`.
+    pub fn evict(&self, id: Uuid) {
+        let mut inner = self.inner.lock().unwrap();
+        let keys: Vec<_> = inner
+            .entries
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| key.id == id)
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some(evicted) = inner.entries.pop(&key) {
+                inner.size -= evicted.len();
+            }
+        }
+    }
+
+    /// Forget everything cached, for when every paste is removed.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.clear();
+        inner.size = 0;
+    }
+
+    /// How many times output wasn't cached.
+    #[cfg(test)]
+    pub fn misses(&self) -> u64 { self.misses.load(Ordering::Relaxed) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: Uuid, lang: &str) -> HighlightKey {
+        HighlightKey {
+            id,
+            lang: lang.to_string(),
+            theme: "theme".to_string(),
+            format: Format::Terminal,
+            lines: None,
+            tabs: 4,
+        }
+    }
+
+    #[test]
+    fn test_cache() {
+        let cache = HighlightCache::new(10);
+        let id = Uuid::new_v4();
+
+        // Test that output is kept once cached.
+        assert_eq!(cache.get(&key(id, "rs")), None);
+        cache.insert(key(id, "rs"), "12345".to_string());
+        assert_eq!(cache.get(&key(id, "rs")).as_deref(), Some("12345"));
+        assert_eq!(cache.misses(), 1);
+
+        // Test that going over size evicts the least recently used output.
+        cache.insert(key(id, "py"), "67890".to_string());
+        assert!(cache.get(&key(id, "rs")).is_some());
+        cache.insert(key(id, "c"), "abcde".to_string());
+        assert!(cache.get(&key(id, "rs")).is_some());
+        assert_eq!(cache.get(&key(id, "py")), None);
+
+        // Test that output that could never fit isn't cached.
+        cache.insert(key(id, "js"), "x".repeat(11));
+        assert_eq!(cache.get(&key(id, "js")), None);
+        assert!(cache.get(&key(id, "rs")).is_some());
+
+        // Test that evicting a paste forgets all of its output, and only its.
+        let other = Uuid::new_v4();
+        cache.insert(key(other, "rs"), "x".to_string());
+        cache.evict(id);
+        assert!(cache.get(&key(other, "rs")).is_some());
+        assert_eq!(cache.get(&key(id, "rs")), None);
+
+        // Test that clearing forgets everything, making room for more.
+        cache.clear();
+        assert_eq!(cache.get(&key(other, "rs")), None);
+        cache.insert(key(id, "rs"), "x".repeat(10));
+        assert!(cache.get(&key(id, "rs")).is_some());
+    }
+}
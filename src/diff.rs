@@ -0,0 +1,69 @@
+//! Differences between two pastes, for reviewing changes.
+
+use similar::TextDiff;
+
+/// How many unchanged lines to show either side of a change.
+const CONTEXT_LINES: usize = 3;
+
+/// The unified diff turning `a` into `b`, line by line, which is empty if
+/// they're the same.
+pub fn unified_diff(a: &str, b: &str) -> String {
+    TextDiff::from_lines(a, b)
+        .unified_diff()
+        .context_radius(CONTEXT_LINES)
+        .to_string()
+}
+
+/// The [unified diff](unified_diff) turning `a` into `b` as HTML, with lines
+/// added and removed colored in, like a code review would show it.
+pub fn diff_html(a: &str, b: &str) -> String {
+    let mut html = String::from("<pre>\n");
+    for line in unified_diff(a, b).lines() {
+        let style = match line.chars().next() {
+            Some('+') => "background-color:#e6ffec",
+            Some('-') => "background-color:#ffebe9",
+            Some('@') => "color:#6e7781",
+            _ => "",
+        };
+        let line = ammonia::clean_text(line);
+        match style {
+            "" => html.push_str(&format!("{}\n", line)),
+            _ => html.push_str(&format!("<span style=\"{}\">{}</span>\n", style, line)),
+        }
+    }
+    html.push_str("</pre>\n");
+
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        // Test that there's nothing to show between the same pastes.
+        let a = "fn main() {\n    println!(\"Hello\");\n}\n";
+        assert_eq!(unified_diff(a, a), "");
+
+        // Test that changed lines are shown removed then added, in context.
+        let b = "fn main() {\n    println!(\"Goodbye\");\n}\n";
+        assert_eq!(
+            unified_diff(a, b),
+            "@@ -1,3 +1,3 @@\n \
+             fn main() {\n\
+             -    println!(\"Hello\");\n\
+             +    println!(\"Goodbye\");\n \
+             }\n"
+        );
+    }
+
+    #[test]
+    fn test_diff_html() {
+        // Test that changes are colored in, and their content escaped.
+        let html = diff_html("<b>\n", "<i>\n");
+        assert!(html.contains("#ffebe9\">-&lt;b&gt;</span>"));
+        assert!(html.contains("#e6ffec\">+&lt;i&gt;</span>"));
+        assert!(!html.contains("<b>"));
+    }
+}
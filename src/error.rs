@@ -1,37 +1,291 @@
+use std::time::Duration;
+
 use axum::{
-    http::StatusCode,
+    http::{header::RETRY_AFTER, StatusCode},
     response::{IntoResponse, Response},
+    Json,
 };
+use serde::Serialize;
 
 /// A type alias for `Result<T, AppError>` that is suitable
 /// for use as the primary error type for this application.
+pub type Result<T> = std::result::Result<T, AppError>;
+
+tokio::task_local! {
+    /// The id of the request being handled, for correlating the errors it's
+    /// responded to with with the logs.
+    pub static CURRENT_REQUEST_ID: String;
+}
+
+/// An error that can be turned into a response.
+///
+/// Each kind of error gets its own variant so that it's responded to with the
+/// right status. Errors from our dependencies convert with `?`, and anything
+/// else unexpected can be wrapped in an `anyhow::Error`.
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    /// There's no paste by that id or name, or it has expired.
+    #[error("Paste not found")]
+    NotFound,
+    /// The request doesn't make sense.
+    #[error("{0}")]
+    BadRequest(String),
+    /// The request conflicts with an existing resource.
+    #[error("{0}")]
+    Conflict(String),
+    /// The request body is bigger than we're willing to accept.
+    #[error("{0}")]
+    PayloadTooLarge(String),
+    /// The request lacks valid credentials for the resource.
+    #[error("{0}")]
+    Unauthorized(String),
+    /// The request's credentials don't grant access to the resource.
+    #[error("{0}")]
+    Forbidden(String),
+    /// The client has made too many requests, and may try again after the
+    /// given time.
+    #[error("Too many requests, try again in {} seconds", seconds(.0))]
+    TooManyRequests(Duration),
+    /// Any other error the client should know about, with its status.
+    #[error("{1}")]
+    Status(StatusCode, String),
+    /// The database failed us.
+    #[error("Something went wrong with the database: {0}")]
+    Database(#[from] sqlx::Error),
+    /// Syntax highlighting failed.
+    #[error("Something went wrong highlighting the paste: {0}")]
+    Highlight(#[from] syntect::Error),
+    /// Something else unexpected went wrong on our end.
+    #[error("Something went wrong: {0}")]
+    Internal(#[from] anyhow::Error),
+}
+
+/// How long clients are told to wait before trying again when the database
+/// can't be reached.
+pub const DATABASE_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Whether `err` means the database couldn't be reached, rather than that it
+/// refused what was asked of it.
 ///
-/// It employs type erasure through `anyhow::Error` to allow
-/// for easy conversion from other error types, and since our error
-/// path isn't critical the performance overhead isn't a problem.
-pub type Result<T> = anyhow::Result<T, AppError>;
+/// That's failing to get or keep a connection, or Postgres reporting a
+/// connection exception (class `08`) or that it's shutting down or starting up.
+fn unreachable(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_) => true,
+        sqlx::Error::Database(db) => db.code().is_some_and(|code| {
+            code.starts_with("08") || matches!(&*code, "57P01" | "57P03")
+        }),
+        _ => false,
+    }
+}
+
+/// `duration` in whole seconds, rounded up so that clients that wait as told
+/// aren't turned away.
+fn seconds(duration: &Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
 
-/// A new type around `anyhow::Error` so that we can implement [IntoResponse].
-#[derive(Debug)]
-pub struct AppError(anyhow::Error);
+impl AppError {
+    /// An error with an arbitrary status, for when no other variant fits.
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self::Status(status, message.into())
+    }
+
+    /// An error for a request that doesn't make sense.
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::BadRequest(message.into())
+    }
+
+    /// The status code the error is responded to with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::NotFound | Self::Database(sqlx::Error::RowNotFound) => {
+                StatusCode::NOT_FOUND
+            }
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Status(status, _) => *status,
+            Self::Database(err) if unreachable(err) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Database(_) | Self::Highlight(_) | Self::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+/// The JSON body of an error response.
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+    status: u16,
+    /// Outside of a request, as in tests, there's no id to give.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
 
 // Tell axum how to convert `AppError` into a response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
-        )
-            .into_response()
-    }
-}
-
-// This enables using `?` on functions that return `Result<_, anyhow::Error>`
-// (or any thing convertable to `anyhow::Error` for that matter) to turn them
-// into `Result<_, AppError>`.
-impl<E> From<E> for AppError
-where
-    E: Into<anyhow::Error>,
-{
-    fn from(err: E) -> Self { Self(err.into()) }
+        let status = self.status();
+        let request_id = CURRENT_REQUEST_ID.try_with(String::clone).ok();
+        // Our failures need looking into, the client's are just worth knowing.
+        if status.is_server_error() {
+            tracing::error!(request_id, "responding with error: {:?}", self);
+        } else {
+            tracing::info!(request_id, "responding with error: {}", self);
+        }
+
+        let retry_after = match &self {
+            Self::TooManyRequests(after) => Some(*after),
+            // The database is most likely back by then.
+            Self::Database(_) if status == StatusCode::SERVICE_UNAVAILABLE => {
+                Some(DATABASE_RETRY_AFTER)
+            }
+            _ => None,
+        }
+        .map(|after| [(RETRY_AFTER, seconds(&after).to_string())]);
+        let body = ErrorBody {
+            error: self.to_string(),
+            status: status.as_u16(),
+            request_id,
+        };
+
+        (status, retry_after, Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header::CONTENT_TYPE;
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    // Turn an error into its status, content type, and JSON body.
+    async fn render(err: AppError) -> (StatusCode, String, Value) {
+        let response = err.into_response();
+        let status = response.status();
+        let content_type = response.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        (status, content_type, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_internal_error() {
+        let err = AppError::from(anyhow::anyhow!("the database is on fire"));
+        let (status, content_type, body) = render(err).await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(content_type, "application/json");
+        let error = "Something went wrong: the database is on fire";
+        assert_eq!(body, json!({ "error": error, "status": 500 }));
+    }
+
+    #[tokio::test]
+    async fn test_bad_request() {
+        let (status, _, body) = render(AppError::bad_request("no thanks")).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, json!({ "error": "no thanks", "status": 400 }));
+    }
+
+    #[tokio::test]
+    async fn test_too_many_requests() {
+        let err = AppError::TooManyRequests(Duration::from_millis(1500));
+        let response = err.into_response();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "2");
+    }
+
+    #[tokio::test]
+    async fn test_database_unavailable() {
+        let err = AppError::from(sqlx::Error::PoolTimedOut);
+        let response = err.into_response();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "5");
+    }
+
+    #[test]
+    fn test_status() {
+        // Test that each kind of error gets its own status.
+        let cases = [
+            (AppError::NotFound, StatusCode::NOT_FOUND),
+            (AppError::bad_request("bad"), StatusCode::BAD_REQUEST),
+            (AppError::Conflict("taken".into()), StatusCode::CONFLICT),
+            (
+                AppError::PayloadTooLarge("big".into()),
+                StatusCode::PAYLOAD_TOO_LARGE,
+            ),
+            (
+                AppError::Unauthorized("who".into()),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (AppError::Forbidden("no".into()), StatusCode::FORBIDDEN),
+            (
+                AppError::TooManyRequests(Duration::from_secs(1)),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (
+                AppError::new(StatusCode::BAD_GATEWAY, "down"),
+                StatusCode::BAD_GATEWAY,
+            ),
+            (
+                AppError::from(sqlx::Error::RowNotFound),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                AppError::from(sqlx::Error::PoolTimedOut),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                AppError::from(sqlx::Error::Io(
+                    std::io::ErrorKind::ConnectionRefused.into(),
+                )),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                AppError::from(sqlx::Error::ColumnNotFound("content".into())),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                AppError::from(syntect::Error::Io(std::io::ErrorKind::Other.into())),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                AppError::from(anyhow::anyhow!("oops")),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+
+        for (err, status) in cases {
+            assert_eq!(err.status(), status, "{err:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_not_found() {
+        let (status, _, body) = render(AppError::NotFound).await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, json!({ "error": "Paste not found", "status": 404 }));
+
+        // Test that errors in a request say which.
+        let (_, _, body) = CURRENT_REQUEST_ID
+            .scope("abc123".to_string(), render(AppError::NotFound))
+            .await;
+        assert_eq!(body["request_id"], "abc123");
+    }
 }
@@ -0,0 +1,419 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    io::Read,
+    net::{IpAddr, SocketAddr},
+};
+
+use async_trait::async_trait;
+use axum::{
+    body::{Body, HttpBody},
+    extract::{
+        multipart::MultipartError, ConnectInfo, FromRequest, FromRequestParts, Json,
+        Multipart, Path,
+    },
+    http::{
+        header::{AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE},
+        request::Parts,
+        HeaderMap, Request, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{app::App, error::AppError, paste, util};
+
+/// The body of a request that sets a paste's content.
+///
+/// The content is either the raw body, or the `content` field of a
+/// `multipart/form-data` body, as sent by HTML forms.
+///
+/// A raw body may be sent gzipped, with `Content-Encoding: gzip`, in which case
+/// it's the decompressed body that's the content.
+///
+/// Rejects bodies bigger than [App::max_paste_size] with a descriptive 413,
+/// including those too big for axum to even buffer, and anything that isn't
+/// [text](paste::text) with a descriptive 400. A raw body is [read as it
+/// streams in](read_body), so it's rejected as soon as it's too big.
+#[derive(Debug)]
+pub struct PasteContent(pub String);
+
+/// The rejection for content bigger than [App::max_paste_size].
+fn too_large(state: &App) -> Response {
+    AppError::PayloadTooLarge(format!(
+        "Paste is too large, the maximum size is {} bytes",
+        state.max_paste_size
+    ))
+    .into_response()
+}
+
+/// Whether the request body is a form.
+fn is_multipart(req: &Request<Body>) -> bool {
+    req.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("multipart/form-data"))
+}
+
+/// The encodings a request body may be sent in, from `Content-Encoding`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Identity,
+    Gzip,
+}
+
+/// Which encoding the request body is in.
+///
+/// Rejects encodings we can't decode with a descriptive 415.
+fn encoding(req: &Request<Body>) -> Result<Encoding, AppError> {
+    let Some(value) = req.headers().get(CONTENT_ENCODING) else {
+        return Ok(Encoding::Identity);
+    };
+
+    match value.to_str().map(str::trim) {
+        Ok(value) if value.eq_ignore_ascii_case("identity") => Ok(Encoding::Identity),
+        Ok(value) if value.eq_ignore_ascii_case("gzip") => Ok(Encoding::Gzip),
+        _ => Err(AppError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Only `gzip` request bodies are supported",
+        )),
+    }
+}
+
+/// Read a raw body a chunk at a time as it streams in, rather than buffering it
+/// whole first.
+///
+/// Bodies bigger than [App::max_paste_size] are rejected as soon as that's
+/// known, from `Content-Length` before reading any of them if it's given, and
+/// otherwise once there's too much, without reading the rest. That goes for a
+/// gzipped body too, which would hardly decompress into anything smaller.
+async fn read_body(req: Request<Body>, state: &App) -> Result<Vec<u8>, Response> {
+    let length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if length.is_some_and(|length| length > state.max_paste_size) {
+        return Err(too_large(state));
+    }
+
+    let mut body = req.into_body();
+    let mut content = Vec::with_capacity(length.unwrap_or_default());
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| {
+            AppError::bad_request(format!("Failed to read the body: {}", err))
+                .into_response()
+        })?;
+        if content.len() + chunk.len() > state.max_paste_size {
+            return Err(too_large(state));
+        }
+        content.extend_from_slice(&chunk);
+    }
+
+    Ok(content)
+}
+
+/// Decompress a gzipped body.
+///
+/// Decompression stops as soon as the body is over `max_size`, so that a small
+/// body can't decompress into one too big to hold. Whether it was is up to the
+/// caller to check.
+fn gunzip(body: &[u8], max_size: usize) -> Result<Vec<u8>, AppError> {
+    let mut content = Vec::new();
+    GzDecoder::new(body)
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut content)
+        .map_err(|_| AppError::bad_request("Paste isn't valid gzip"))?;
+
+    Ok(content)
+}
+
+/// Read the `content` field of a form.
+async fn read_form(req: Request<Body>, state: &App) -> Result<String, Response> {
+    let reject = |err: MultipartError| match err.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => too_large(state),
+        _ => err.into_response(),
+    };
+
+    let mut multipart = Multipart::from_request(req, state)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    while let Some(field) = multipart.next_field().await.map_err(reject)? {
+        if field.name() == Some("content") {
+            let content = field.bytes().await.map_err(reject)?;
+            return paste::text(content.into()).map_err(IntoResponse::into_response);
+        }
+    }
+
+    Err(AppError::bad_request("Form is missing a `content` field").into_response())
+}
+
+#[async_trait]
+impl FromRequest<App, Body> for PasteContent {
+    type Rejection = Response;
+
+    async fn from_request(
+        req: Request<Body>,
+        state: &App,
+    ) -> Result<Self, Self::Rejection> {
+        let content = if is_multipart(&req) {
+            read_form(req, state).await?
+        } else {
+            let encoding = encoding(&req).map_err(IntoResponse::into_response)?;
+            let content = read_body(req, state).await?;
+            let content = match encoding {
+                Encoding::Identity => content,
+                Encoding::Gzip => gunzip(&content, state.max_paste_size)
+                    .map_err(IntoResponse::into_response)?,
+            };
+            // Checked before the text is, since a cut off body may not even be
+            // UTF-8.
+            if content.len() > state.max_paste_size {
+                return Err(too_large(state));
+            }
+            paste::text(content).map_err(IntoResponse::into_response)?
+        };
+        if content.len() > state.max_paste_size {
+            return Err(too_large(state));
+        }
+
+        Ok(Self(content))
+    }
+}
+
+/// A paste to create, and how, as uploaded in an `application/json` body.
+///
+/// The options mean the same as the query parameters of a plain upload.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreatePasteRequest {
+    pub content: String,
+    /// Extension or name of the language to highlight the paste as.
+    pub language: Option<String>,
+    /// Number of seconds the paste should live for.
+    pub ttl: Option<u32>,
+    /// Number of seconds the paste may go unread before it's removed.
+    pub idle_ttl: Option<u32>,
+    /// A human-readable name to retrieve the paste by.
+    pub name: Option<String>,
+    /// A password that must be given to read the paste.
+    pub password: Option<String>,
+    /// Whether the paste should be deleted the first time it's read.
+    #[serde(default)]
+    pub burn: bool,
+    /// What to call the paste.
+    pub title: Option<String>,
+    /// Whether the paste may be discovered, by listing or at random.
+    #[serde(default)]
+    pub public: bool,
+}
+
+/// The body of a request that uploads a paste.
+///
+/// An `application/json` body is a [CreatePasteRequest], carrying the options
+/// along with the content. Any other body is just the content, read as by
+/// [PasteContent], and checked just the same.
+#[derive(Debug)]
+pub enum UploadBody {
+    Content(String),
+    Json(CreatePasteRequest),
+}
+
+/// Whether the request body is JSON.
+fn is_json(req: &Request<Body>) -> bool {
+    req.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"))
+}
+
+#[async_trait]
+impl FromRequest<App, Body> for UploadBody {
+    type Rejection = Response;
+
+    async fn from_request(
+        req: Request<Body>,
+        state: &App,
+    ) -> Result<Self, Self::Rejection> {
+        if !is_json(&req) {
+            let PasteContent(content) = PasteContent::from_request(req, state).await?;
+            return Ok(Self::Content(content));
+        }
+
+        let Json(mut request) = Json::<CreatePasteRequest>::from_request(req, state)
+            .await
+            .map_err(|rejection| match rejection.status() {
+                StatusCode::PAYLOAD_TOO_LARGE => too_large(state),
+                _ => rejection.into_response(),
+            })?;
+        request.content =
+            paste::text(request.content.into()).map_err(IntoResponse::into_response)?;
+        if request.content.len() > state.max_paste_size {
+            return Err(too_large(state));
+        }
+
+        Ok(Self::Json(request))
+    }
+}
+
+/// The IP address of the client that made a request, if it can be told.
+///
+/// Behind a proxy (as on Shuttle) the connection comes from the proxy, so the
+/// last address in `X-Forwarded-For`, the one the proxy saw, is used. Earlier
+/// addresses are whatever the client claims, so aren't trusted.
+#[derive(Debug)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let forwarded = parts
+            .headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .last()
+            .and_then(|ip| ip.trim().parse().ok());
+        let connected = || {
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        };
+
+        Ok(Self(forwarded.or_else(connected)))
+    }
+}
+
+/// The id of the paste a request is for, from the `:id` segment of its path.
+///
+/// Rejects ids that aren't UUIDs with a 400 saying so, rather than axum's
+/// terse default.
+#[derive(Debug, Clone, Copy)]
+pub struct PasteId(pub Uuid);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for PasteId {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let Path(params) =
+            Path::<HashMap<String, String>>::from_request_parts(parts, state)
+                .await
+                .map_err(|rejection| AppError::bad_request(rejection.body_text()))?;
+        // Only used on routes with an id, so this really is our mistake.
+        let id = params
+            .get("id")
+            .ok_or_else(|| anyhow::anyhow!("Route has no `:id` segment"))?;
+
+        Self::parse(id)
+    }
+}
+
+impl PasteId {
+    /// Parse `id`, failing with a 400 saying what an id looks like if it isn't
+    /// one.
+    pub fn parse(id: &str) -> Result<Self, AppError> {
+        id.parse().map(Self).map_err(|_| {
+            AppError::bad_request(format!(
+                "`{}` isn't a paste id, ids are UUIDs such as \
+                 `67e55044-10b1-426f-9247-bb680e5fe0c8`",
+                id
+            ))
+        })
+    }
+}
+
+/// Request header with the API key that pastes uploaded with it are owned by.
+pub const API_KEY: &str = "x-api-key";
+
+/// The API key a request was made with, which pastes uploaded with it are owned
+/// by, from an `Authorization: Bearer <key>` header or its [API_KEY] header.
+///
+/// Any key is accepted, the first paste uploaded with a key is what makes it
+/// one. Rejects requests without a key, so use [ApiKey::from_headers] where
+/// one isn't needed. The [admin token](App::admin_token) is never taken for a
+/// key, so that admins don't end up owning what they upload. Nor are delete
+/// tokens, since no route that takes one looks for a key.
+#[derive(Debug)]
+pub struct ApiKey(pub String);
+
+impl ApiKey {
+    /// The API key in `headers`, if there is one, the [API_KEY] header winning
+    /// over a bearer token.
+    pub fn from_headers(headers: &HeaderMap, state: &App) -> Option<Self> {
+        let header =
+            |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let bearer = header(AUTHORIZATION.as_str())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let admin = |key: &str| {
+            state.admin_token.as_ref().is_some_and(|token| {
+                util::constant_time_eq(token.as_bytes(), key.as_bytes())
+            })
+        };
+
+        header(API_KEY)
+            .or(bearer)
+            .filter(|key| !key.is_empty() && !admin(key))
+            .map(|key| Self(key.to_string()))
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<App> for ApiKey {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &App,
+    ) -> Result<Self, Self::Rejection> {
+        Self::from_headers(&parts.headers, state).ok_or_else(|| {
+            AppError::Unauthorized(
+                "An API key is required, as `Authorization: Bearer <key>`".to_string(),
+            )
+        })
+    }
+}
+
+/// Proof that a request was made by an admin.
+///
+/// Rejects requests without an `Authorization: Bearer <token>` header matching
+/// [App::admin_token], or all of them if there's no token.
+#[derive(Debug)]
+pub struct Admin;
+
+#[async_trait]
+impl FromRequestParts<App> for Admin {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &App,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        match (&state.admin_token, token) {
+            (Some(expected), Some(token))
+                if util::constant_time_eq(expected.as_bytes(), token.as_bytes()) =>
+            {
+                Ok(Self)
+            }
+            _ => Err(AppError::Forbidden("Admin access required".to_string())),
+        }
+    }
+}
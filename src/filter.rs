@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use anyhow::Context;
+use axum::http::StatusCode;
+use regex::RegexSet;
+
+use crate::error::{AppError, Result};
+
+/// Patterns that pastes mustn't match, to keep abuse off the service.
+///
+/// The patterns are regexes, compiled once up front and matched all at once.
+#[derive(Debug)]
+pub struct ContentFilter {
+    patterns: RegexSet,
+}
+
+impl Default for ContentFilter {
+    /// A filter that lets everything through.
+    fn default() -> Self {
+        Self {
+            patterns: RegexSet::empty(),
+        }
+    }
+}
+
+impl ContentFilter {
+    /// Block content matching any of `patterns`.
+    pub fn new<I, P>(patterns: I) -> anyhow::Result<Self>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<str>,
+    {
+        let patterns = RegexSet::new(patterns).context("invalid blocked pattern")?;
+
+        Ok(Self { patterns })
+    }
+
+    /// Block content matching any of the patterns in `list`, one per line.
+    ///
+    /// Blank lines, and lines starting with `#`, are skipped so that the list
+    /// can say why each pattern is there.
+    pub fn parse(list: &str) -> anyhow::Result<Self> {
+        Self::new(
+            list.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#')),
+        )
+    }
+
+    /// Block content matching any of the patterns listed in the file at `path`,
+    /// as in [ContentFilter::parse].
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let list = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let filter = Self::parse(&list)?;
+        tracing::info!(
+            "loaded {} blocked patterns from {}",
+            filter.patterns.len(),
+            path.display()
+        );
+
+        Ok(filter)
+    }
+
+    /// Fail with a 422 if `content` matches any of the patterns.
+    ///
+    /// Which pattern matched isn't said, so as not to help get around it.
+    pub fn check(&self, content: &str) -> Result<()> {
+        if self.patterns.is_match(content) {
+            return Err(AppError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Paste contains content that isn't allowed",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let filter = ContentFilter::parse(
+            "# Spam\n\
+             \n\
+             (?i)buy cheap \\w+\n\
+             # Anything at all, were it not a comment.\n\
+             # .*\n",
+        )?;
+
+        // Test that the patterns, and only the patterns, are blocked.
+        assert!(filter.check("BUY CHEAP watches").is_err());
+        assert!(filter.check("# Spam").is_ok());
+        assert!(filter.check("fn main() {}").is_ok());
+
+        // Test that nothing is blocked by default.
+        assert!(ContentFilter::default().check("buy cheap watches").is_ok());
+
+        // Test that patterns that aren't regexes are caught up front.
+        assert!(ContentFilter::parse("(unclosed").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1194 @@
+use std::{
+    collections::HashSet, ops::RangeInclusive, path::Path, str::FromStr, time::Duration,
+};
+
+use syntect::{
+    dumps::{dump_to_file, from_dump_file},
+    easy::HighlightLines,
+    highlighting::{Color, Theme, ThemeSet},
+    html::{
+        css_for_theme_with_class_style, highlighted_html_for_string,
+        start_highlighted_html_snippet, styled_line_to_highlighted_html, ClassStyle,
+        ClassedHTMLGenerator, IncludeBackground,
+    },
+    parsing::{SyntaxReference, SyntaxSet},
+    util::{as_24_bit_terminal_escaped, LinesWithEndings},
+};
+
+use crate::error::{AppError, Result};
+
+/// The theme used to highlight pastes when none is requested, unless the
+/// deployment configures another.
+pub const DEFAULT_THEME: &str = "base16-ocean.dark";
+
+/// What to do when a paste is asked for highlighted as a language there's no
+/// syntax for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownLangPolicy {
+    /// Serve the paste as plain text.
+    #[default]
+    PlainText,
+    /// Respond with a 404, for clients that would rather know.
+    NotFound,
+    /// Highlight the paste as the language it was uploaded as, or whatever its
+    /// first line gives away, falling back to plain text.
+    Detect,
+}
+
+impl FromStr for UnknownLangPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(policy: &str) -> anyhow::Result<Self> {
+        match policy {
+            "plain-text" => Ok(Self::PlainText),
+            "not-found" => Ok(Self::NotFound),
+            "detect" => Ok(Self::Detect),
+            _ => anyhow::bail!(
+                "unknown policy `{}`, expected `plain-text`, `not-found`, or `detect`",
+                policy
+            ),
+        }
+    }
+}
+
+/// What to do when a line of a paste can't be highlighted, as happens with
+/// syntaxes that refer to others that aren't there.
+///
+/// Terminal output and HTML styled inline are dealt with line by line. HTML
+/// styled by classes is highlighted all at once, so the whole paste is served
+/// unhighlighted, or fails, rather than just the line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HighlightErrorPolicy {
+    /// Serve the line as it is, unhighlighted, and carry on.
+    #[default]
+    Raw,
+    /// Give up on the paste, responding with a 500.
+    Fail,
+    /// Serve the line as it is, followed by a comment saying what went wrong.
+    Comment,
+}
+
+impl HighlightErrorPolicy {
+    /// Deal with `err` from highlighting a line, giving the comment to follow
+    /// it with, if any, made by `comment` from the error's message.
+    fn recover(
+        self,
+        err: syntect::Error,
+        comment: impl FnOnce(&str) -> String,
+    ) -> Result<Option<String>> {
+        match self {
+            Self::Raw => Ok(None),
+            Self::Fail => Err(err.into()),
+            Self::Comment => Ok(Some(comment(&err.to_string()))),
+        }
+    }
+}
+
+impl FromStr for HighlightErrorPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(policy: &str) -> anyhow::Result<Self> {
+        match policy {
+            "raw" => Ok(Self::Raw),
+            "fail" => Ok(Self::Fail),
+            "comment" => Ok(Self::Comment),
+            _ => anyhow::bail!(
+                "unknown policy `{}`, expected `raw`, `fail`, or `comment`",
+                policy
+            ),
+        }
+    }
+}
+
+/// The languages pastes may be highlighted as when asked for, for deployments
+/// that only support some.
+///
+/// Languages are given by the names or extensions of their syntaxes, ignoring
+/// case, e.g. `rs,Python,md`. Every language is allowed by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LangAllowlist(Option<HashSet<String>>);
+
+impl LangAllowlist {
+    /// Whether pastes may be highlighted as `syntax`.
+    pub fn allows(&self, syntax: &SyntaxReference) -> bool {
+        let Some(langs) = &self.0 else {
+            return true;
+        };
+
+        std::iter::once(&syntax.name)
+            .chain(&syntax.file_extensions)
+            .any(|lang| langs.contains(&lang.to_lowercase()))
+    }
+}
+
+impl FromStr for LangAllowlist {
+    type Err = anyhow::Error;
+
+    fn from_str(langs: &str) -> anyhow::Result<Self> {
+        let langs: HashSet<_> = langs
+            .split(',')
+            .map(|lang| lang.trim().to_lowercase())
+            .filter(|lang| !lang.is_empty())
+            .collect();
+        if langs.is_empty() {
+            anyhow::bail!("no languages given, expected e.g. `rs,py,md`");
+        }
+
+        Ok(Self(Some(langs)))
+    }
+}
+
+/// Load the syntaxes dumped to `path` on an earlier start, or build them with
+/// `build` and dump them there for the next, since building syntaxes from
+/// folders is slow.
+///
+/// A dump is only as fresh as the syntaxes it was built from, so delete it when
+/// they change. A dump that can't be loaded is rebuilt, and one that can't be
+/// written is done without, as it only speeds things up.
+pub fn cached_syntax_set(
+    path: &Path,
+    build: impl FnOnce() -> anyhow::Result<SyntaxSet>,
+) -> anyhow::Result<SyntaxSet> {
+    if path.exists() {
+        match from_dump_file(path) {
+            Ok(syntax_set) => {
+                tracing::info!("loaded syntaxes from {}", path.display());
+                return Ok(syntax_set);
+            }
+            Err(err) => tracing::warn!(
+                "failed to load syntaxes from {}, rebuilding them: {}",
+                path.display(),
+                err
+            ),
+        }
+    }
+
+    let syntax_set = build()?;
+    match dump_to_file(&syntax_set, path) {
+        Ok(()) => tracing::info!("dumped syntaxes to {}", path.display()),
+        Err(err) => {
+            tracing::warn!("failed to dump syntaxes to {}: {}", path.display(), err)
+        }
+    }
+
+    Ok(syntax_set)
+}
+
+/// Look up a theme by name, or the `default` theme, which must exist, if no
+/// name is given.
+///
+/// Fails with a 400 listing the themes there are if there is no theme by that
+/// name, since quietly highlighting with another would hide the typo.
+pub fn theme<'a>(
+    theme_set: &'a ThemeSet,
+    name: Option<&str>,
+    default: &str,
+) -> Result<&'a Theme> {
+    let name = name.unwrap_or(default);
+    theme_set.themes.get(name).ok_or_else(|| {
+        let names: Vec<_> = theme_set.themes.keys().map(String::as_str).collect();
+        AppError::bad_request(format!(
+            "Unknown theme `{}`, the themes are: {}",
+            name,
+            names.join(", ")
+        ))
+    })
+}
+
+/// Lines to draw attention to when highlighting, parsed from a spec such as
+/// `10`, `10-15`, or `3,10-15`.
+///
+/// Lines are numbered from 1, as in an editor. HTML output goes further and
+/// shows only the [LineRanges::excerpt] they cover.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LineRanges(Vec<RangeInclusive<usize>>);
+
+impl LineRanges {
+    /// Whether the 1-based `line` is one of the selected lines.
+    pub fn contains(&self, line: usize) -> bool {
+        self.0.iter().any(|range| range.contains(&line))
+    }
+
+    /// Fail if any selected line is past the end of content with `count`
+    /// lines.
+    pub fn check(&self, count: usize) -> Result<()> {
+        match self.0.iter().map(|range| *range.end()).max() {
+            Some(last) if last > count => Err(AppError::bad_request(format!(
+                "Line {} is past the end of the paste, which has {} line(s)",
+                last, count
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// The lines of `content` from the first selected line to the last, along
+    /// with the number of the first.
+    ///
+    /// This is cut out before highlighting, so that only it is highlighted.
+    pub fn excerpt(&self, content: &str) -> (usize, String) {
+        let first = self.0.iter().map(|range| *range.start()).min().unwrap_or(1);
+        let last = self.0.iter().map(|range| *range.end()).max().unwrap_or(0);
+        let excerpt = LinesWithEndings::from(content)
+            .skip(first - 1)
+            .take((last + 1).saturating_sub(first))
+            .collect();
+
+        (first, excerpt)
+    }
+
+    /// The selected lines as numbered within the [LineRanges::excerpt], if any
+    /// of it isn't selected, i.e. if there's more than one range.
+    pub fn within_excerpt(&self) -> Option<Self> {
+        let first = self.0.iter().map(|range| *range.start()).min()?;
+        let offset = |line: usize| line - first + 1;
+        let ranges = self
+            .0
+            .iter()
+            .map(|range| offset(*range.start())..=offset(*range.end()));
+
+        (self.0.len() > 1).then(|| Self(ranges.collect()))
+    }
+}
+
+impl FromStr for LineRanges {
+    type Err = AppError;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let invalid = || {
+            AppError::bad_request(format!(
+                "Invalid line range `{}`, expected e.g. `10`, `10-15`, or `3,10-15`",
+                spec
+            ))
+        };
+        let line = |line: &str| {
+            line.trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|&line| line > 0)
+                .ok_or_else(invalid)
+        };
+
+        spec.split(',')
+            .map(|part| {
+                let (start, end) = match part.split_once('-') {
+                    Some((start, end)) => (line(start)?, line(end)?),
+                    None => (line(part)?, line(part)?),
+                };
+                if start > end {
+                    return Err(invalid());
+                }
+                Ok(start..=end)
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
+/// The width of a tab when none is requested.
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// The widest a tab may be requested to be.
+pub const MAX_TAB_WIDTH: usize = 16;
+
+/// Replace tabs in `content` with spaces, up to the next multiple of `width`
+/// columns, as an editor would.
+///
+/// Columns are counted in characters, which is close enough for code.
+pub fn expand_tabs(content: &str, width: usize) -> String {
+    let mut expanded = String::with_capacity(content.len());
+    let mut column = 0;
+    for c in content.chars() {
+        match c {
+            '\t' => {
+                let spaces = width - column % width;
+                expanded.push_str(&" ".repeat(spaces));
+                column += spaces;
+            }
+            '\n' => {
+                expanded.push(c);
+                column = 0;
+            }
+            _ => {
+                expanded.push(c);
+                column += 1;
+            }
+        }
+    }
+
+    expanded
+}
+
+/// Whether `content` has more than `max` lines, too many to be worth
+/// highlighting.
+///
+/// Lines are counted as they're highlighted, so a trailing newline doesn't
+/// start a line of its own. Counting stops as soon as it's past `max`.
+pub fn too_many_lines(content: &str, max: usize) -> bool {
+    LinesWithEndings::from(content).nth(max).is_some()
+}
+
+/// Prefix of selected lines in terminal output.
+const SELECTED_MARKER: &str = "> ";
+
+/// Class of selected lines in HTML output.
+const SELECTED_CLASS: &str = "selected";
+
+/// The CSS background of selected lines in HTML output.
+///
+/// Uses the theme's own color for the current line where it has one.
+fn selected_background(theme: &Theme) -> String {
+    match theme.settings.line_highlight {
+        Some(Color { r, g, b, .. }) => format!("#{:02x}{:02x}{:02x}", r, g, b),
+        None => "rgba(128,128,128,0.25)".to_string(),
+    }
+}
+
+/// The escape code resetting a terminal's colors and styles.
+const RESET: &str = "\x1b[0m";
+
+/// The escape code dimming text in a terminal, for comments on the output.
+const DIM: &str = "\x1b[2m";
+
+/// Highlight `content` using 24-bit terminal escape codes.
+///
+/// Each line ends with a reset so that colors don't bleed into whatever the
+/// terminal prints next. If any lines are `selected`, every line gets a gutter
+/// in which the selected ones are marked. Lines that can't be highlighted are
+/// dealt with as `on_error` says.
+pub fn highlight(
+    content: &str,
+    syntax: &SyntaxReference,
+    syntax_set: &SyntaxSet,
+    theme: &Theme,
+    selected: Option<&LineRanges>,
+    on_error: HighlightErrorPolicy,
+) -> Result<String> {
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut lines = Vec::new();
+    for (number, line) in LinesWithEndings::from(content).enumerate() {
+        let (escaped, comment) = match highlighter.highlight_line(line, syntax_set) {
+            Ok(ranges) => (as_24_bit_terminal_escaped(&ranges[..], false), None),
+            Err(err) => {
+                let comment = on_error.recover(err, |message| {
+                    format!("  {}# highlighting failed: {}", DIM, message)
+                });
+                (line.to_string(), comment?)
+            }
+        };
+        let marker = match selected {
+            Some(selected) if selected.contains(number + 1) => SELECTED_MARKER,
+            Some(_) => "  ",
+            None => "",
+        };
+        // Reset before the line ending, so the next line starts clean.
+        let text = escaped.trim_end_matches(['\r', '\n']);
+        let ending = &escaped[text.len()..];
+        let comment = comment.unwrap_or_default();
+        lines.push(format!("{}{}{}{}{}", marker, text, comment, RESET, ending));
+    }
+
+    Ok(lines.join(""))
+}
+
+/// Make terminal output safe to print wherever, by resetting the terminal
+/// before it, in case something printed earlier left it colored, and making
+/// sure it's reset after it.
+///
+/// [highlight] resets before each line ending, but some terminals carry the
+/// last line's colors past its newline, so output is reset after that too.
+pub fn for_terminal(output: &str) -> String {
+    match output.ends_with(RESET) {
+        true => format!("{}{}", RESET, output),
+        false => format!("{}{}{}", RESET, output, RESET),
+    }
+}
+
+/// Highlight each line of `content` as HTML with inline styles, without their
+/// line endings, dealing with lines that can't be highlighted as `on_error`
+/// says.
+fn html_lines(
+    content: &str,
+    syntax: &SyntaxReference,
+    syntax_set: &SyntaxSet,
+    theme: &Theme,
+    on_error: HighlightErrorPolicy,
+) -> Result<Vec<String>> {
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let background = theme.settings.background.unwrap_or(Color::WHITE);
+    // A trailing newline ends the last line rather than starting a new one, so
+    // it doesn't count as a line of its own.
+    LinesWithEndings::from(content)
+        .map(|line| {
+            let mut ranges = match highlighter.highlight_line(line, syntax_set) {
+                Ok(ranges) => ranges,
+                Err(err) => {
+                    let comment = on_error.recover(err, html_comment)?;
+                    let text = ammonia::clean_text(line.trim_end_matches(['\r', '\n']));
+                    return Ok(format!("{}{}", text, comment.unwrap_or_default()));
+                }
+            };
+            if let Some((_, text)) = ranges.last_mut() {
+                *text = text.trim_end_matches(['\r', '\n']);
+            }
+            let html = styled_line_to_highlighted_html(
+                &ranges[..],
+                IncludeBackground::IfDifferent(background),
+            )?;
+            Ok(html)
+        })
+        .collect()
+}
+
+/// An HTML comment saying that highlighting failed with `message`.
+fn html_comment(message: &str) -> String {
+    // Comments can't have `--` in them, so as not to end early.
+    format!(
+        "<!-- highlighting failed: {} -->",
+        message.replace("--", "- -")
+    )
+}
+
+/// Highlight `content` as HTML with inline styles, wrapped in a `<pre>`.
+///
+/// Any `selected` lines are wrapped in a block with the class `selected`. Lines
+/// that can't be highlighted are dealt with as `on_error` says.
+pub fn highlight_html(
+    content: &str,
+    syntax: &SyntaxReference,
+    syntax_set: &SyntaxSet,
+    theme: &Theme,
+    selected: Option<&LineRanges>,
+    on_error: HighlightErrorPolicy,
+) -> Result<String> {
+    // Most pastes highlight without trouble, so with no lines to pick out they
+    // are highlighted all at once, only going line by line if that fails.
+    if selected.is_none() {
+        if let Ok(html) =
+            highlighted_html_for_string(content, syntax_set, syntax, theme)
+        {
+            return Ok(html);
+        }
+    }
+
+    let (mut html, _) = start_highlighted_html_snippet(theme);
+    for (number, line) in html_lines(content, syntax, syntax_set, theme, on_error)?
+        .iter()
+        .enumerate()
+    {
+        if selected.is_some_and(|selected| selected.contains(number + 1)) {
+            // Being a block, the span breaks the line itself.
+            html.push_str(&format!(
+                "<span class=\"{}\" style=\"display:block;background-color:{}\">{}</span>",
+                SELECTED_CLASS,
+                selected_background(theme),
+                line
+            ));
+        } else {
+            html.push_str(line);
+            html.push('\n');
+        }
+    }
+    html.push_str("</pre>\n");
+
+    Ok(html)
+}
+
+/// Highlight `content` as HTML like [highlight_html], with a gutter of line
+/// numbers down the side, counting from `first`.
+///
+/// Each line is a row of a table, the first cell holding its number and the
+/// second its content. Rows of `selected` lines have the class `selected`,
+/// lines being numbered from 1 there whatever `first` is.
+pub fn highlight_html_with_linenos(
+    content: &str,
+    syntax: &SyntaxReference,
+    syntax_set: &SyntaxSet,
+    theme: &Theme,
+    selected: Option<&LineRanges>,
+    first: usize,
+    on_error: HighlightErrorPolicy,
+) -> Result<String> {
+    let (mut html, _) = start_highlighted_html_snippet(theme);
+    html.push_str("<table>\n");
+    for (number, line) in html_lines(content, syntax, syntax_set, theme, on_error)?
+        .iter()
+        .enumerate()
+    {
+        let number = number + 1;
+        match selected {
+            Some(selected) if selected.contains(number) => html.push_str(&format!(
+                "<tr class=\"{}\" style=\"background-color:{}\">",
+                SELECTED_CLASS,
+                selected_background(theme)
+            )),
+            _ => html.push_str("<tr>"),
+        }
+        html.push_str(&format!(
+            "<td class=\"lineno\" style=\"user-select:none;text-align:right;padding-right:1em\">{}</td><td>{}</td></tr>\n",
+            first + number - 1, line
+        ));
+    }
+    html.push_str("</table></pre>\n");
+
+    Ok(html)
+}
+
+/// How classes in classed HTML are named, prefixed so that they don't clash
+/// with the classes of whatever page the HTML is embedded in.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// The opening tag of classed HTML.
+const CLASSED_PRE: &str = "<pre class=\"hl-code\">";
+
+/// The opening tag of classed HTML that soft-wraps long lines.
+const CLASSED_WRAPPED_PRE: &str = "<pre class=\"hl-code hl-wrap\">";
+
+/// Highlight `content` as HTML with classes rather than inline styles, wrapped
+/// in a `<pre>`.
+///
+/// This is much smaller than [highlight_html], and the same whatever the theme,
+/// but needs the stylesheet from [theme_css] to be colored. If any line can't
+/// be highlighted, the whole paste is dealt with as `on_error` says.
+pub fn highlight_html_classed(
+    content: &str,
+    syntax: &SyntaxReference,
+    syntax_set: &SyntaxSet,
+    on_error: HighlightErrorPolicy,
+) -> Result<String> {
+    let mut generator =
+        ClassedHTMLGenerator::new_with_class_style(syntax, syntax_set, CLASS_STYLE);
+    for line in LinesWithEndings::from(content) {
+        if let Err(err) = generator.parse_html_for_line_which_includes_newline(line) {
+            let comment = on_error.recover(err, html_comment)?;
+            return Ok(format!(
+                "{}{}{}</pre>\n",
+                CLASSED_PRE,
+                ammonia::clean_text(content),
+                comment.unwrap_or_default()
+            ));
+        }
+    }
+
+    Ok(format!("{}{}</pre>\n", CLASSED_PRE, generator.finalize()))
+}
+
+/// The stylesheet coloring HTML from [highlight_html_classed] with `theme`.
+pub fn theme_css(theme: &Theme) -> Result<String> {
+    let mut css = css_for_theme_with_class_style(theme, CLASS_STYLE)?;
+    css.push_str(".hl-wrap {\n white-space: pre-wrap;\n overflow-wrap: anywhere;\n}\n");
+
+    Ok(css)
+}
+
+/// Make HTML from [highlight_html], [highlight_html_with_linenos], or
+/// [highlight_html_classed] soft-wrap long lines, rather than overflow them.
+pub fn wrap_html(html: &str) -> String {
+    if html.starts_with(CLASSED_PRE) {
+        return html.replacen(CLASSED_PRE, CLASSED_WRAPPED_PRE, 1);
+    }
+
+    html.replacen(
+        "<pre style=\"",
+        "<pre style=\"white-space:pre-wrap;overflow-wrap:anywhere;",
+        1,
+    )
+}
+
+/// Wrap HTML from [highlight_html], [highlight_html_with_linenos], or
+/// [highlight_html_classed] in a complete document, for viewing on its own.
+///
+/// The page takes on the background and text colors of `theme`, so the code
+/// doesn't sit in a box of another color, and classed HTML comes with the
+/// stylesheet coloring it.
+pub fn wrap_full_html(body: &str, theme: &Theme) -> Result<String> {
+    let css_color = |Color { r, g, b, .. }| format!("#{:02x}{:02x}{:02x}", r, g, b);
+    let background = css_color(theme.settings.background.unwrap_or(Color::WHITE));
+    let foreground = css_color(theme.settings.foreground.unwrap_or(Color::BLACK));
+    let stylesheet = match body.starts_with(CLASSED_PRE) {
+        true => theme_css(theme)?,
+        false => String::new(),
+    };
+
+    Ok(format!(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <style>\n\
+         body {{\n margin: 0;\n background-color: {};\n color: {};\n}}\n\
+         pre {{\n margin: 0;\n padding: 1em;\n}}\n\
+         {}\
+         </style>\n\
+         </head>\n\
+         <body>\n\
+         {}\
+         </body>\n\
+         </html>\n",
+        background, foreground, stylesheet, body
+    ))
+}
+
+/// Wrap HTML from [highlight_html] or [highlight_html_with_linenos] for
+/// embedding in another page, with a link to the paste at `url` beneath it.
+pub fn embed_html(body: &str, url: &str) -> String {
+    let url = ammonia::clean_text(url);
+    format!(
+        "<div class=\"pstrs-embed\">\n\
+         {}\
+         <a href=\"{}\" target=\"_blank\" rel=\"noopener\" \
+         style=\"font-family:sans-serif;font-size:small\">{}</a>\n\
+         </div>\n",
+        body, url, url
+    )
+}
+
+/// Run `highlight` on a blocking thread, so that it doesn't hold up other
+/// requests, giving up on it after `timeout`.
+///
+/// Some inputs make syntect crawl, so callers should serve the content as is
+/// if this gives `None`. The thread can't be stopped, so it still runs to
+/// completion, but nobody waits for it.
+pub async fn with_timeout<T: Send + 'static>(
+    timeout: Duration,
+    highlight: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<Option<T>> {
+    let highlighting = tokio::task::spawn_blocking(highlight);
+    match tokio::time::timeout(timeout, highlighting).await {
+        Ok(output) => output.map_err(anyhow::Error::from)?.map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+/// `content` as HTML without any highlighting, for when highlighting it took
+/// too long.
+pub fn plain_html(content: &str) -> String {
+    format!("<pre>\n{}</pre>\n", ammonia::clean_text(content))
+}
+
+/// Friendly names for languages, and the extensions syntect knows them by.
+///
+/// Names syntect itself knows, e.g. `rust` for `Rust`, would be found anyway,
+/// but are listed for clarity.
+const ALIASES: &[(&str, &str)] = &[
+    ("c++", "cpp"),
+    ("c#", "cs"),
+    ("cplusplus", "cpp"),
+    ("csharp", "cs"),
+    ("golang", "go"),
+    ("haskell", "hs"),
+    ("javascript", "js"),
+    ("latex", "tex"),
+    ("markdown", "md"),
+    ("node", "js"),
+    ("perl", "pl"),
+    ("plaintext", "txt"),
+    ("py3", "py"),
+    ("python", "py"),
+    ("python3", "py"),
+    ("ruby", "rb"),
+    ("rust", "rs"),
+    ("shell", "sh"),
+    ("text", "txt"),
+];
+
+/// Find the syntax for the language `lang`, which is usually an extension but
+/// may be a friendlier name, such as `rust` or `c++`.
+pub fn find_syntax<'a>(
+    syntax_set: &'a SyntaxSet,
+    lang: &str,
+) -> Option<&'a SyntaxReference> {
+    if let Some(syntax) = syntax_set.find_syntax_by_extension(lang) {
+        return Some(syntax);
+    }
+
+    let lowercase = lang.to_lowercase();
+    ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == lowercase)
+        .and_then(|(_, extension)| syntax_set.find_syntax_by_extension(extension))
+        // Tokens are extensions or names, ignoring case.
+        .or_else(|| syntax_set.find_syntax_by_token(lang))
+}
+
+/// Guess the language of `content` from its first line (e.g. a shebang or a
+/// modeline) and highlight it using 24-bit terminal escape codes.
+///
+/// Returns the name of the detected language alongside the highlighted content,
+/// or `None` if the language couldn't be detected.
+pub fn highlight_auto(
+    content: &str,
+    syntax_set: &SyntaxSet,
+    theme: &Theme,
+    on_error: HighlightErrorPolicy,
+) -> Result<Option<(String, String)>> {
+    let first_line = content.lines().next().unwrap_or_default();
+    let Some(syntax) = syntax_set.find_syntax_by_first_line(first_line) else {
+        return Ok(None);
+    };
+    let highlighted = highlight(content, syntax, syntax_set, theme, None, on_error)?;
+
+    Ok(Some((syntax.name.clone(), highlighted)))
+}
+
+/// A syntax that fails on any line with a `!`, by going somewhere that isn't
+/// there, for testing how lines that can't be highlighted are dealt with.
+#[cfg(test)]
+pub const BROKEN_SYNTAX: &str = "name: Broken
+file_extensions: [broken]
+scope: source.broken
+contexts:
+  main:
+    - match: '!'
+      push: scope:source.missing
+";
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use syntect::parsing::{SyntaxDefinition, SyntaxSetBuilder};
+
+    use super::*;
+
+    #[test]
+    fn test_highlight_auto() -> Result<()> {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let theme_set = ThemeSet::load_defaults();
+        let theme = theme(&theme_set, None, DEFAULT_THEME)?;
+
+        // Test that a shebang gives the language away.
+        let content = "#!/usr/bin/env python\nprint('hello')\n";
+        let on_error = HighlightErrorPolicy::Raw;
+        let highlighted = highlight_auto(content, &syntax_set, theme, on_error)?;
+        let (name, highlighted) = highlighted.unwrap();
+        assert_eq!(name, "Python");
+        assert!(highlighted.contains("\x1b["));
+
+        // Test that plain prose doesn't.
+        let content = "Just some words.\n";
+        let highlighted = highlight_auto(content, &syntax_set, theme, on_error)?;
+        assert!(highlighted.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_highlight_html_with_linenos() -> Result<()> {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let theme_set = ThemeSet::load_defaults();
+        let theme = theme(&theme_set, None, DEFAULT_THEME)?;
+        let syntax = syntax_set.find_syntax_by_extension("rs").unwrap();
+
+        // Test that every line is numbered, and the trailing newline isn't.
+        let content = "fn main() {\n    println!(\"Hello, world!\");\n}\n";
+        let html = highlight_html_with_linenos(
+            content,
+            syntax,
+            &syntax_set,
+            theme,
+            None,
+            1,
+            HighlightErrorPolicy::Raw,
+        )?;
+        assert_eq!(html.matches("class=\"lineno\"").count(), 3);
+        assert!(html.contains(">3</td>"));
+        assert!(!html.contains(">4</td>"));
+
+        // Test that a final line without a newline is still numbered.
+        let content = "fn main() {}\nfn other() {}";
+        let html = highlight_html_with_linenos(
+            content,
+            syntax,
+            &syntax_set,
+            theme,
+            None,
+            1,
+            HighlightErrorPolicy::Raw,
+        )?;
+        assert_eq!(html.matches("class=\"lineno\"").count(), 2);
+
+        // Test that numbering can start further on, as for an excerpt.
+        let html = highlight_html_with_linenos(
+            content,
+            syntax,
+            &syntax_set,
+            theme,
+            None,
+            10,
+            HighlightErrorPolicy::Raw,
+        )?;
+        assert!(html.contains(">10</td>") && html.contains(">11</td>"));
+        assert!(!html.contains(">1</td>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_line_ranges() -> Result<()> {
+        // Test that a single line selects just that line.
+        let lines = "10".parse::<LineRanges>()?;
+        assert_eq!(lines, LineRanges(vec![10..=10]));
+        assert!(lines.contains(10));
+        assert!(!lines.contains(9) && !lines.contains(11));
+
+        // Test that a range selects both ends and everything between.
+        let lines = "10-15".parse::<LineRanges>()?;
+        assert_eq!(lines, LineRanges(vec![10..=15]));
+        assert!(lines.contains(10) && lines.contains(12) && lines.contains(15));
+        assert!(!lines.contains(16));
+
+        // Test that a list selects each of its parts.
+        let lines = "3,10-15".parse::<LineRanges>()?;
+        assert_eq!(lines, LineRanges(vec![3..=3, 10..=15]));
+        assert!(lines.contains(3) && lines.contains(10));
+        assert!(!lines.contains(4));
+
+        // Test that malformed specs are rejected.
+        for spec in ["", "abc", "0", "10-", "-10", "15-10", "3,,10", "1-2-3"] {
+            let err = spec.parse::<LineRanges>().unwrap_err();
+            assert_eq!(err.status(), StatusCode::BAD_REQUEST, "{:?}", spec);
+        }
+
+        // Test that lines past the end of the content are rejected.
+        let lines = "3,10-15".parse::<LineRanges>()?;
+        assert!(lines.check(15).is_ok());
+        let err = lines.check(12).unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+
+        // Test that the excerpt runs from the first selected line to the last.
+        let content = "1\n2\n3\n4\n5\n";
+        let lines = "2-3".parse::<LineRanges>()?;
+        assert_eq!(lines.excerpt(content), (2, "2\n3\n".to_string()));
+        assert_eq!(lines.within_excerpt(), None);
+        let lines = "2,4-5".parse::<LineRanges>()?;
+        assert_eq!(lines.excerpt(content), (2, "2\n3\n4\n5\n".to_string()));
+        assert_eq!(lines.within_excerpt(), Some(LineRanges(vec![1..=1, 3..=4])));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_highlight_errors() -> anyhow::Result<()> {
+        let broken = SyntaxDefinition::load_from_str(BROKEN_SYNTAX, true, None)?;
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(broken);
+        let syntax_set = builder.build();
+        let theme_set = ThemeSet::load_defaults();
+        let theme = theme(&theme_set, None, DEFAULT_THEME)?;
+        let syntax = syntax_set.find_syntax_by_extension("broken").unwrap();
+        let content = "before\nbroken<!>\nafter\n";
+
+        // Test that by default the line is left as it is, escaped for HTML.
+        let on_error = HighlightErrorPolicy::Raw;
+        let output = highlight(content, syntax, &syntax_set, theme, None, on_error)?;
+        assert!(output.contains("broken<!>"));
+        assert!(!output.contains("highlighting failed"));
+        assert_eq!(output.lines().count(), 3);
+        let html = highlight_html(content, syntax, &syntax_set, theme, None, on_error)?;
+        assert!(html.contains("broken&lt;!&gt;"));
+        assert!(html.contains("after"));
+        let html = highlight_html_classed(content, syntax, &syntax_set, on_error)?;
+        assert!(html.starts_with(CLASSED_PRE));
+        assert!(html.contains("broken&lt;!&gt;"));
+        assert!(!html.contains("highlighting failed"));
+
+        // Test that it can fail the whole paste instead.
+        let on_error = HighlightErrorPolicy::Fail;
+        let err = highlight(content, syntax, &syntax_set, theme, None, on_error);
+        assert!(matches!(err, Err(AppError::Highlight(_))));
+        let err = highlight_html(content, syntax, &syntax_set, theme, None, on_error);
+        assert!(matches!(err, Err(AppError::Highlight(_))));
+        let err = highlight_html_classed(content, syntax, &syntax_set, on_error);
+        assert!(matches!(err, Err(AppError::Highlight(_))));
+
+        // Test that it can say what went wrong, on the line that went wrong.
+        let on_error = HighlightErrorPolicy::Comment;
+        let output = highlight(content, syntax, &syntax_set, theme, None, on_error)?;
+        let line = output.lines().nth(1).unwrap();
+        assert!(line.starts_with("broken<!>  \x1b[2m# highlighting failed: "));
+        assert_eq!(output.matches("highlighting failed").count(), 1);
+        let html = highlight_html(content, syntax, &syntax_set, theme, None, on_error)?;
+        assert!(html.contains("broken&lt;!&gt;<!-- highlighting failed: "));
+        let html = highlight_html_classed(content, syntax, &syntax_set, on_error)?;
+        assert!(html.contains("<!-- highlighting failed: "));
+
+        // Test that policies are parsed from their names.
+        assert_eq!("comment".parse::<HighlightErrorPolicy>()?, on_error);
+        assert!("ignore".parse::<HighlightErrorPolicy>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_highlight_selected() -> Result<()> {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let theme_set = ThemeSet::load_defaults();
+        let theme = theme(&theme_set, None, DEFAULT_THEME)?;
+        let syntax = syntax_set.find_syntax_by_extension("rs").unwrap();
+        let content = "fn main() {\n    println!(\"Hello, world!\");\n}\n";
+        let selected = "2".parse::<LineRanges>()?;
+
+        // Test that only the selected line is marked in the terminal.
+        let highlighted = highlight(
+            content,
+            syntax,
+            &syntax_set,
+            theme,
+            Some(&selected),
+            HighlightErrorPolicy::Raw,
+        )?;
+        let marked: Vec<_> = highlighted
+            .lines()
+            .map(|line| line.starts_with(SELECTED_MARKER))
+            .collect();
+        assert_eq!(marked, [false, true, false]);
+
+        // Test that only the selected line gets the class in HTML.
+        let html = highlight_html(
+            content,
+            syntax,
+            &syntax_set,
+            theme,
+            Some(&selected),
+            HighlightErrorPolicy::Raw,
+        )?;
+        assert_eq!(html.matches("class=\"selected\"").count(), 1);
+        let html = highlight_html_with_linenos(
+            content,
+            syntax,
+            &syntax_set,
+            theme,
+            Some(&selected),
+            1,
+            HighlightErrorPolicy::Raw,
+        )?;
+        assert_eq!(html.matches("class=\"selected\"").count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrap_html() -> Result<()> {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let theme_set = ThemeSet::load_defaults();
+        let theme = theme(&theme_set, None, DEFAULT_THEME)?;
+        let syntax = syntax_set.find_syntax_by_extension("rs").unwrap();
+        let content = "fn main() {}\n";
+
+        // Test that both kinds of HTML can be wrapped.
+        let html = highlight_html(
+            content,
+            syntax,
+            &syntax_set,
+            theme,
+            None,
+            HighlightErrorPolicy::Raw,
+        )?;
+        assert!(!html.contains("pre-wrap"));
+        assert!(wrap_html(&html).starts_with("<pre style=\"white-space:pre-wrap;"));
+        let html = highlight_html_with_linenos(
+            content,
+            syntax,
+            &syntax_set,
+            theme,
+            None,
+            1,
+            HighlightErrorPolicy::Raw,
+        )?;
+        assert!(!html.contains("pre-wrap"));
+        assert!(wrap_html(&html).starts_with("<pre style=\"white-space:pre-wrap;"));
+        let html = highlight_html_classed(
+            content,
+            syntax,
+            &syntax_set,
+            HighlightErrorPolicy::Fail,
+        )?;
+        assert!(wrap_html(&html).starts_with(CLASSED_WRAPPED_PRE));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrap_full_html() -> Result<()> {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let theme_set = ThemeSet::load_defaults();
+        let theme = theme(&theme_set, Some("InspiredGitHub"), DEFAULT_THEME)?;
+        let syntax = syntax_set.find_syntax_by_extension("rs").unwrap();
+        let content = "fn main() {}\n";
+
+        // Test that the page is the theme's color, around the code.
+        let html = highlight_html(
+            content,
+            syntax,
+            &syntax_set,
+            theme,
+            None,
+            HighlightErrorPolicy::Raw,
+        )?;
+        let page = wrap_full_html(&html, theme)?;
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("background-color: #ffffff;"));
+        assert!(page.contains(&html));
+        assert!(!page.contains(".hl-"));
+
+        // Test that classed HTML brings its stylesheet along.
+        let html = highlight_html_classed(
+            content,
+            syntax,
+            &syntax_set,
+            HighlightErrorPolicy::Fail,
+        )?;
+        let page = wrap_full_html(&html, theme)?;
+        assert!(page.contains(&theme_css(theme)?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_highlight_html_classed() -> Result<()> {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let theme_set = ThemeSet::load_defaults();
+        let theme = theme(&theme_set, None, DEFAULT_THEME)?;
+        let syntax = syntax_set.find_syntax_by_extension("rs").unwrap();
+        let content = "fn main() {}\n";
+
+        // Test that the HTML is styled by classes rather than inline.
+        let html = highlight_html_classed(
+            content,
+            syntax,
+            &syntax_set,
+            HighlightErrorPolicy::Fail,
+        )?;
+        assert!(html.starts_with(CLASSED_PRE));
+        assert!(html.contains("<span class=\"hl-storage hl-type hl-function"));
+        assert!(!html.contains("style="));
+
+        // Test that the stylesheet has rules for those classes.
+        let css = theme_css(theme)?;
+        assert!(css.contains(".hl-code {"));
+        assert!(css.contains(".hl-storage"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_syntax() {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let name = |lang| find_syntax(&syntax_set, lang).map(|syntax| &syntax.name);
+
+        // Test that extensions, friendly names, and names all work.
+        for lang in ["rs", "rust", "Rust", "RUST"] {
+            assert_eq!(name(lang).map(String::as_str), Some("Rust"));
+        }
+        for lang in ["py", "python", "Python", "python3"] {
+            assert_eq!(name(lang).map(String::as_str), Some("Python"));
+        }
+        for lang in ["js", "javascript", "JavaScript", "node"] {
+            assert_eq!(name(lang).map(String::as_str), Some("JavaScript"));
+        }
+        for lang in ["cpp", "c++", "C++"] {
+            assert_eq!(name(lang).map(String::as_str), Some("C++"));
+        }
+        assert_eq!(name("golang").map(String::as_str), Some("Go"));
+
+        // Test that aliases are all for syntaxes that exist.
+        for (alias, _) in ALIASES {
+            assert!(name(alias).is_some(), "{} has no syntax", alias);
+        }
+
+        // Test that made up languages still aren't found.
+        assert_eq!(name("not-a-language"), None);
+    }
+
+    #[test]
+    fn test_lang_allowlist() -> anyhow::Result<()> {
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let rust = syntax_set.find_syntax_by_extension("rs").unwrap();
+        let python = syntax_set.find_syntax_by_extension("py").unwrap();
+
+        // Test that everything is allowed by default.
+        assert!(LangAllowlist::default().allows(rust));
+
+        // Test that languages are allowed by extension or name, ignoring case.
+        let allowlist: LangAllowlist = "RS, md".parse()?;
+        assert!(allowlist.allows(rust));
+        assert!(!allowlist.allows(python));
+        let allowlist: LangAllowlist = "python".parse()?;
+        assert!(allowlist.allows(python));
+        assert!(!allowlist.allows(rust));
+
+        // Test that an empty list is a mistake rather than allowing nothing.
+        assert!(" , ".parse::<LangAllowlist>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_too_many_lines() {
+        // Test that only going past the limit is too many.
+        assert!(!too_many_lines("one\ntwo\n", 2));
+        assert!(!too_many_lines("one\ntwo", 2));
+        assert!(too_many_lines("one\ntwo\nthree", 2));
+        assert!(!too_many_lines("", 0));
+    }
+
+    #[test]
+    fn test_for_terminal() {
+        // Test that output is reset before, and after its last line ending.
+        let output = for_terminal("\x1b[38;2;1;2;3mfn\x1b[0m\n");
+        assert_eq!(output, "\x1b[0m\x1b[38;2;1;2;3mfn\x1b[0m\n\x1b[0m");
+
+        // Test that output already ending with a reset isn't reset twice.
+        assert_eq!(for_terminal("fn\x1b[0m"), "\x1b[0mfn\x1b[0m");
+    }
+
+    #[test]
+    fn test_expand_tabs() {
+        // Test that tabs at the start of lines indent by the whole width.
+        assert_eq!(expand_tabs("\tone\n\t\ttwo\n", 4), "    one\n        two\n");
+        assert_eq!(expand_tabs("\tone\n", 2), "  one\n");
+
+        // Test that tabs mid-line only go as far as the next tab stop.
+        assert_eq!(expand_tabs("a\tb\n", 4), "a   b\n");
+        assert_eq!(expand_tabs("abc\td\n", 4), "abc d\n");
+        assert_eq!(expand_tabs("abcd\te\n", 4), "abcd    e\n");
+        assert_eq!(expand_tabs("ab\tc\n", 8), "ab      c\n");
+
+        // Test that each line starts counting columns afresh.
+        assert_eq!(expand_tabs("abc\nd\te", 4), "abc\nd   e");
+    }
+
+    #[test]
+    fn test_cached_syntax_set() -> anyhow::Result<()> {
+        let assets = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/assets");
+        let dump = std::env::temp_dir()
+            .join(format!("pstrs-{}.packdump", uuid::Uuid::new_v4()));
+        let build = || {
+            let mut syntaxes = SyntaxSet::load_defaults_newlines().into_builder();
+            syntaxes.add_from_folder(assets, true)?;
+            Ok(syntaxes.build())
+        };
+
+        // Test that the syntaxes are built and dumped the first time.
+        let built = cached_syntax_set(&dump, build)?;
+        assert!(built.find_syntax_by_extension("pstrs").is_some());
+        assert!(dump.exists());
+
+        // Test that they're loaded from the dump after, extensions and all.
+        let loaded =
+            cached_syntax_set(&dump, || panic!("the dump should have been loaded"))?;
+        assert!(loaded.find_syntax_by_extension("pstrs").is_some());
+        assert!(loaded.find_syntax_by_extension("rs").is_some());
+        assert_eq!(loaded.syntaxes().len(), built.syntaxes().len());
+
+        // Test that a dump that can't be loaded is rebuilt.
+        std::fs::write(&dump, "not a dump")?;
+        let rebuilt = cached_syntax_set(&dump, build)?;
+        assert!(rebuilt.find_syntax_by_extension("pstrs").is_some());
+        assert!(from_dump_file::<SyntaxSet, _>(&dump).is_ok());
+
+        std::fs::remove_file(&dump)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use axum::http::StatusCode;
+use reqwest::{redirect, Url};
+use url::Host;
+
+use crate::{
+    error::{AppError, Result},
+    paste,
+};
+
+/// How long fetching a URL may take, from connecting to the last byte.
+const IMPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether `ip` is on the public internet, rather than loopback, a private
+/// network, or otherwise somewhere we shouldn't be fetching from.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    // Carrier-grade NAT, 100.64.0.0/10.
+    let shared = a == 100 && (b & 0xc0) == 64;
+
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || shared)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    // Unique local, fc00::/7, and link local, fe80::/10.
+    let unique_local = (first & 0xfe00) == 0xfc00;
+    let link_local = (first & 0xffc0) == 0xfe80;
+
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || unique_local
+        || link_local)
+}
+
+/// Check that `url` is safe for the server to fetch, returning the address to
+/// fetch it from.
+///
+/// Only HTTP(S) URLs leading solely to public addresses are allowed, so that
+/// imports can't be used to reach services behind our firewall.
+pub async fn check_url(url: &Url) -> Result<SocketAddr> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(AppError::bad_request("Only HTTP(S) URLs can be imported"));
+    }
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addrs: Vec<_> = match url.host() {
+        Some(Host::Ipv4(ip)) => vec![SocketAddr::new(ip.into(), port)],
+        Some(Host::Ipv6(ip)) => vec![SocketAddr::new(ip.into(), port)],
+        Some(Host::Domain(domain)) => tokio::net::lookup_host((domain, port))
+            .await
+            .map_err(|_| AppError::bad_request(format!("Couldn't resolve {}", domain)))?
+            .collect(),
+        None => return Err(AppError::bad_request("URL must have a host")),
+    };
+
+    // Every address must be public, or which one we got would be up to chance.
+    match addrs.first() {
+        Some(addr) if addrs.iter().all(|addr| is_public(addr.ip())) => Ok(*addr),
+        Some(_) => Err(AppError::bad_request(
+            "URL must not lead to a private address",
+        )),
+        None => Err(AppError::bad_request("URL doesn't lead anywhere")),
+    }
+}
+
+/// Fetch the text at `url` from `addr`, as checked by [check_url].
+///
+/// The connection is pinned to `addr` so that the host can't resolve somewhere
+/// else the second time around, and redirects aren't followed, since they
+/// could lead anywhere. Gives up if the body is bigger than `max_size` bytes
+/// or takes longer than [IMPORT_TIMEOUT].
+pub async fn fetch(url: &Url, addr: SocketAddr, max_size: usize) -> Result<String> {
+    let too_large = || {
+        AppError::PayloadTooLarge(format!(
+            "Paste is too large, the maximum size is {} bytes",
+            max_size
+        ))
+    };
+    let unreachable = |err: reqwest::Error| match err.is_timeout() {
+        true => {
+            AppError::new(StatusCode::GATEWAY_TIMEOUT, "Fetching the URL timed out")
+        }
+        false => AppError::new(StatusCode::BAD_GATEWAY, "Fetching the URL failed"),
+    };
+
+    let mut builder = reqwest::Client::builder()
+        .redirect(redirect::Policy::none())
+        .timeout(IMPORT_TIMEOUT);
+    if let Some(domain) = url.domain() {
+        builder = builder.resolve(domain, addr);
+    }
+    let client = builder.build().map_err(anyhow::Error::from)?;
+
+    let mut response = client.get(url.clone()).send().await.map_err(unreachable)?;
+    if !response.status().is_success() {
+        return Err(AppError::new(
+            StatusCode::BAD_GATEWAY,
+            format!("Fetching the URL failed with status {}", response.status()),
+        ));
+    }
+    if response.content_length().unwrap_or(0) > max_size as u64 {
+        return Err(too_large());
+    }
+
+    // The length may be missing or a lie, so count as we go too.
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(unreachable)? {
+        if body.len() + chunk.len() > max_size {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    paste::text(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+
+    use super::*;
+
+    #[test]
+    fn test_is_public() {
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_url() {
+        // Test that private addresses, however they're spelled, are rejected.
+        for url in [
+            "http://127.0.0.1/",
+            "http://localhost:8000/",
+            "http://[::1]/",
+            "http://169.254.169.254/latest/meta-data/",
+            "ftp://example.com/",
+        ] {
+            let url = url.parse().unwrap();
+            let err = check_url(&url).await.unwrap_err();
+            assert_eq!(err.status(), StatusCode::BAD_REQUEST, "{}", url);
+        }
+
+        // Test that public addresses are allowed.
+        let url = "https://93.184.216.34/".parse().unwrap();
+        let addr = check_url(&url).await.unwrap();
+        assert_eq!(addr, "93.184.216.34:443".parse().unwrap());
+    }
+
+    // Serve `body` at `/` on a random local port, returning its address.
+    async fn serve(body: &'static str) -> SocketAddr {
+        let router = Router::new().route("/", get(move || async move { body }));
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap())
+            .serve(router.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_fetch() -> anyhow::Result<()> {
+        let addr = serve("Hello, world!").await;
+        let url = format!("http://{}/", addr).parse()?;
+
+        // Test that the body is fetched.
+        assert_eq!(fetch(&url, addr, 1024).await?, "Hello, world!");
+
+        // Test that bodies that are too big are rejected.
+        let err = fetch(&url, addr, 5).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        Ok(())
+    }
+}
@@ -1,17 +1,73 @@
-use shuttle_axum::ShuttleAxum;
+use std::time::Duration;
+
+use shuttle_runtime::CustomError;
+use shuttle_secrets::SecretStore;
 use shuttle_shared_db::Postgres;
 use sqlx::PgPool;
 
 mod app;
+mod cache;
+mod diff;
 mod error;
+mod extract;
+mod filter;
+mod highlight;
+mod import;
+mod markdown;
+mod password;
 mod paste;
+mod rate_limit;
 mod routes;
+mod shutdown;
+mod spool;
+mod sweeper;
+mod telemetry;
+mod util;
+
+/// How often expired pastes are removed from the database.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
 
 #[shuttle_runtime::main]
-async fn axum(#[Postgres] pool: PgPool) -> ShuttleAxum {
-    // Initialize the router.
-    let router = routes::make_router().with_state(app::App::postgres(pool));
+async fn axum(
+    #[Postgres] pool: PgPool,
+    #[shuttle_secrets::Secrets] secrets: SecretStore,
+) -> Result<shutdown::GracefulService, shuttle_runtime::Error> {
+    // Bring the database schema up to date.
+    sqlx::migrate!()
+        .run(&pool)
+        .await
+        .map_err(CustomError::new)?;
+
+    // Start recording metrics for `/metrics` to serve.
+    telemetry::install();
+
+    // Initialize the router, letting browser apps from the `CORS_ORIGINS` secret
+    // use it, or from anywhere if it isn't set, under the `BASE_PATH` secret if
+    // there is one.
+    let mut app = app::App::postgres(pool)?;
+    if let Some(token) = secrets.get("ADMIN_TOKEN") {
+        app = app.admin_token(token);
+    }
+    if let Some(max) = secrets.get("MAX_PASTES") {
+        app = app.max_pastes(max.parse().map_err(CustomError::new)?);
+    }
+    let app = app.build();
+    let allowed_origins = secrets
+        .get("CORS_ORIGINS")
+        .map(|list| routes::parse_origins(&list))
+        .transpose()?;
+    let base_path = secrets.get("BASE_PATH");
+    let router = routes::make_router(allowed_origins, base_path.as_deref())
+        .with_state(app.clone());
+
+    // Clean up expired pastes in the background.
+    let sweeper = sweeper::spawn_sweeper(app.pastes.clone(), SWEEP_INTERVAL);
 
-    // Let shuttle take the wheel :^)
-    Ok(router.into())
+    // Let shuttle take the wheel :^), until asked to shut down, when requests
+    // in flight are drained and expired pastes swept one last time.
+    Ok(shutdown::GracefulService {
+        router,
+        pastes: app.pastes,
+        sweeper,
+    })
 }
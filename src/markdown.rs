@@ -0,0 +1,159 @@
+use std::str::FromStr;
+
+use pulldown_cmark::{html, Options, Parser};
+
+/// What rendered Markdown may have beyond plain formatting, for deployments
+/// that would rather trade richness for safety, e.g. not to load images from
+/// wherever a paste says.
+///
+/// Parsed from a comma-separated list of what to allow, e.g. `images,links`,
+/// or `none` for neither. An empty list is rejected, like [LangAllowlist]'s,
+/// rather than taken to mean either. Everything is allowed by default.
+///
+/// [LangAllowlist]: crate::highlight::LangAllowlist
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MarkdownAllowlist {
+    /// Whether images are kept.
+    pub images: bool,
+    /// Whether links are kept, rather than just their text.
+    pub links: bool,
+}
+
+impl Default for MarkdownAllowlist {
+    fn default() -> Self {
+        Self {
+            images: true,
+            links: true,
+        }
+    }
+}
+
+impl MarkdownAllowlist {
+    /// The sanitizer for [render_markdown] that keeps only what's allowed.
+    pub fn sanitizer(&self) -> ammonia::Builder<'static> {
+        let mut sanitizer = ammonia::Builder::default();
+        if !self.images {
+            sanitizer.rm_tags(&["img"]);
+        }
+        if !self.links {
+            sanitizer.rm_tags(&["a"]);
+        }
+
+        sanitizer
+    }
+}
+
+impl FromStr for MarkdownAllowlist {
+    type Err = anyhow::Error;
+
+    fn from_str(list: &str) -> anyhow::Result<Self> {
+        let mut allowlist = Self {
+            images: false,
+            links: false,
+        };
+        let features: Vec<_> = list
+            .split(',')
+            .map(str::trim)
+            .filter(|f| !f.is_empty())
+            .collect();
+        match features[..] {
+            [] => anyhow::bail!(
+                "nothing given, expected e.g. `images,links`, or `none` for neither"
+            ),
+            [feature] if feature.eq_ignore_ascii_case("none") => return Ok(allowlist),
+            _ => {}
+        }
+        for feature in features {
+            match feature.to_lowercase().as_str() {
+                "images" => allowlist.images = true,
+                "links" => allowlist.links = true,
+                _ => anyhow::bail!(
+                    "unknown Markdown feature `{}`, expected `images` or `links`",
+                    feature
+                ),
+            }
+        }
+
+        Ok(allowlist)
+    }
+}
+
+/// Render Markdown `content` as HTML, sanitized by `sanitizer`.
+///
+/// Pastes come from anyone, so the HTML is sanitized of anything that could
+/// run script or otherwise misbehave, such as `<script>` tags, event handler
+/// attributes, and `javascript:` links, whatever the [MarkdownAllowlist]
+/// behind `sanitizer` allows. Plain HTML that's safe is kept.
+pub fn render_markdown(content: &str, sanitizer: &ammonia::Builder) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, Parser::new_ext(content, options));
+
+    sanitizer.clean(&unsafe_html).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_markdown() {
+        let sanitizer = MarkdownAllowlist::default().sanitizer();
+
+        // Test that Markdown becomes the HTML it stands for.
+        let html = render_markdown("# Title\n\nSome *emphasis*.\n", &sanitizer);
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(html.contains("<em>emphasis</em>"));
+    }
+
+    #[test]
+    fn test_render_markdown_sanitized() {
+        let sanitizer = MarkdownAllowlist::default().sanitizer();
+
+        // Test that scripts are stripped, however they're snuck in.
+        let html = render_markdown("Hi<script>alert('xss')</script>\n", &sanitizer);
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("alert"));
+
+        let html =
+            render_markdown("<img src=x onerror=\"alert('xss')\">\n", &sanitizer);
+        assert!(!html.contains("onerror"));
+
+        let html = render_markdown("[click](javascript:alert('xss'))\n", &sanitizer);
+        assert!(!html.contains("javascript:"));
+    }
+
+    #[test]
+    fn test_markdown_allowlist() -> anyhow::Result<()> {
+        let content = "![](x.png) ![]() [link](https://example.com)\n";
+
+        // Test that images and links are kept by default.
+        let html = render_markdown(content, &MarkdownAllowlist::default().sanitizer());
+        assert_eq!(html.matches("<img").count(), 2);
+        assert!(html.contains("<a href=\"https://example.com\""));
+
+        // Test that images can be stripped, leaving links.
+        let allowlist = "links".parse::<MarkdownAllowlist>()?;
+        let html = render_markdown(content, &allowlist.sanitizer());
+        assert!(!html.contains("<img"));
+        assert!(html.contains("<a href=\"https://example.com\""));
+
+        // Test that links can be stripped, leaving their text.
+        let allowlist = "images".parse::<MarkdownAllowlist>()?;
+        let html = render_markdown(content, &allowlist.sanitizer());
+        assert_eq!(html.matches("<img").count(), 2);
+        assert!(!html.contains("<a") && html.contains("link"));
+
+        // Test that the list is checked.
+        let none = "none".parse::<MarkdownAllowlist>()?;
+        assert!(!none.images && !none.links);
+        assert!("".parse::<MarkdownAllowlist>().is_err());
+        assert!(" , ".parse::<MarkdownAllowlist>().is_err());
+        assert!("images,videos".parse::<MarkdownAllowlist>().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+use argon2::{
+    password_hash::{
+        rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
+    Argon2,
+};
+
+use crate::error::Result;
+
+/// Hash a password with Argon2 and a random salt, for storing in the database.
+pub fn hash(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|err| anyhow::anyhow!("Failed to hash password: {err}"))?;
+
+    Ok(hash.to_string())
+}
+
+/// Check a password against a hash produced by [hash].
+///
+/// A malformed hash never matches.
+pub fn verify(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .and_then(|hash| Argon2::default().verify_password(password.as_bytes(), &hash))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_verify() -> Result<()> {
+        let hash = hash("hunter2")?;
+
+        // Test that only the original password matches.
+        assert!(verify("hunter2", &hash));
+        assert!(!verify("hunter3", &hash));
+        assert!(!verify("hunter2", "not a hash"));
+
+        Ok(())
+    }
+}
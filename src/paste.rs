@@ -1,15 +1,255 @@
+use std::{fmt, str::FromStr};
+
 use async_trait::async_trait;
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
+use futures_util::TryStreamExt;
 use serde::Serialize;
-use sqlx::PgPool;
+use sha2::{Digest, Sha256};
+use sqlx::{Executor, PgPool, Postgres};
 use uuid::Uuid;
 
-use crate::error::Result;
+use crate::{
+    error::{AppError, Result},
+    util,
+};
+
+mod compress;
+#[cfg(test)]
+pub mod mock;
+pub mod retry;
 
-/// A paste row in our database.
+/// A paste in our database.
 #[derive(Debug, Serialize)]
 pub struct Paste {
     pub id: Uuid,
     pub content: String,
+    pub created_at: DateTime<Utc>,
+    pub slug: Option<String>,
+    /// Argon2 hash of the password needed to read the paste, if any.
+    #[serde(skip)]
+    pub password_hash: Option<String>,
+    /// Whether the paste is deleted the first time it's read.
+    pub burn: bool,
+    /// How many times the paste has been read.
+    pub views: i64,
+    /// Extension of the language the paste was uploaded as, if any.
+    pub language: Option<String>,
+    /// SHA-256 of the token needed to delete the paste, if any.
+    #[serde(skip)]
+    pub delete_token_hash: Option<Vec<u8>>,
+    /// What the uploader called the paste, if anything.
+    pub title: Option<String>,
+    /// Whether the paste may be discovered, by listing or at random, rather
+    /// than only by those given its id.
+    pub public: bool,
+}
+
+impl Paste {
+    /// Whether `password` grants access to the paste.
+    ///
+    /// Pastes without a password are readable by anyone.
+    pub fn unlocked_by(&self, password: Option<&str>) -> bool {
+        match (&self.password_hash, password) {
+            (None, _) => true,
+            (Some(hash), Some(password)) => crate::password::verify(password, hash),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Whether `token` is the one issued for deleting the paste.
+    ///
+    /// Pastes created before delete tokens were issued can't be deleted.
+    pub fn deletable_by(&self, token: &str) -> bool {
+        self.delete_token_hash
+            .as_deref()
+            .is_some_and(|hash| util::constant_time_eq(hash, &hash_delete_token(token)))
+    }
+}
+
+/// Make a new random token for deleting a paste.
+pub fn new_delete_token() -> String { Uuid::new_v4().simple().to_string() }
+
+/// The token for deleting a paste created with `idempotency_key`.
+///
+/// It's derived from the key rather than random, so that a retry whose first
+/// response was lost can be given the token again. Anyone with the key can
+/// derive it, so keys should be as hard to guess as tokens.
+pub fn idempotent_delete_token(idempotency_key: &str) -> String {
+    format!(
+        "{:x}",
+        Sha256::digest(format!("delete:{}", idempotency_key))
+    )
+}
+
+/// Hash a delete token for storing in the database.
+///
+/// Unlike passwords, tokens are random enough that a fast hash is safe.
+pub fn hash_delete_token(token: &str) -> Vec<u8> { Sha256::digest(token).to_vec() }
+
+/// Hash an API key for storing in the database as the owner of a paste.
+///
+/// Keys are meant to be as random as tokens, so they're hashed the same way,
+/// which also keeps the hash the same each time for looking pastes up by it.
+pub fn hash_api_key(key: &str) -> Vec<u8> { Sha256::digest(key).to_vec() }
+
+/// Check that `content` is text we can store as a paste's content.
+///
+/// Besides being UTF-8, it mustn't contain NUL bytes, which Postgres `text`
+/// can't hold.
+pub fn text(content: Vec<u8>) -> Result<String> {
+    let content = String::from_utf8(content)
+        .map_err(|_| AppError::bad_request("Paste must be valid UTF-8 text"))?;
+    if content.contains('\0') {
+        return Err(AppError::bad_request("Paste must not contain NUL bytes"));
+    }
+
+    Ok(content)
+}
+
+/// Everything needed to create a new paste.
+#[derive(Debug, Default, Clone)]
+pub struct NewPaste {
+    pub content: String,
+    /// When the paste should stop being retrievable, if ever.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// How long the paste may go without being read before it's removed, if
+    /// ever.
+    pub idle_ttl: Option<Duration>,
+    /// A human-readable name the paste can also be retrieved by.
+    pub slug: Option<String>,
+    /// Argon2 hash of the password needed to read the paste, if any.
+    pub password_hash: Option<String>,
+    /// Whether the paste should be deleted the first time it's read.
+    pub burn: bool,
+    /// Extension of the language to highlight the paste as by default.
+    pub language: Option<String>,
+    /// SHA-256 of the token needed to delete the paste, if any.
+    pub delete_token_hash: Option<Vec<u8>>,
+    /// A key chosen by the client, so that creating a paste with a key already
+    /// used gives the paste first created with it rather than a new one.
+    pub idempotency_key: Option<String>,
+    /// Whether the paste is a fork of another, made to be a copy of it.
+    pub fork: bool,
+    /// What the uploader calls the paste.
+    pub title: Option<String>,
+    /// Whether the paste may be discovered, by listing or at random.
+    pub public: bool,
+    /// SHA-256 of the API key of whoever uploaded the paste, if they gave one.
+    pub owner_key_hash: Option<Vec<u8>>,
+}
+
+impl NewPaste {
+    /// SHA-256 of the content, by which identical pastes are deduplicated.
+    ///
+    /// Only pastes with nothing but content are deduplicated, since handing
+    /// out an existing paste in place of a named, protected, expiring,
+    /// highlighted, public, or owned one would silently lose what the uploader
+    /// asked for. Pastes with an idempotency key are deduplicated by that
+    /// instead, and forks not at all, since the point of one is a paste of
+    /// its own.
+    pub fn content_hash(&self) -> Option<Vec<u8>> {
+        let plain = self.idempotency_key.is_none()
+            && !self.fork
+            && self.expires_at.is_none()
+            && self.idle_ttl.is_none()
+            && self.slug.is_none()
+            && self.password_hash.is_none()
+            && !self.burn
+            && self.language.is_none()
+            && self.title.is_none()
+            && !self.public
+            && self.owner_key_hash.is_none();
+
+        plain.then(|| Sha256::digest(&self.content).to_vec())
+    }
+}
+
+/// Metadata about a paste, without its content.
+#[derive(Debug, Serialize)]
+pub struct PasteMeta {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    /// Length of the content in bytes.
+    pub size: usize,
+    pub views: i64,
+    pub title: Option<String>,
+    /// Whether the paste is password protected, in which case only those with
+    /// the password should see the rest.
+    #[serde(skip)]
+    pub protected: bool,
+}
+
+impl From<&Paste> for PasteMeta {
+    fn from(paste: &Paste) -> Self {
+        Self {
+            id: paste.id,
+            created_at: paste.created_at,
+            size: paste.content.len(),
+            views: paste.views,
+            title: paste.title.clone(),
+            protected: paste.password_hash.is_some(),
+        }
+    }
+}
+
+/// Where a [PasteStore::search] stopped, for the next one to carry on from:
+/// the last paste it went through, newest first.
+///
+/// It's given to clients as `<created_at>.<id>`, the creation time in
+/// microseconds, so it keeps its place even as newer pastes are created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchCursor {
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl fmt::Display for SearchCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.created_at.timestamp_micros(), self.id)
+    }
+}
+
+impl FromStr for SearchCursor {
+    type Err = AppError;
+
+    fn from_str(cursor: &str) -> Result<Self> {
+        let invalid = || AppError::bad_request(format!("Invalid cursor `{}`", cursor));
+        let (micros, id) = cursor.split_once('.').ok_or_else(invalid)?;
+        let created_at = micros
+            .parse()
+            .ok()
+            .and_then(NaiveDateTime::from_timestamp_micros)
+            .ok_or_else(invalid)?;
+
+        Ok(Self {
+            created_at: Utc.from_utc_datetime(&created_at),
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// A page of what a [PasteStore::search] found.
+#[derive(Debug)]
+pub struct SearchPage {
+    /// Metadata of the pastes found, newest first.
+    pub pastes: Vec<PasteMeta>,
+    /// Where to carry on searching from, unless the search went through every
+    /// paste there was left to.
+    pub next: Option<SearchCursor>,
+}
+
+/// Escape the characters `LIKE` treats specially in `term`, so that it only
+/// matches itself.
+fn escape_like(term: &str) -> String {
+    let mut escaped = String::with_capacity(term.len());
+    for c in term.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
 }
 
 /// Trait for interacting with the paste database.
@@ -22,51 +262,1029 @@ pub struct Paste {
 /// See: https://rust-lang.github.io/async-fundamentals-initiative/explainer/async_fn_in_dyn_trait.html
 #[async_trait]
 pub trait PasteStore: Send + Sync {
-    /// Get a paste by its ID.
+    /// Get a paste by its ID, counting it as a view.
+    ///
+    /// Pastes whose expiry has passed are treated as if they don't exist, even
+    /// if they haven't been physically removed yet. Views count as accesses,
+    /// keeping pastes with an idle TTL from being removed.
     async fn get(&self, id: Uuid) -> Result<Option<Paste>>;
 
+    /// Get a paste by its ID, without counting it as a view.
+    ///
+    /// Like [PasteStore::get], expired pastes are treated as missing.
+    async fn peek(&self, id: Uuid) -> Result<Option<Paste>>;
+
+    /// Get the metadata of a paste, without counting it as a view or loading
+    /// its content.
+    ///
+    /// Like [PasteStore::get], expired pastes are treated as missing.
+    async fn get_meta(&self, id: Uuid) -> Result<Option<PasteMeta>>;
+
+    /// Whether there's a paste with the ID, without counting it as a view or
+    /// loading any of it.
+    ///
+    /// Like [PasteStore::get], expired pastes are treated as missing.
+    async fn exists(&self, id: Uuid) -> Result<bool>;
+
+    /// Get the slug of a paste, if it's there and has one, without counting it
+    /// as a view or loading the rest of it.
+    ///
+    /// Like [PasteStore::get], expired pastes are treated as missing.
+    async fn slug(&self, id: Uuid) -> Result<Option<String>>;
+
+    /// Get a paste by its slug, counting it as a view.
+    ///
+    /// Like [PasteStore::get], expired pastes are treated as missing.
+    async fn get_by_slug(&self, slug: &str) -> Result<Option<Paste>>;
+
+    /// Get the pastes with any of the IDs, counting each as a view.
+    ///
+    /// Pastes that can't be read in bulk, being password protected or burn
+    /// after reading, are left out as if they don't exist, as are expired ones.
+    /// The pastes are in no particular order.
+    async fn get_many(&self, ids: &[Uuid]) -> Result<Vec<Paste>>;
+
     /// Create a new paste.
-    async fn create(&self, content: String) -> Result<Paste>;
+    ///
+    /// If an identical paste already exists (see [NewPaste::content_hash]),
+    /// that paste is returned instead, keeping its own delete token. Likewise
+    /// if a paste was already created with the same idempotency key.
+    ///
+    /// Fails with [AppError::Conflict] if another paste already has the slug.
+    async fn create(&self, paste: NewPaste) -> Result<Paste>;
+
+    /// Replace the content of a paste, returning the updated paste.
+    ///
+    /// The paste stops being deduplicated, so uploads of its old content no
+    /// longer lead to it.
+    ///
+    /// Like [PasteStore::get], expired pastes are treated as missing.
+    async fn update(&self, id: Uuid, content: String) -> Result<Option<Paste>>;
+
+    /// Change the title and language of a paste to those given, leaving those
+    /// that aren't as they are, returning the updated paste's metadata.
+    ///
+    /// Like [PasteStore::get], expired pastes are treated as missing.
+    async fn update_meta(
+        &self,
+        id: Uuid,
+        title: Option<&str>,
+        language: Option<&str>,
+    ) -> Result<Option<PasteMeta>>;
+
+    /// Add `content` onto the end of a paste, returning the updated paste.
+    ///
+    /// This is atomic, so concurrent appends all make it in, in some order.
+    /// Like [PasteStore::update], the paste stops being deduplicated, and
+    /// expired pastes are treated as missing.
+    async fn append(&self, id: Uuid, content: &str) -> Result<Option<Paste>>;
+
+    /// Remove a burn after reading paste, returning it.
+    ///
+    /// This is atomic, so of many concurrent readers only one gets the paste,
+    /// the rest get `None` as though it never existed.
+    async fn get_and_burn(&self, id: Uuid) -> Result<Option<Paste>>;
 
     /// Remove a paste.
     async fn remove(&self, id: Uuid) -> Result<Option<Paste>>;
+
+    /// Remove the pastes with any of the IDs, all at once or not at all,
+    /// returning the IDs of those there were.
+    ///
+    /// Expired pastes not yet removed are removed too, as by
+    /// [PasteStore::remove].
+    async fn remove_many(&self, ids: &[Uuid]) -> Result<Vec<Uuid>>;
+
+    /// List metadata of the pastes, newest first, skipping the first `offset`
+    /// and returning at most `limit`.
+    ///
+    /// Only [public](Paste::public) pastes are listed if `public_only`. Like
+    /// [PasteStore::get], expired pastes are treated as missing.
+    async fn list(
+        &self,
+        limit: u32,
+        offset: u32,
+        public_only: bool,
+    ) -> Result<Vec<PasteMeta>>;
+
+    /// List metadata of the pastes uploaded with the API key hashed to
+    /// `owner_key_hash`, newest first, skipping the first `offset` and
+    /// returning at most `limit`.
+    ///
+    /// Like [PasteStore::get], expired pastes are treated as missing.
+    async fn list_by_owner(
+        &self,
+        owner_key_hash: &[u8],
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<PasteMeta>>;
+
+    /// Search for pastes whose content contains `term`, ignoring case, finding
+    /// at most `limit` of them, newest first.
+    ///
+    /// Only `window` pastes are gone through, since compressed ones have to be
+    /// read to search them, starting with the newest, or the newest older than
+    /// `after` if given. The search stops there or once `limit` are found,
+    /// saying where it did in [SearchPage::next]. The term is matched
+    /// literally, wildcards and all. Like [PasteStore::get], expired pastes are
+    /// treated as missing.
+    async fn search(
+        &self,
+        term: &str,
+        limit: u32,
+        after: Option<SearchCursor>,
+        window: u32,
+    ) -> Result<SearchPage>;
+
+    /// Pick the id of a paste at random, if there are any.
+    ///
+    /// Only [public](Paste::public) pastes anyone may read without consequence
+    /// are picked from, so not password protected or burn after reading ones,
+    /// nor expired ones.
+    async fn random(&self) -> Result<Option<Uuid>>;
+
+    /// Count the pastes, including expired ones not yet removed.
+    async fn count(&self) -> Result<u64>;
+
+    /// Remove every paste whose expiry has passed, or that has gone unread for
+    /// longer than its idle TTL, returning how many were removed.
+    async fn remove_expired(&self) -> Result<u64>;
+
+    /// Remove every paste, returning how many were removed.
+    ///
+    /// Meant for resetting test and staging deployments, there's no undoing it.
+    async fn clear(&self) -> Result<u64>;
+}
+
+/// The table pastes are kept in, unless configured otherwise.
+pub const DEFAULT_TABLE: &str = "pastes";
+
+/// Whether `table` is a plain table name, optionally qualified by its schema,
+/// and so safe to put in a query as is.
+fn valid_table_name(table: &str) -> bool {
+    let valid_identifier = |name: &str| {
+        let mut chars = name.chars();
+        chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+
+    table.split('.').count() <= 2 && table.split('.').all(valid_identifier)
+}
+
+/// Pastes stored in a Postgres table.
+///
+/// The table needs the columns of `pastes` as migrated, e.g. made with
+/// `CREATE TABLE <table> (LIKE pastes INCLUDING ALL)`. Since the table name
+/// isn't known until runtime, the queries can't be checked at compile time.
+#[derive(Debug, Clone)]
+pub struct PgPasteStore {
+    pool: PgPool,
+    table: String,
+}
+
+impl PgPasteStore {
+    /// Store pastes in `table`, e.g. `pastes` or `archive.pastes`, through
+    /// `pool`.
+    pub fn new(pool: PgPool, table: &str) -> anyhow::Result<Self> {
+        if !valid_table_name(table) {
+            anyhow::bail!("invalid table name `{}`", table);
+        }
+
+        Ok(Self {
+            pool,
+            table: table.to_string(),
+        })
+    }
+
+    /// Fill the configured table name into `query` in place of `{table}`.
+    fn sql(&self, query: &str) -> String { query.replace("{table}", &self.table) }
+
+    /// Replace the content of a paste through `executor`, returning the updated
+    /// paste, for [PasteStore::update] and [PasteStore::append].
+    async fn set_content<'e>(
+        &self,
+        executor: impl Executor<'e, Database = Postgres>,
+        id: Uuid,
+        content: &str,
+    ) -> Result<Option<Paste>> {
+        let stored = compress::compress(content)?;
+        let row: Option<PasteRow> = sqlx::query_as(&self.sql(
+            "UPDATE {table} SET content = $2, compressed = $3, size = $4, content_hash = NULL
+             WHERE id = $1 AND (expires_at IS NULL OR expires_at > now())
+             RETURNING id, content, compressed, created_at, slug, password_hash, burn, views, language,
+                       delete_token_hash, title, public",
+        ))
+        .bind(id)
+        .bind(&stored.bytes)
+        .bind(stored.compressed)
+        .bind(content.len() as i32)
+        .fetch_optional(executor)
+        .await?;
+
+        row.map(Paste::try_from).transpose()
+    }
+}
+
+/// A paste row, before its content is decompressed into a [Paste].
+#[derive(sqlx::FromRow)]
+struct PasteRow {
+    id: Uuid,
+    content: Vec<u8>,
+    compressed: bool,
+    created_at: DateTime<Utc>,
+    slug: Option<String>,
+    password_hash: Option<String>,
+    burn: bool,
+    views: i64,
+    language: Option<String>,
+    delete_token_hash: Option<Vec<u8>>,
+    title: Option<String>,
+    public: bool,
+}
+
+impl TryFrom<PasteRow> for Paste {
+    type Error = AppError;
+
+    fn try_from(row: PasteRow) -> Result<Self> {
+        Ok(Self {
+            id: row.id,
+            content: compress::decompress(&row.content, row.compressed)?,
+            created_at: row.created_at,
+            slug: row.slug,
+            password_hash: row.password_hash,
+            burn: row.burn,
+            views: row.views,
+            language: row.language,
+            delete_token_hash: row.delete_token_hash,
+            title: row.title,
+            public: row.public,
+        })
+    }
+}
+
+/// A row of paste metadata, before being converted to [PasteMeta].
+#[derive(sqlx::FromRow)]
+struct MetaRow {
+    id: Uuid,
+    created_at: DateTime<Utc>,
+    size: i32,
+    views: i64,
+    title: Option<String>,
+    protected: bool,
+}
+
+/// A row of paste metadata, for searching, along with whether its content
+/// matched, or the content itself if it's compressed and has to be read to
+/// tell.
+#[derive(sqlx::FromRow)]
+struct SearchRow {
+    #[sqlx(flatten)]
+    meta: MetaRow,
+    matched: bool,
+    compressed_content: Option<Vec<u8>>,
+}
+
+impl From<MetaRow> for PasteMeta {
+    fn from(row: MetaRow) -> Self {
+        Self {
+            id: row.id,
+            created_at: row.created_at,
+            size: row.size as usize,
+            views: row.views,
+            title: row.title,
+            protected: row.protected,
+        }
+    }
 }
 
 #[async_trait]
-impl PasteStore for PgPool {
+impl PasteStore for PgPasteStore {
     async fn get(&self, id: Uuid) -> Result<Option<Paste>> {
-        let paste = sqlx::query_as!(
-            crate::paste::Paste,
-            "SELECT id, content FROM pastes WHERE id = $1",
-            id
-        )
-        .fetch_optional(self)
+        let row: Option<PasteRow> = sqlx::query_as(&self.sql(
+            "UPDATE {table} SET views = views + 1, last_accessed_at = now()
+             WHERE id = $1 AND (expires_at IS NULL OR expires_at > now())
+             RETURNING id, content, compressed, created_at, slug, password_hash, burn, views, language,
+                       delete_token_hash, title, public",
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
         .await?;
 
-        Ok(paste)
+        row.map(Paste::try_from).transpose()
     }
 
-    async fn create(&self, content: String) -> Result<Paste> {
-        let paste = sqlx::query_as!(
-            crate::paste::Paste,
-            "INSERT INTO pastes(content) VALUES ($1) RETURNING id, content",
-            content
-        )
-        .fetch_one(self)
+    async fn peek(&self, id: Uuid) -> Result<Option<Paste>> {
+        let row: Option<PasteRow> = sqlx::query_as(&self.sql(
+            "SELECT id, content, compressed, created_at, slug, password_hash, burn, views, language,
+                    delete_token_hash, title, public FROM {table}
+             WHERE id = $1 AND (expires_at IS NULL OR expires_at > now())",
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
         .await?;
 
+        row.map(Paste::try_from).transpose()
+    }
+
+    async fn get_meta(&self, id: Uuid) -> Result<Option<PasteMeta>> {
+        let meta = sqlx::query_as::<_, MetaRow>(&self.sql(
+            "SELECT id, created_at, size, views, title,
+                 password_hash IS NOT NULL AS protected
+             FROM {table}
+             WHERE id = $1 AND (expires_at IS NULL OR expires_at > now())",
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(PasteMeta::from);
+
+        Ok(meta)
+    }
+
+    async fn exists(&self, id: Uuid) -> Result<bool> {
+        let exists = sqlx::query_scalar(&self.sql(
+            "SELECT EXISTS(
+                 SELECT 1 FROM {table}
+                 WHERE id = $1 AND (expires_at IS NULL OR expires_at > now())
+             )",
+        ))
+        .bind(id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(exists)
+    }
+
+    async fn slug(&self, id: Uuid) -> Result<Option<String>> {
+        let slug = sqlx::query_scalar::<_, Option<String>>(&self.sql(
+            "SELECT slug FROM {table}
+             WHERE id = $1 AND (expires_at IS NULL OR expires_at > now())",
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(slug.flatten())
+    }
+
+    async fn get_by_slug(&self, slug: &str) -> Result<Option<Paste>> {
+        let row: Option<PasteRow> = sqlx::query_as(&self.sql(
+            "UPDATE {table} SET views = views + 1, last_accessed_at = now()
+             WHERE slug = $1 AND (expires_at IS NULL OR expires_at > now())
+             RETURNING id, content, compressed, created_at, slug, password_hash, burn, views, language,
+                       delete_token_hash, title, public",
+        ))
+        .bind(slug)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(Paste::try_from).transpose()
+    }
+
+    async fn get_many(&self, ids: &[Uuid]) -> Result<Vec<Paste>> {
+        let rows: Vec<PasteRow> = sqlx::query_as(&self.sql(
+            "UPDATE {table} SET views = views + 1, last_accessed_at = now()
+             WHERE id = ANY($1) AND password_hash IS NULL AND NOT burn
+                AND (expires_at IS NULL OR expires_at > now())
+             RETURNING id, content, compressed, created_at, slug, password_hash, burn, views, language,
+                       delete_token_hash, title, public",
+        ))
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(Paste::try_from).collect()
+    }
+
+    async fn create(&self, paste: NewPaste) -> Result<Paste> {
+        let content_hash = paste.content_hash();
+        let stored = compress::compress(&paste.content)?;
+        let size = paste.content.len() as i32;
+        // The constraint is named after the table, whatever that is.
+        let slug_conflict = |err: sqlx::Error| match &err {
+            sqlx::Error::Database(db)
+                if db
+                    .constraint()
+                    .is_some_and(|name| name.ends_with("_slug_key")) =>
+            {
+                AppError::Conflict("A paste with that name already exists".to_string())
+            }
+            _ => err.into(),
+        };
+
+        let Some(key) = &paste.idempotency_key else {
+            // Updating the conflicting row to itself is what gets it returned.
+            let row: PasteRow = sqlx::query_as(&self.sql(
+                "INSERT INTO {table}(content, compressed, size, expires_at, slug, password_hash, burn, language,
+                                     content_hash, delete_token_hash, idle_ttl, title, public, owner_key_hash)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+                 ON CONFLICT (content_hash) DO UPDATE SET content_hash = EXCLUDED.content_hash
+                 RETURNING id, content, compressed, created_at, slug, password_hash, burn, views, language,
+                           delete_token_hash, title, public",
+            ))
+            .bind(&stored.bytes)
+            .bind(stored.compressed)
+            .bind(size)
+            .bind(paste.expires_at)
+            .bind(&paste.slug)
+            .bind(&paste.password_hash)
+            .bind(paste.burn)
+            .bind(&paste.language)
+            .bind(content_hash)
+            .bind(&paste.delete_token_hash)
+            .bind(paste.idle_ttl)
+            .bind(&paste.title)
+            .bind(paste.public)
+            .bind(&paste.owner_key_hash)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(slug_conflict)?;
+
+            return row.try_into();
+        };
+
+        // Keyed pastes aren't deduplicated by content, so the key is the only
+        // conflict to expect.
+        let created: Option<PasteRow> = sqlx::query_as(&self.sql(
+            "INSERT INTO {table}(content, compressed, size, expires_at, slug, password_hash, burn, language,
+                                 delete_token_hash, idempotency_key, idle_ttl, title, public, owner_key_hash)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+             ON CONFLICT (idempotency_key) DO NOTHING
+             RETURNING id, content, compressed, created_at, slug, password_hash, burn, views, language,
+                       delete_token_hash, title, public",
+        ))
+        .bind(&stored.bytes)
+        .bind(stored.compressed)
+        .bind(size)
+        .bind(paste.expires_at)
+        .bind(&paste.slug)
+        .bind(&paste.password_hash)
+        .bind(paste.burn)
+        .bind(&paste.language)
+        .bind(&paste.delete_token_hash)
+        .bind(key)
+        .bind(paste.idle_ttl)
+        .bind(&paste.title)
+        .bind(paste.public)
+        .bind(&paste.owner_key_hash)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(slug_conflict)?;
+        if let Some(row) = created {
+            return row.try_into();
+        }
+
+        // Doing nothing returns nothing, so fetch what the key was used for.
+        let row: PasteRow = sqlx::query_as(&self.sql(
+            "SELECT id, content, compressed, created_at, slug, password_hash, burn, views, language,
+                    delete_token_hash, title, public
+             FROM {table}
+             WHERE idempotency_key = $1",
+        ))
+        .bind(key)
+        .fetch_one(&self.pool)
+        .await?;
+
+        row.try_into()
+    }
+
+    async fn update(&self, id: Uuid, content: String) -> Result<Option<Paste>> {
+        self.set_content(&self.pool, id, &content).await
+    }
+
+    async fn update_meta(
+        &self,
+        id: Uuid,
+        title: Option<&str>,
+        language: Option<&str>,
+    ) -> Result<Option<PasteMeta>> {
+        let meta = sqlx::query_as::<_, MetaRow>(&self.sql(
+            "UPDATE {table} SET title = COALESCE($2, title), language = COALESCE($3, language)
+             WHERE id = $1 AND (expires_at IS NULL OR expires_at > now())
+             RETURNING id, created_at, size, views, title,
+                 password_hash IS NOT NULL AS protected",
+        ))
+        .bind(id)
+        .bind(title)
+        .bind(language)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(PasteMeta::from);
+
+        Ok(meta)
+    }
+
+    async fn append(&self, id: Uuid, content: &str) -> Result<Option<Paste>> {
+        // Compressed content can't be appended to in the database, so the paste
+        // is locked while it's appended to here, keeping concurrent appends
+        // from losing each other's content.
+        let mut tx = self.pool.begin().await?;
+        let row: Option<(Vec<u8>, bool)> = sqlx::query_as(&self.sql(
+            "SELECT content, compressed FROM {table}
+             WHERE id = $1 AND (expires_at IS NULL OR expires_at > now())
+             FOR UPDATE",
+        ))
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some((stored, compressed)) = row else {
+            return Ok(None);
+        };
+        let mut appended = compress::decompress(&stored, compressed)?;
+        appended.push_str(content);
+        let paste = self.set_content(&mut *tx, id, &appended).await?;
+        tx.commit().await?;
+
         Ok(paste)
     }
 
+    async fn get_and_burn(&self, id: Uuid) -> Result<Option<Paste>> {
+        let row: Option<PasteRow> = sqlx::query_as(&self.sql(
+            "DELETE FROM {table}
+             WHERE id = $1 AND burn AND (expires_at IS NULL OR expires_at > now())
+             RETURNING id, content, compressed, created_at, slug, password_hash, burn, views, language,
+                       delete_token_hash, title, public",
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(Paste::try_from).transpose()
+    }
+
     async fn remove(&self, id: Uuid) -> Result<Option<Paste>> {
-        let paste = sqlx::query_as!(
-            crate::paste::Paste,
-            "DELETE FROM pastes WHERE id = $1 RETURNING id, content",
-            id
+        let row: Option<PasteRow> = sqlx::query_as(&self.sql(
+            "DELETE FROM {table} WHERE id = $1
+             RETURNING id, content, compressed, created_at, slug, password_hash, burn, views, language,
+                       delete_token_hash, title, public",
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(Paste::try_from).transpose()
+    }
+
+    async fn remove_many(&self, ids: &[Uuid]) -> Result<Vec<Uuid>> {
+        // Being one statement, this is one transaction, so either every paste
+        // is removed or none are.
+        let removed = sqlx::query_scalar(
+            &self.sql("DELETE FROM {table} WHERE id = ANY($1) RETURNING id"),
         )
-        .fetch_optional(self)
+        .bind(ids)
+        .fetch_all(&self.pool)
         .await?;
 
-        Ok(paste)
+        Ok(removed)
+    }
+
+    async fn list(
+        &self,
+        limit: u32,
+        offset: u32,
+        public_only: bool,
+    ) -> Result<Vec<PasteMeta>> {
+        let pastes = sqlx::query_as::<_, MetaRow>(&self.sql(
+            "SELECT id, created_at, size, views, title,
+                 password_hash IS NOT NULL AS protected
+             FROM {table}
+             WHERE (public OR NOT $3) AND (expires_at IS NULL OR expires_at > now())
+             ORDER BY created_at DESC, id
+             LIMIT $1 OFFSET $2",
+        ))
+        .bind(i64::from(limit))
+        .bind(i64::from(offset))
+        .bind(public_only)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(PasteMeta::from)
+        .collect();
+
+        Ok(pastes)
+    }
+
+    async fn list_by_owner(
+        &self,
+        owner_key_hash: &[u8],
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<PasteMeta>> {
+        let pastes = sqlx::query_as::<_, MetaRow>(&self.sql(
+            "SELECT id, created_at, size, views, title,
+                 password_hash IS NOT NULL AS protected
+             FROM {table}
+             WHERE owner_key_hash = $1 AND (expires_at IS NULL OR expires_at > now())
+             ORDER BY created_at DESC, id
+             LIMIT $2 OFFSET $3",
+        ))
+        .bind(owner_key_hash)
+        .bind(i64::from(limit))
+        .bind(i64::from(offset))
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(PasteMeta::from)
+        .collect();
+
+        Ok(pastes)
+    }
+
+    async fn search(
+        &self,
+        term: &str,
+        limit: u32,
+        after: Option<SearchCursor>,
+        window: u32,
+    ) -> Result<SearchPage> {
+        // Uncompressed content is matched in the database, but compressed
+        // content can't be, so it's read back and matched here instead. The
+        // window of pastes is gone through newest first until there are enough
+        // matches, which costs decompressing up to `window` pastes, hence it
+        // being capped.
+        let query = self.sql(
+            "SELECT id, created_at, size, views, title,
+                 password_hash IS NOT NULL AS protected,
+                 NOT compressed AND convert_from(content, 'UTF8') ILIKE $1 ESCAPE '\\'
+                     AS matched,
+                 CASE WHEN compressed THEN content END AS compressed_content
+             FROM {table}
+             WHERE (expires_at IS NULL OR expires_at > now())
+                   AND ($2::timestamptz IS NULL
+                        OR created_at < $2 OR (created_at = $2 AND id > $3))
+             ORDER BY created_at DESC, id
+             LIMIT $4",
+        );
+        let mut rows = sqlx::query_as::<_, SearchRow>(&query)
+            .bind(format!("%{}%", escape_like(term)))
+            .bind(after.map(|after| after.created_at))
+            .bind(after.map(|after| after.id))
+            .bind(i64::from(window))
+            .fetch(&self.pool);
+        let term = term.to_lowercase();
+        let mut pastes = Vec::new();
+        let mut searched = 0;
+        let mut last = None;
+        while pastes.len() < limit as usize {
+            let Some(row) = rows.try_next().await? else {
+                break;
+            };
+            searched += 1;
+            last = Some(SearchCursor {
+                created_at: row.meta.created_at,
+                id: row.meta.id,
+            });
+            let matched = match row.compressed_content {
+                Some(content) => compress::decompress(&content, true)?
+                    .to_lowercase()
+                    .contains(&term),
+                None => row.matched,
+            };
+            if matched {
+                pastes.push(PasteMeta::from(row.meta));
+            }
+        }
+        // Stopping short of the window means there were no pastes left.
+        let next = match pastes.len() == limit as usize || searched == window {
+            true => last,
+            false => None,
+        };
+
+        Ok(SearchPage { pastes, next })
+    }
+
+    async fn random(&self) -> Result<Option<Uuid>> {
+        let id = sqlx::query_scalar(&self.sql(
+            "SELECT id FROM {table}
+             WHERE public AND password_hash IS NULL AND NOT burn
+                   AND (expires_at IS NULL OR expires_at > now())
+             ORDER BY random()
+             LIMIT 1",
+        ))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(id)
+    }
+
+    async fn count(&self) -> Result<u64> {
+        let count: i64 = sqlx::query_scalar(&self.sql("SELECT count(*) FROM {table}"))
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(count as u64)
+    }
+
+    async fn remove_expired(&self) -> Result<u64> {
+        let result = sqlx::query(&self.sql(
+            "DELETE FROM {table}
+             WHERE expires_at < now() OR last_accessed_at + idle_ttl < now()",
+        ))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn clear(&self) -> Result<u64> {
+        let result = sqlx::query(&self.sql("DELETE FROM {table}"))
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text() {
+        // Test that text, however exotic, is accepted as is.
+        let content = "hello 🌍\n\ttabs and all";
+        assert_eq!(text(content.into()).ok().as_deref(), Some(content));
+
+        // Test that binary isn't.
+        assert!(text(vec![0xff, 0xfe, 0x00]).is_err());
+        assert!(text(b"hello\0world".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_escape_like() {
+        // Test that ordinary terms are left alone.
+        assert_eq!(escape_like("hello world"), "hello world");
+
+        // Test that wildcards, and the escape itself, are escaped.
+        assert_eq!(escape_like("100%"), "100\\%");
+        assert_eq!(escape_like("snake_case"), "snake\\_case");
+        assert_eq!(escape_like("C:\\"), "C:\\\\");
+    }
+
+    #[test]
+    fn test_search_cursor() {
+        // Test that a cursor is read back as it was given out.
+        let cursor = SearchCursor {
+            created_at: Utc.timestamp_opt(1_700_000_000, 123_456_000).unwrap(),
+            id: Uuid::new_v4(),
+        };
+        assert_eq!(
+            cursor.to_string().parse::<SearchCursor>().ok(),
+            Some(cursor)
+        );
+
+        // Test that anything else is refused.
+        assert!("".parse::<SearchCursor>().is_err());
+        assert!("1700000000123456".parse::<SearchCursor>().is_err());
+        assert!("soon.67e55044-10b1-426f-9247-bb680e5fe0c8"
+            .parse::<SearchCursor>()
+            .is_err());
+        assert!("1700000000123456.nope".parse::<SearchCursor>().is_err());
+    }
+
+    #[test]
+    fn test_valid_table_name() {
+        // Test that names, qualified by their schema or not, are valid.
+        assert!(valid_table_name(DEFAULT_TABLE));
+        assert!(valid_table_name("archive.old_pastes2"));
+
+        // Test that anything that could change the query isn't.
+        assert!(!valid_table_name(""));
+        assert!(!valid_table_name("2pastes"));
+        assert!(!valid_table_name("a.b.c"));
+        assert!(!valid_table_name("pastes; DROP TABLE pastes"));
+        assert!(!valid_table_name("\"pastes\""));
+    }
+
+    // Needs a database to create the table in, so run it with `DATABASE_URL`
+    // set and `--ignored`.
+    #[sqlx::test]
+    #[ignore = "needs a Postgres database at DATABASE_URL"]
+    async fn test_table(pool: PgPool) -> anyhow::Result<()> {
+        sqlx::query("CREATE SCHEMA archive").execute(&pool).await?;
+        sqlx::query("CREATE TABLE archive.pastes (LIKE public.pastes INCLUDING ALL)")
+            .execute(&pool)
+            .await?;
+        let store = PgPasteStore::new(pool.clone(), "archive.pastes")?;
+        let default = PgPasteStore::new(pool, DEFAULT_TABLE)?;
+
+        // Test that pastes go in the configured table, and only that table.
+        let paste = store
+            .create(NewPaste {
+                content: "hello".to_string(),
+                slug: Some("hello".to_string()),
+                ..Default::default()
+            })
+            .await?;
+        assert_eq!(store.count().await?, 1);
+        assert_eq!(default.count().await?, 0);
+        assert!(default.get(paste.id).await?.is_none());
+
+        // Test that pastes are read back from it.
+        let read = store.get_by_slug("hello").await?.expect("paste exists");
+        assert_eq!(read.content, "hello");
+        assert_eq!(read.views, 1);
+        let meta = store.get_meta(paste.id).await?.expect("paste exists");
+        assert_eq!(meta.size, 5);
+
+        // Test that its constraints are still enforced.
+        let taken = store
+            .create(NewPaste {
+                content: "world".to_string(),
+                slug: Some("hello".to_string()),
+                ..Default::default()
+            })
+            .await;
+        assert!(matches!(taken, Err(AppError::Conflict(_))));
+
+        Ok(())
+    }
+
+    // Needs a database like `test_table`.
+    #[sqlx::test]
+    #[ignore = "needs a Postgres database at DATABASE_URL"]
+    async fn test_exists(pool: PgPool) -> anyhow::Result<()> {
+        let store = PgPasteStore::new(pool, DEFAULT_TABLE)?;
+        let paste = store
+            .create(NewPaste {
+                content: "hello".to_string(),
+                slug: Some("hello".to_string()),
+                ..Default::default()
+            })
+            .await?;
+        let expired = store
+            .create(NewPaste {
+                content: "goodbye".to_string(),
+                slug: Some("goodbye".to_string()),
+                expires_at: Some(Utc::now() - Duration::seconds(1)),
+                ..Default::default()
+            })
+            .await?;
+
+        // Test that only a paste that's there, and still live, exists.
+        assert!(store.exists(paste.id).await?);
+        assert!(!store.exists(expired.id).await?);
+        assert!(!store.exists(Uuid::new_v4()).await?);
+
+        // Test that only a live paste's slug is read.
+        assert_eq!(store.slug(paste.id).await?.as_deref(), Some("hello"));
+        assert_eq!(store.slug(expired.id).await?, None);
+
+        // Test that asking doesn't count as a view.
+        let meta = store.get_meta(paste.id).await?.expect("paste exists");
+        assert_eq!(meta.views, 0);
+
+        Ok(())
+    }
+
+    // Needs a database like `test_table`.
+    #[sqlx::test]
+    #[ignore = "needs a Postgres database at DATABASE_URL"]
+    async fn test_remove_many(pool: PgPool) -> anyhow::Result<()> {
+        let store = PgPasteStore::new(pool, DEFAULT_TABLE)?;
+        let mut ids = Vec::new();
+        for i in 0..3 {
+            let paste = store
+                .create(NewPaste {
+                    content: format!("Paste {}", i),
+                    ..Default::default()
+                })
+                .await?;
+            ids.push(paste.id);
+        }
+        let missing = Uuid::new_v4();
+
+        // Test that only the pastes there were are said to be removed.
+        let mut removed = store.remove_many(&[ids[0], missing, ids[2]]).await?;
+        removed.sort();
+        let mut expected = vec![ids[0], ids[2]];
+        expected.sort();
+        assert_eq!(removed, expected);
+
+        // Test that they're gone, and the rest kept.
+        assert!(!store.exists(ids[0]).await? && !store.exists(ids[2]).await?);
+        assert!(store.exists(ids[1]).await?);
+        assert!(store.remove_many(&[ids[0]]).await?.is_empty());
+
+        Ok(())
+    }
+
+    // Needs a database like `test_table`.
+    #[sqlx::test]
+    #[ignore = "needs a Postgres database at DATABASE_URL"]
+    async fn test_compression(pool: PgPool) -> anyhow::Result<()> {
+        let store = PgPasteStore::new(pool.clone(), DEFAULT_TABLE)?;
+        let content = "All work and no play makes Jack a dull boy.\n".repeat(1000);
+        let paste = store
+            .create(NewPaste {
+                content: content.clone(),
+                ..Default::default()
+            })
+            .await?;
+
+        // Test that the content takes up less room than it would as is.
+        let (stored, size): (i32, i32) = sqlx::query_as(
+            "SELECT octet_length(content), size FROM pastes WHERE id = $1",
+        )
+        .bind(paste.id)
+        .fetch_one(&pool)
+        .await?;
+        assert_eq!(size as usize, content.len());
+        assert!((stored as usize) < content.len());
+
+        // Test that it's read back, appended to, and searched as it was given.
+        assert_eq!(
+            store.peek(paste.id).await?.map(|p| p.content),
+            Some(content)
+        );
+        let appended = store.append(paste.id, "THE END").await?;
+        assert!(appended.is_some_and(|p| p.content.ends_with("boy.\nTHE END")));
+        let page = store.search("the end", 10, None, 10).await?;
+        assert_eq!(page.pastes.len(), 1);
+        let meta = store.get_meta(paste.id).await?.expect("paste exists");
+        assert_eq!(meta.size, 44 * 1000 + 7);
+
+        // Test that changing the metadata leaves the content be.
+        let meta = store.update_meta(paste.id, Some("Jack"), None).await?;
+        assert_eq!(meta.and_then(|meta| meta.title).as_deref(), Some("Jack"));
+        let read = store.peek(paste.id).await?.expect("paste exists");
+        assert!(read.content.ends_with("THE END"));
+
+        Ok(())
+    }
+
+    // Needs a database like `test_table`.
+    #[sqlx::test]
+    #[ignore = "needs a Postgres database at DATABASE_URL"]
+    async fn test_search(pool: PgPool) -> anyhow::Result<()> {
+        let store = PgPasteStore::new(pool, DEFAULT_TABLE)?;
+        for content in ["hello again", "100% sure", "snake_case", "C:\\Users"] {
+            let content = content.to_string();
+            store
+                .create(NewPaste {
+                    content,
+                    ..Default::default()
+                })
+                .await?;
+        }
+        let search = |term| store.search(term, 10, None, 10);
+
+        // Test that terms match ignoring case, and wildcards only themselves.
+        assert_eq!(search("HELLO").await?.pastes.len(), 1);
+        assert_eq!(search("%").await?.pastes.len(), 1);
+        assert_eq!(search("_").await?.pastes.len(), 1);
+        assert_eq!(search("\\").await?.pastes.len(), 1);
+        assert!(search("hello_again").await?.pastes.is_empty());
+
+        // Test that searching carries on from where the last search stopped.
+        let page = store.search("s", 1, None, 10).await?;
+        let (newest, next) = (&page.pastes[0], page.next.expect("more to search"));
+        let page = store.search("s", 10, Some(next), 10).await?;
+        assert_eq!(page.pastes.len(), 2);
+        assert!(page.pastes.iter().all(|meta| meta.id != newest.id));
+        assert_eq!(page.next, None);
+
+        // Test that only the window is searched, then the next one after it.
+        let page = store.search("s", 10, None, 2).await?;
+        assert_eq!(page.pastes.len(), 2);
+        let page = store.search("s", 10, page.next, 2).await?;
+        assert_eq!(page.pastes.len(), 1);
+        let page = store.search("s", 10, page.next, 2).await?;
+        assert!(page.pastes.is_empty());
+        assert_eq!(page.next, None);
+
+        Ok(())
+    }
+
+    // Needs a database like `test_table`.
+    #[sqlx::test]
+    #[ignore = "needs a Postgres database at DATABASE_URL"]
+    async fn test_list_by_owner(pool: PgPool) -> anyhow::Result<()> {
+        let store = PgPasteStore::new(pool, DEFAULT_TABLE)?;
+        let owner = hash_api_key("alice-key");
+        let paste = store
+            .create(NewPaste {
+                content: "hello".to_string(),
+                owner_key_hash: Some(owner.clone()),
+                ..Default::default()
+            })
+            .await?;
+        store
+            .create(NewPaste {
+                content: "hello".to_string(),
+                ..Default::default()
+            })
+            .await?;
+
+        // Test that only the owner's paste is listed, and only for the owner.
+        let pastes = store.list_by_owner(&owner, 10, 0).await?;
+        assert_eq!(pastes.len(), 1);
+        assert_eq!(pastes[0].id, paste.id);
+        let others = hash_api_key("bob-key");
+        assert!(store.list_by_owner(&others, 10, 0).await?.is_empty());
+
+        Ok(())
     }
 }
@@ -0,0 +1,77 @@
+//! Compression of paste content at rest.
+//!
+//! Content is stored zstd compressed when that makes it smaller, which for
+//! text of any size it usually does, and as plain UTF-8 when it doesn't, with
+//! a flag saying which.
+
+use anyhow::Context;
+
+use crate::error::Result;
+
+/// The zstd level content is compressed at, zstd's own default, which is fast
+/// enough to do on every upload.
+const LEVEL: i32 = zstd::DEFAULT_COMPRESSION_LEVEL;
+
+/// Content as stored in the database.
+#[derive(Debug)]
+pub struct Stored {
+    pub bytes: Vec<u8>,
+    /// Whether [Stored::bytes] are zstd compressed, rather than plain UTF-8.
+    pub compressed: bool,
+}
+
+/// Compress `content` for storing, if that makes it any smaller.
+pub fn compress(content: &str) -> Result<Stored> {
+    let compressed = zstd::bulk::compress(content.as_bytes(), LEVEL)
+        .context("Failed to compress paste")?;
+    let stored = match compressed.len() < content.len() {
+        true => Stored {
+            bytes: compressed,
+            compressed: true,
+        },
+        false => Stored {
+            bytes: content.as_bytes().to_vec(),
+            compressed: false,
+        },
+    };
+
+    Ok(stored)
+}
+
+/// Get back the content that was [compressed](compress) into `bytes`.
+pub fn decompress(bytes: &[u8], compressed: bool) -> Result<String> {
+    let bytes = match compressed {
+        true => {
+            zstd::stream::decode_all(bytes).context("Failed to decompress paste")?
+        }
+        false => bytes.to_vec(),
+    };
+
+    Ok(String::from_utf8(bytes).context("Stored paste isn't UTF-8")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        // Test that a large paste is stored smaller, yet read back the same.
+        let content = "fn main() {\n    println!(\"Hello, world!\");\n}\n".repeat(1000);
+        let stored = compress(&content)?;
+        assert!(stored.compressed);
+        assert!(stored.bytes.len() < content.len() / 10);
+        assert_eq!(decompress(&stored.bytes, stored.compressed)?, content);
+
+        // Test that a paste too small to compress is stored as is.
+        let stored = compress("hi")?;
+        assert!(!stored.compressed);
+        assert_eq!(stored.bytes, b"hi");
+        assert_eq!(decompress(&stored.bytes, stored.compressed)?, "hi");
+
+        // Test that corrupt content is an error rather than garbage.
+        assert!(decompress(b"not zstd", true).is_err());
+
+        Ok(())
+    }
+}
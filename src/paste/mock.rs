@@ -0,0 +1,460 @@
+//! An in-memory [PasteStore] for use in tests, sans any infrastructural setup.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicI64, AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, Result},
+    paste::{NewPaste, Paste, PasteMeta, PasteStore, SearchCursor, SearchPage},
+};
+
+// The first mock paste is created at a fixed instant, so tests are
+// deterministic, and each after it a second later, so they're still ordered.
+pub fn created_at() -> DateTime<Utc> { Utc.timestamp_opt(1_692_000_000, 0).unwrap() }
+
+// A row in the mock database.
+pub struct MockEntry {
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub idle_ttl: Option<Duration>,
+    pub last_accessed_at: DateTime<Utc>,
+    pub slug: Option<String>,
+    pub password_hash: Option<String>,
+    pub burn: bool,
+    pub views: i64,
+    pub language: Option<String>,
+    pub content_hash: Option<Vec<u8>>,
+    pub delete_token_hash: Option<Vec<u8>>,
+    pub idempotency_key: Option<String>,
+    pub title: Option<String>,
+    pub public: bool,
+    pub owner_key_hash: Option<Vec<u8>>,
+}
+
+impl MockEntry {
+    fn to_paste(&self, id: Uuid) -> Paste {
+        Paste {
+            created_at: self.created_at,
+            slug: self.slug.clone(),
+            password_hash: self.password_hash.clone(),
+            burn: self.burn,
+            views: self.views,
+            language: self.language.clone(),
+            delete_token_hash: self.delete_token_hash.clone(),
+            title: self.title.clone(),
+            public: self.public,
+            ..Paste::new(id, self.content.clone())
+        }
+    }
+
+    fn view(&mut self, id: Uuid) -> Paste {
+        self.views += 1;
+        self.last_accessed_at = Utc::now();
+        self.to_paste(id)
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+
+    fn is_idle(&self) -> bool {
+        self.idle_ttl
+            .is_some_and(|idle_ttl| self.last_accessed_at + idle_ttl < Utc::now())
+    }
+}
+
+// Create Mock database type.
+#[derive(Default)]
+pub struct MockPasteStore {
+    pub entries: Mutex<HashMap<Uuid, MockEntry>>,
+    // How many pastes have been created, for spacing out their creation times.
+    pub created: AtomicI64,
+    // How many of the next calls fail, as though the database were unreachable.
+    pub failures: AtomicU32,
+    // How many of the next calls lose the connection after being applied, as
+    // though the response never made it back.
+    pub lost: AtomicU32,
+}
+
+// Make convenience methods for it.
+impl MockPasteStore {
+    pub fn arc() -> Arc<Self> { Arc::new(Self::default()) }
+
+    // Fail if there are still failures left to fail with.
+    fn fail(&self) -> Result<()> {
+        let failing = self
+            .failures
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok();
+        match failing {
+            true => Err(sqlx::Error::PoolTimedOut.into()),
+            false => Ok(()),
+        }
+    }
+
+    // Fail if there are still responses left to lose, once the call's applied.
+    fn lose(&self) -> Result<()> {
+        let losing = self
+            .lost
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok();
+        match losing {
+            true => {
+                Err(sqlx::Error::Io(std::io::ErrorKind::ConnectionReset.into()).into())
+            }
+            false => Ok(()),
+        }
+    }
+}
+
+// Implement our database trait on it.
+#[async_trait]
+impl PasteStore for MockPasteStore {
+    async fn get(&self, id: Uuid) -> Result<Option<Paste>> {
+        self.fail()?;
+        let mut lock = self.entries.lock().await;
+        let paste = lock
+            .get_mut(&id)
+            .filter(|e| !e.is_expired())
+            .map(|e| e.view(id));
+        Ok(paste)
+    }
+
+    async fn peek(&self, id: Uuid) -> Result<Option<Paste>> {
+        self.fail()?;
+        let lock = self.entries.lock().await;
+        let paste = lock
+            .get(&id)
+            .filter(|e| !e.is_expired())
+            .map(|e| e.to_paste(id));
+        Ok(paste)
+    }
+
+    async fn get_meta(&self, id: Uuid) -> Result<Option<PasteMeta>> {
+        self.fail()?;
+        let lock = self.entries.lock().await;
+        let meta = lock
+            .get(&id)
+            .filter(|e| !e.is_expired())
+            .map(|e| PasteMeta::from(&e.to_paste(id)));
+        Ok(meta)
+    }
+
+    async fn exists(&self, id: Uuid) -> Result<bool> {
+        self.fail()?;
+        let lock = self.entries.lock().await;
+        Ok(lock.get(&id).is_some_and(|e| !e.is_expired()))
+    }
+
+    async fn slug(&self, id: Uuid) -> Result<Option<String>> {
+        self.fail()?;
+        let lock = self.entries.lock().await;
+        let slug = lock
+            .get(&id)
+            .filter(|e| !e.is_expired())
+            .and_then(|e| e.slug.clone());
+        Ok(slug)
+    }
+
+    async fn get_by_slug(&self, slug: &str) -> Result<Option<Paste>> {
+        self.fail()?;
+        let mut lock = self.entries.lock().await;
+        let paste = lock
+            .iter_mut()
+            .find(|(_, e)| e.slug.as_deref() == Some(slug) && !e.is_expired())
+            .map(|(id, e)| e.view(*id));
+        Ok(paste)
+    }
+
+    async fn get_many(&self, ids: &[Uuid]) -> Result<Vec<Paste>> {
+        self.fail()?;
+        let mut lock = self.entries.lock().await;
+        let pastes = lock
+            .iter_mut()
+            .filter(|(id, e)| {
+                ids.contains(id)
+                    && e.password_hash.is_none()
+                    && !e.burn
+                    && !e.is_expired()
+            })
+            .map(|(id, e)| e.view(*id))
+            .collect();
+        Ok(pastes)
+    }
+
+    async fn create(&self, paste: NewPaste) -> Result<Paste> {
+        self.fail()?;
+        let id = Uuid::new_v4();
+        let content_hash = paste.content_hash();
+        let mut lock = self.entries.lock().await;
+        if let Some((id, e)) = lock
+            .iter()
+            .find(|(_, e)| content_hash.is_some() && e.content_hash == content_hash)
+        {
+            return Ok(e.to_paste(*id));
+        }
+        if let Some((id, e)) = lock.iter().find(|(_, e)| {
+            paste.idempotency_key.is_some()
+                && e.idempotency_key == paste.idempotency_key
+        }) {
+            return Ok(e.to_paste(*id));
+        }
+        if paste.slug.is_some() && lock.values().any(|e| e.slug == paste.slug) {
+            let message = "A paste with that name already exists".to_string();
+            return Err(AppError::Conflict(message));
+        }
+        let created = self.created.fetch_add(1, Ordering::Relaxed);
+        let entry = MockEntry {
+            content: paste.content,
+            created_at: created_at() + Duration::seconds(created),
+            expires_at: paste.expires_at,
+            idle_ttl: paste.idle_ttl,
+            last_accessed_at: Utc::now(),
+            slug: paste.slug,
+            password_hash: paste.password_hash,
+            burn: paste.burn,
+            views: 0,
+            language: paste.language,
+            content_hash,
+            delete_token_hash: paste.delete_token_hash,
+            idempotency_key: paste.idempotency_key,
+            title: paste.title,
+            public: paste.public,
+            owner_key_hash: paste.owner_key_hash,
+        };
+        let paste = entry.to_paste(id);
+        lock.insert(id, entry);
+        Ok(paste)
+    }
+
+    async fn update(&self, id: Uuid, content: String) -> Result<Option<Paste>> {
+        self.fail()?;
+        let mut lock = self.entries.lock().await;
+        let paste = lock.get_mut(&id).filter(|e| !e.is_expired()).map(|e| {
+            e.content = content;
+            e.content_hash = None;
+            e.to_paste(id)
+        });
+        Ok(paste)
+    }
+
+    async fn update_meta(
+        &self,
+        id: Uuid,
+        title: Option<&str>,
+        language: Option<&str>,
+    ) -> Result<Option<PasteMeta>> {
+        self.fail()?;
+        let mut lock = self.entries.lock().await;
+        let meta = lock.get_mut(&id).filter(|e| !e.is_expired()).map(|e| {
+            if let Some(title) = title {
+                e.title = Some(title.to_string());
+            }
+            if let Some(language) = language {
+                e.language = Some(language.to_string());
+            }
+            PasteMeta::from(&e.to_paste(id))
+        });
+        Ok(meta)
+    }
+
+    async fn append(&self, id: Uuid, content: &str) -> Result<Option<Paste>> {
+        self.fail()?;
+        let mut lock = self.entries.lock().await;
+        let paste = lock.get_mut(&id).filter(|e| !e.is_expired()).map(|e| {
+            e.content.push_str(content);
+            e.content_hash = None;
+            e.to_paste(id)
+        });
+        Ok(paste)
+    }
+
+    async fn get_and_burn(&self, id: Uuid) -> Result<Option<Paste>> {
+        self.fail()?;
+        let mut lock = self.entries.lock().await;
+        if !lock.get(&id).is_some_and(|e| e.burn && !e.is_expired()) {
+            return Ok(None);
+        }
+        let paste = lock.remove(&id).map(|e| e.to_paste(id));
+        self.lose()?;
+        Ok(paste)
+    }
+
+    async fn remove(&self, id: Uuid) -> Result<Option<Paste>> {
+        self.fail()?;
+        let mut lock = self.entries.lock().await;
+        let paste = lock.remove(&id).map(|e| e.to_paste(id));
+        Ok(paste)
+    }
+
+    async fn remove_many(&self, ids: &[Uuid]) -> Result<Vec<Uuid>> {
+        self.fail()?;
+        let mut lock = self.entries.lock().await;
+        let removed = ids
+            .iter()
+            .filter(|id| lock.remove(id).is_some())
+            .copied()
+            .collect();
+        Ok(removed)
+    }
+
+    async fn list(
+        &self,
+        limit: u32,
+        offset: u32,
+        public_only: bool,
+    ) -> Result<Vec<PasteMeta>> {
+        self.fail()?;
+        let lock = self.entries.lock().await;
+        let mut pastes: Vec<_> = lock
+            .iter()
+            .filter(|(_, e)| (e.public || !public_only) && !e.is_expired())
+            .map(|(id, e)| PasteMeta::from(&e.to_paste(*id)))
+            .collect();
+        pastes.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(a.id.cmp(&b.id)));
+        let pastes = pastes
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect();
+        Ok(pastes)
+    }
+
+    async fn list_by_owner(
+        &self,
+        owner_key_hash: &[u8],
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<PasteMeta>> {
+        self.fail()?;
+        let lock = self.entries.lock().await;
+        let mut pastes: Vec<_> = lock
+            .iter()
+            .filter(|(_, e)| {
+                e.owner_key_hash.as_deref() == Some(owner_key_hash) && !e.is_expired()
+            })
+            .map(|(id, e)| PasteMeta::from(&e.to_paste(*id)))
+            .collect();
+        pastes.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(a.id.cmp(&b.id)));
+        let pastes = pastes
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect();
+        Ok(pastes)
+    }
+
+    async fn search(
+        &self,
+        term: &str,
+        limit: u32,
+        after: Option<SearchCursor>,
+        window: u32,
+    ) -> Result<SearchPage> {
+        self.fail()?;
+        let term = term.to_lowercase();
+        let lock = self.entries.lock().await;
+        let mut pastes: Vec<_> = lock
+            .iter()
+            .filter(|(_, e)| !e.is_expired())
+            .map(|(id, e)| e.to_paste(*id))
+            .collect();
+        pastes.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(a.id.cmp(&b.id)));
+        let after = |paste: &&Paste| match after {
+            Some(after) => {
+                paste.created_at < after.created_at
+                    || (paste.created_at == after.created_at && paste.id > after.id)
+            }
+            None => true,
+        };
+        let mut found = Vec::new();
+        let mut searched = 0;
+        let mut last = None;
+        for paste in pastes.iter().filter(after).take(window as usize) {
+            if found.len() == limit as usize {
+                break;
+            }
+            searched += 1;
+            last = Some(SearchCursor {
+                created_at: paste.created_at,
+                id: paste.id,
+            });
+            if paste.content.to_lowercase().contains(&term) {
+                found.push(PasteMeta::from(paste));
+            }
+        }
+        let next = match found.len() == limit as usize || searched == window {
+            true => last,
+            false => None,
+        };
+        Ok(SearchPage {
+            pastes: found,
+            next,
+        })
+    }
+
+    async fn random(&self) -> Result<Option<Uuid>> {
+        self.fail()?;
+        // Arbitrary is random enough for tests.
+        let lock = self.entries.lock().await;
+        let id = lock
+            .iter()
+            .find(|(_, e)| {
+                e.public && e.password_hash.is_none() && !e.burn && !e.is_expired()
+            })
+            .map(|(id, _)| *id);
+        Ok(id)
+    }
+
+    async fn count(&self) -> Result<u64> {
+        self.fail()?;
+        let lock = self.entries.lock().await;
+        Ok(lock.len() as u64)
+    }
+
+    async fn remove_expired(&self) -> Result<u64> {
+        self.fail()?;
+        let mut lock = self.entries.lock().await;
+        let before = lock.len();
+        lock.retain(|_, e| !e.is_expired() && !e.is_idle());
+        Ok((before - lock.len()) as u64)
+    }
+
+    async fn clear(&self) -> Result<u64> {
+        self.fail()?;
+        let mut lock = self.entries.lock().await;
+        let removed = lock.len();
+        lock.clear();
+        Ok(removed as u64)
+    }
+}
+
+impl Paste {
+    pub fn new(id: Uuid, content: String) -> Self {
+        Self {
+            id,
+            content,
+            created_at: created_at(),
+            slug: None,
+            password_hash: None,
+            burn: false,
+            views: 0,
+            language: None,
+            delete_token_hash: None,
+            title: None,
+            public: false,
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! A [PasteStore] that retries another store's operations when the database
+//! fails in a way that's likely to pass, like a dropped connection.
+
+use std::{future::Future, time::Duration};
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, Result},
+    paste::{NewPaste, Paste, PasteMeta, PasteStore, SearchCursor, SearchPage},
+};
+
+/// The default most times an operation is attempted.
+pub const DEFAULT_ATTEMPTS: u32 = 3;
+
+/// The default wait before the first retry, doubled before each one after.
+pub const DEFAULT_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Whether `err` is likely to pass if the operation is attempted again.
+///
+/// That's failing to get a connection or losing it, Postgres reporting a
+/// connection exception (class `08`), a serialization failure or deadlock
+/// (whose transaction is rolled back), or the server shutting down.
+fn transient(err: &AppError) -> bool {
+    match err {
+        AppError::Database(sqlx::Error::PoolTimedOut | sqlx::Error::Io(_)) => true,
+        AppError::Database(sqlx::Error::Database(db)) => {
+            db.code().is_some_and(|code| {
+                code.starts_with("08") || matches!(&*code, "40001" | "40P01" | "57P01")
+            })
+        }
+        _ => false,
+    }
+}
+
+/// Whether `err` means the operation never reached the database, so that
+/// attempting it again can't apply it twice.
+fn unsent(err: &AppError) -> bool {
+    matches!(err, AppError::Database(sqlx::Error::PoolTimedOut))
+}
+
+/// Wraps a store, retrying its operations that fail [transiently](transient),
+/// waiting longer before each retry.
+///
+/// Operations that would be applied twice if they did make it to the database
+/// before the connection was lost, creating, appending, and burning, are only
+/// retried when they [never got there](unsent). A burn applied twice would
+/// find the paste already gone, losing it for good.
+pub struct RetryingStore<S> {
+    inner: S,
+    attempts: u32,
+    backoff: Duration,
+}
+
+impl<S: PasteStore> RetryingStore<S> {
+    /// Retry `inner`'s operations with the defaults.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            attempts: DEFAULT_ATTEMPTS,
+            backoff: DEFAULT_BACKOFF,
+        }
+    }
+
+    /// Wait `backoff` before the first retry, rather than [DEFAULT_BACKOFF].
+    #[cfg(test)]
+    pub fn backoff(self, backoff: Duration) -> Self { Self { backoff, ..self } }
+
+    /// Attempt `op` until it succeeds, fails in a way `retryable` says won't
+    /// pass, or has been attempted as many times as allowed.
+    async fn retry<T, F, Fut>(
+        &self,
+        retryable: fn(&AppError) -> bool,
+        mut op: F,
+    ) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut backoff = self.backoff;
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Err(err) if attempt < self.attempts && retryable(&err) => {
+                    tracing::warn!(
+                        "database operation failed, retrying in {:?} (attempt {} of {}): {}",
+                        backoff,
+                        attempt,
+                        self.attempts,
+                        err
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<S: PasteStore> PasteStore for RetryingStore<S> {
+    async fn get(&self, id: Uuid) -> Result<Option<Paste>> {
+        self.retry(transient, || self.inner.get(id)).await
+    }
+
+    async fn peek(&self, id: Uuid) -> Result<Option<Paste>> {
+        self.retry(transient, || self.inner.peek(id)).await
+    }
+
+    async fn get_meta(&self, id: Uuid) -> Result<Option<PasteMeta>> {
+        self.retry(transient, || self.inner.get_meta(id)).await
+    }
+
+    async fn exists(&self, id: Uuid) -> Result<bool> {
+        self.retry(transient, || self.inner.exists(id)).await
+    }
+
+    async fn slug(&self, id: Uuid) -> Result<Option<String>> {
+        self.retry(transient, || self.inner.slug(id)).await
+    }
+
+    async fn get_by_slug(&self, slug: &str) -> Result<Option<Paste>> {
+        self.retry(transient, || self.inner.get_by_slug(slug)).await
+    }
+
+    async fn get_many(&self, ids: &[Uuid]) -> Result<Vec<Paste>> {
+        self.retry(transient, || self.inner.get_many(ids)).await
+    }
+
+    async fn create(&self, paste: NewPaste) -> Result<Paste> {
+        self.retry(unsent, || self.inner.create(paste.clone()))
+            .await
+    }
+
+    async fn update(&self, id: Uuid, content: String) -> Result<Option<Paste>> {
+        self.retry(transient, || self.inner.update(id, content.clone()))
+            .await
+    }
+
+    async fn update_meta(
+        &self,
+        id: Uuid,
+        title: Option<&str>,
+        language: Option<&str>,
+    ) -> Result<Option<PasteMeta>> {
+        self.retry(transient, || self.inner.update_meta(id, title, language))
+            .await
+    }
+
+    async fn append(&self, id: Uuid, content: &str) -> Result<Option<Paste>> {
+        self.retry(unsent, || self.inner.append(id, content)).await
+    }
+
+    async fn get_and_burn(&self, id: Uuid) -> Result<Option<Paste>> {
+        self.retry(unsent, || self.inner.get_and_burn(id)).await
+    }
+
+    async fn remove(&self, id: Uuid) -> Result<Option<Paste>> {
+        self.retry(transient, || self.inner.remove(id)).await
+    }
+
+    async fn remove_many(&self, ids: &[Uuid]) -> Result<Vec<Uuid>> {
+        self.retry(transient, || self.inner.remove_many(ids)).await
+    }
+
+    async fn list(
+        &self,
+        limit: u32,
+        offset: u32,
+        public_only: bool,
+    ) -> Result<Vec<PasteMeta>> {
+        self.retry(transient, || self.inner.list(limit, offset, public_only))
+            .await
+    }
+
+    async fn list_by_owner(
+        &self,
+        owner_key_hash: &[u8],
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<PasteMeta>> {
+        self.retry(transient, || {
+            self.inner.list_by_owner(owner_key_hash, limit, offset)
+        })
+        .await
+    }
+
+    async fn search(
+        &self,
+        term: &str,
+        limit: u32,
+        after: Option<SearchCursor>,
+        window: u32,
+    ) -> Result<SearchPage> {
+        self.retry(transient, || self.inner.search(term, limit, after, window))
+            .await
+    }
+
+    async fn random(&self) -> Result<Option<Uuid>> {
+        self.retry(transient, || self.inner.random()).await
+    }
+
+    async fn count(&self) -> Result<u64> {
+        self.retry(transient, || self.inner.count()).await
+    }
+
+    async fn remove_expired(&self) -> Result<u64> {
+        self.retry(transient, || self.inner.remove_expired()).await
+    }
+
+    async fn clear(&self) -> Result<u64> {
+        self.retry(transient, || self.inner.clear()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::paste::mock::MockPasteStore;
+
+    // A store that retries a mock without waiting in between.
+    fn store() -> RetryingStore<MockPasteStore> {
+        RetryingStore::new(MockPasteStore::default()).backoff(Duration::ZERO)
+    }
+
+    #[tokio::test]
+    async fn test_retry() -> Result<()> {
+        let store = store();
+        let paste = store
+            .create(NewPaste {
+                content: "hello".to_string(),
+                ..Default::default()
+            })
+            .await?;
+
+        // Test that an operation failing twice still succeeds the third time.
+        store.inner.failures.store(2, Ordering::Relaxed);
+        let read = store.get(paste.id).await?.expect("paste exists");
+        assert_eq!(read.content, "hello");
+        assert_eq!(store.inner.failures.load(Ordering::Relaxed), 0);
+
+        // Test that one failing every time gives up with the error.
+        store
+            .inner
+            .failures
+            .store(DEFAULT_ATTEMPTS, Ordering::Relaxed);
+        let err = store.get(paste.id).await.expect_err("attempts run out");
+        assert!(transient(&err));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_burn_not_retried() -> Result<()> {
+        let store = store();
+        let paste = store
+            .create(NewPaste {
+                content: "hello".to_string(),
+                burn: true,
+                ..Default::default()
+            })
+            .await?;
+
+        // Test that a burn whose response was lost isn't attempted again, which
+        // would find nothing, but fails with the error instead.
+        store.inner.lost.store(1, Ordering::Relaxed);
+        let err = store.get_and_burn(paste.id).await.expect_err("lost");
+        assert!(transient(&err) && !unsent(&err));
+        assert_eq!(store.inner.lost.load(Ordering::Relaxed), 0);
+
+        // Test that it's retried if it never got to the database.
+        let paste = store
+            .create(NewPaste {
+                content: "again".to_string(),
+                burn: true,
+                ..Default::default()
+            })
+            .await?;
+        store.inner.failures.store(2, Ordering::Relaxed);
+        let read = store.get_and_burn(paste.id).await?.expect("paste exists");
+        assert_eq!(read.content, "again");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_transient() {
+        // Test that losing the database is transient, and only as far as it's
+        // safe to retry.
+        let timed_out = AppError::from(sqlx::Error::PoolTimedOut);
+        assert!(transient(&timed_out) && unsent(&timed_out));
+        let reset =
+            AppError::from(sqlx::Error::Io(std::io::ErrorKind::ConnectionReset.into()));
+        assert!(transient(&reset) && !unsent(&reset));
+
+        // Test that mistakes that'll happen every time aren't.
+        assert!(!transient(&AppError::from(sqlx::Error::RowNotFound)));
+        assert!(!transient(&AppError::NotFound));
+    }
+}
@@ -0,0 +1,59 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::error::{AppError, Result};
+
+/// Limits how many requests each client may make in a window of time.
+///
+/// Each client's window starts with its first request, and once it's over the
+/// client can make `limit` more requests. Clients are identified by IP.
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    clients: Mutex<HashMap<IpAddr, Window>>,
+}
+
+/// Requests made by a client in its current window.
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+impl RateLimiter {
+    /// Allow each client `limit` requests every `window`.
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a request from `ip`.
+    ///
+    /// Fails with [AppError::TooManyRequests] if the client has already used up
+    /// its window, saying how long until the next one.
+    pub fn check(&self, ip: IpAddr) -> Result<()> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+
+        // Forget clients whose window is over, so the map doesn't grow forever.
+        clients.retain(|_, window| now.duration_since(window.start) < self.window);
+
+        let window = clients.entry(ip).or_insert(Window {
+            start: now,
+            count: 0,
+        });
+        if window.count >= self.limit {
+            let retry_after = self.window - now.duration_since(window.start);
+            return Err(AppError::TooManyRequests(retry_after));
+        }
+        window.count += 1;
+
+        Ok(())
+    }
+}
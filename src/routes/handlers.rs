@@ -0,0 +1,1780 @@
+use std::{
+    collections::HashSet, convert::Infallible, io::Write, net::IpAddr, time::Instant,
+};
+
+use axum::{
+    body::{self, Body},
+    extract::{Host, Path, Query, State},
+    http::{
+        header::{
+            ACCEPT, AUTHORIZATION, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH,
+            CONTENT_SECURITY_POLICY, CONTENT_TYPE, LOCATION, VARY,
+            X_CONTENT_TYPE_OPTIONS,
+        },
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{Html, IntoResponse, IntoResponseParts, Response, ResponseParts},
+    Extension, Json,
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hyper::body::Sender;
+use image::{DynamicImage, ImageOutputFormat, Luma};
+use qrcode::QrCode;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use uuid::Uuid;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use super::{usage, BasePath};
+use crate::{
+    app::App,
+    cache::{Format, HighlightKey},
+    diff,
+    error::{AppError, Result},
+    extract::{
+        Admin, ApiKey, ClientIp, CreatePasteRequest, PasteContent, PasteId, UploadBody,
+    },
+    highlight::{self, LineRanges, UnknownLangPolicy},
+    import, markdown, password,
+    paste::{self, NewPaste, Paste, PasteMeta},
+    spool::Spool,
+    telemetry, util,
+};
+
+/// Response header naming the language a paste was detected to be.
+pub const DETECTED_LANGUAGE: &str = "x-detected-language";
+
+/// Response header with the hex SHA-256 digest of a paste's content.
+pub const CONTENT_SHA256: &str = "x-content-sha256";
+
+/// Response header with the token for deleting a newly uploaded paste.
+pub const DELETE_TOKEN: &str = "x-delete-token";
+
+/// Response header with where a search stopped, to carry on searching from.
+pub const NEXT_CURSOR: &str = "x-next-cursor";
+
+/// Response header with how long parts of handling the request took.
+pub const SERVER_TIMING: &str = "server-timing";
+
+/// Request header with a key identifying an upload across retries.
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// Request and response header with an id correlating a request with its logs.
+pub const REQUEST_ID: &str = "x-request-id";
+
+/// The longest idempotency key accepted, which is plenty for a UUID or two.
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// Respond with the landing page, if the app has one (see [App::landing_page]),
+/// or else the usage string for our web app.
+pub async fn index(State(state): State<App>) -> Response {
+    match state.landing_page {
+        Some(html) => Html(html).into_response(),
+        None => usage::usage().into_response(),
+    }
+}
+
+/// Query parameters accepted by routes that respond with a paste's content.
+#[derive(Debug, Deserialize)]
+pub struct AccessParams {
+    /// The password for reading a password protected paste.
+    pub password: Option<String>,
+}
+
+/// Read a paste that was fetched in order to respond with its content.
+///
+/// Fails if the paste is password protected and `access` doesn't unlock it.
+/// Burn after reading pastes are burned, giving `None` if somebody else got to
+/// them first.
+async fn read(
+    state: &App,
+    paste: Option<Paste>,
+    access: &AccessParams,
+) -> Result<Option<Paste>> {
+    read_checked(state, paste, access, |_| Ok(())).await
+}
+
+/// Like [read], but first failing with whatever `check` fails with for the
+/// paste, so that a request that can't be answered doesn't burn it.
+async fn read_checked(
+    state: &App,
+    paste: Option<Paste>,
+    access: &AccessParams,
+    check: impl FnOnce(&Paste) -> Result<()>,
+) -> Result<Option<Paste>> {
+    let paste = match paste {
+        Some(p) => {
+            check_unlocked(&p, access)?;
+            check(&p)?;
+            match p.burn {
+                true => state.pastes.get_and_burn(p.id).await?,
+                false => Some(p),
+            }
+        }
+        None => None,
+    };
+
+    if paste.is_some() {
+        metrics::increment_counter!(telemetry::RETRIEVALS);
+    }
+
+    Ok(paste)
+}
+
+/// Fail if `paste` is password protected and `access` doesn't unlock it.
+fn check_unlocked(paste: &Paste, access: &AccessParams) -> Result<()> {
+    match paste.unlocked_by(access.password.as_deref()) {
+        true => Ok(()),
+        false => Err(AppError::Unauthorized(
+            "This paste is password protected".to_string(),
+        )),
+    }
+}
+
+/// How [retrieve] should highlight a paste.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightMode {
+    /// Detect the language from the paste's first line.
+    Auto,
+}
+
+/// Query parameters accepted by [retrieve].
+#[derive(Debug, Deserialize)]
+pub struct RetrieveParams {
+    /// Highlight the paste, rather than returning it as is.
+    pub highlight: Option<HighlightMode>,
+}
+
+/// Retrieve a paste by its UUID.
+///
+/// Extracts the UUID from the query parameters, and a database connection from
+/// the applications state.
+///
+/// Clients that accept `application/json` get the whole paste as JSON, rather
+/// than just its content.
+pub async fn retrieve(
+    PasteId(id): PasteId,
+    State(state): State<App>,
+    Query(params): Query<RetrieveParams>,
+    Query(highlight_params): Query<HighlightParams>,
+    Query(access): Query<AccessParams>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    // Checked before reading, which may burn the paste.
+    let theme = state
+        .theme_name(highlight_params.theme.as_deref())?
+        .to_string();
+    let paste = state.pastes.get(id).await?;
+    let paste = read(&state, paste, &access).await?;
+    let paste = paste.ok_or(AppError::NotFound)?;
+
+    // The response depends on the Accept header, so caches must key on it too.
+    let vary = [(VARY, "accept")];
+    // Always of the content as stored, however it's responded with.
+    let digest = [(
+        CONTENT_SHA256,
+        format!("{:x}", Sha256::digest(&paste.content)),
+    )];
+    if accepts(&headers, "application/json") {
+        return Ok((vary, digest, Json(paste)).into_response());
+    }
+
+    let syntax = paste
+        .language
+        .as_deref()
+        .map(|lang| highlight::find_syntax(&state.syntax_set, lang));
+    let highlightable =
+        !highlight::too_many_lines(&paste.content, state.max_highlight_lines);
+    let response = match (syntax, params.highlight) {
+        // The language given at upload wins over guessing.
+        (Some(Some(syntax)), _) if highlightable => {
+            let content = paste.content.clone();
+            let highlighted =
+                highlight_in_background(&state, syntax, move |app, syntax| {
+                    let theme = app.theme(Some(&theme))?;
+                    highlight::highlight(
+                        &content,
+                        syntax,
+                        &app.syntax_set,
+                        theme,
+                        None,
+                        app.highlight_errors,
+                    )
+                });
+            highlighted.await?.unwrap_or(paste.content).into_response()
+        }
+        (None, Some(HighlightMode::Auto)) if highlightable => {
+            let content = paste.content.clone();
+            let app = state.clone();
+            let highlighted =
+                highlight::with_timeout(state.highlight_timeout, move || {
+                    let theme = app.theme(Some(&theme))?;
+                    highlight::highlight_auto(
+                        &content,
+                        &app.syntax_set,
+                        theme,
+                        app.highlight_errors,
+                    )
+                });
+            match highlighted.await?.flatten() {
+                // Let the client know what we decided the language is.
+                Some((language, highlighted)) => {
+                    ([(DETECTED_LANGUAGE, language)], highlighted).into_response()
+                }
+                None => paste.content.into_response(),
+            }
+        }
+        _ => paste.content.into_response(),
+    };
+
+    Ok((vary, digest, response).into_response())
+}
+
+/// Respond with the headers [retrieve] would for a paste, without its content,
+/// so clients can see how big it is before downloading it.
+///
+/// Uses [PasteStore::peek](crate::paste::PasteStore::peek), so it isn't a read
+/// of the paste, but still needs the password of a protected paste.
+pub async fn retrieve_headers(
+    PasteId(id): PasteId,
+    State(state): State<App>,
+    Query(access): Query<AccessParams>,
+) -> Result<Response> {
+    let paste = state.pastes.peek(id).await?.ok_or(AppError::NotFound)?;
+    if !paste.unlocked_by(access.password.as_deref()) {
+        return Err(AppError::Unauthorized(
+            "This paste is password protected".to_string(),
+        ));
+    }
+
+    let headers = [
+        (CONTENT_TYPE, "text/plain; charset=utf-8".to_string()),
+        (CONTENT_LENGTH, paste.content.len().to_string()),
+        (VARY, "accept".to_string()),
+    ];
+
+    Ok(headers.into_response())
+}
+
+/// Guess the most specific content type a paste's content can safely be served
+/// as, by the language it was uploaded as, or failing that by sniffing it.
+///
+/// Anything a browser might run, like HTML, SVG, or JavaScript, is plain text,
+/// since pastes are untrusted and served from our origin.
+fn guess_content_type(paste: &Paste) -> &'static str {
+    let language = paste.language.as_deref().map(str::to_lowercase);
+    match language.as_deref() {
+        Some("json") => "application/json",
+        Some("md" | "markdown") => "text/markdown; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some(_) => "text/plain; charset=utf-8",
+        // Only objects and arrays, a lone number or string is more likely text.
+        None if paste.content.trim_start().starts_with(['{', '['])
+            && serde_json::from_str::<serde_json::Value>(&paste.content).is_ok() =>
+        {
+            "application/json"
+        }
+        None => "text/plain; charset=utf-8",
+    }
+}
+
+/// Respond with a paste's content as is, with a filename.
+///
+/// `disposition` is whether browsers should show the content `inline`, or save
+/// it as an `attachment`. The filename uses the extension the paste was
+/// uploaded as, or `txt` if it wasn't given one. The content type is guessed
+/// by [guess_content_type], and browsers are told not to second guess it.
+fn raw(paste: Paste, disposition: &str) -> Response {
+    let extension = paste.language.as_deref().unwrap_or("txt");
+    let disposition =
+        format!("{}; filename=\"{}.{}\"", disposition, paste.id, extension);
+    let headers = [
+        (CONTENT_TYPE, guess_content_type(&paste).to_string()),
+        (CONTENT_DISPOSITION, disposition),
+        (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+    ];
+
+    (headers, paste.content).into_response()
+}
+
+/// Retrieve a paste's content as is, for viewing or saving.
+pub async fn retrieve_raw(
+    PasteId(id): PasteId,
+    State(state): State<App>,
+    Query(access): Query<AccessParams>,
+) -> Result<Response> {
+    let paste = state.pastes.get(id).await?;
+    let paste = read(&state, paste, &access).await?;
+    let paste = paste.ok_or(AppError::NotFound)?;
+
+    Ok(raw(paste, "inline"))
+}
+
+/// Retrieve a paste's content as is, as an attachment that browsers save
+/// rather than show.
+pub async fn download(
+    PasteId(id): PasteId,
+    State(state): State<App>,
+    Query(access): Query<AccessParams>,
+) -> Result<Response> {
+    let paste = state.pastes.get(id).await?;
+    let paste = read(&state, paste, &access).await?;
+    let paste = paste.ok_or(AppError::NotFound)?;
+
+    Ok(raw(paste, "attachment"))
+}
+
+/// Retrieve a paste by the human-readable name it was uploaded with.
+pub async fn retrieve_by_slug(
+    Path(slug): Path<String>,
+    State(state): State<App>,
+    Query(access): Query<AccessParams>,
+) -> Result<(StatusCode, String)> {
+    let paste = state.pastes.get_by_slug(&slug).await?;
+    let paste = read(&state, paste, &access).await?;
+
+    let paste = paste.ok_or(AppError::NotFound)?;
+
+    Ok((StatusCode::OK, paste.content))
+}
+
+/// Redirect to a paste picked at random, for discovering pastes.
+pub async fn random(
+    State(state): State<App>,
+    Extension(BasePath(base)): Extension<BasePath>,
+) -> Result<Response> {
+    let id = state.pastes.random().await?.ok_or(AppError::NotFound)?;
+
+    // Every visit should land somewhere new, so the redirect mustn't be cached.
+    let headers = [
+        (LOCATION, format!("{}/{}", base, id)),
+        (CACHE_CONTROL, "no-store".to_string()),
+    ];
+
+    Ok((StatusCode::FOUND, headers).into_response())
+}
+
+/// Retrieve metadata about a paste as JSON.
+///
+/// A password protected paste's title may give away what's in it, so its
+/// metadata needs the password too. Checking it means loading the whole paste,
+/// so is only done for those, but that's still not reading it, so doesn't count
+/// as a view or burn it.
+pub async fn retrieve_meta(
+    PasteId(id): PasteId,
+    State(state): State<App>,
+    Query(access): Query<AccessParams>,
+) -> Result<Response> {
+    let meta = state.pastes.get_meta(id).await?;
+
+    let meta = meta.ok_or(AppError::NotFound)?;
+    if meta.protected {
+        let paste = state.pastes.peek(id).await?;
+        let paste = paste.ok_or(AppError::NotFound)?;
+        if !paste.unlocked_by(access.password.as_deref()) {
+            return Err(AppError::Unauthorized(
+                "This paste is password protected".to_string(),
+            ));
+        }
+    }
+
+    Ok(Json(meta).into_response())
+}
+
+/// Retrieve a paste, rendered from Markdown as HTML.
+pub async fn retrieve_markdown(
+    PasteId(id): PasteId,
+    State(state): State<App>,
+    Query(access): Query<AccessParams>,
+) -> Result<Response> {
+    let paste = state.pastes.get(id).await?;
+    let paste = read(&state, paste, &access).await?;
+
+    let paste = paste.ok_or(AppError::NotFound)?;
+
+    let html = markdown::render_markdown(&paste.content, &state.markdown_sanitizer);
+
+    Ok(Html(html).into_response())
+}
+
+/// The rest of the path of [diff], the ids of the pastes to compare.
+#[derive(Debug, Deserialize)]
+pub struct DiffPath {
+    pub id1: String,
+    pub id2: String,
+}
+
+/// Compare two pastes, responding with the unified diff turning the first into
+/// the second.
+///
+/// Browsers (anything that accepts `text/html`) are sent HTML with the lines
+/// added and removed colored in, everyone else gets plain text. Both pastes are
+/// read, so protected ones need `access` to unlock them.
+pub async fn diff(
+    Path(DiffPath { id1, id2 }): Path<DiffPath>,
+    State(state): State<App>,
+    Query(access): Query<AccessParams>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let (PasteId(id1), PasteId(id2)) = (PasteId::parse(&id1)?, PasteId::parse(&id2)?);
+    // Both must exist and be unlocked before either is read, which may burn it.
+    // A paste compared with itself is only read once, as it'd be gone the second
+    // time if it's burned.
+    let a = state.pastes.get(id1).await?.ok_or(AppError::NotFound)?;
+    let b = match id1 == id2 {
+        true => None,
+        false => Some(state.pastes.get(id2).await?.ok_or(AppError::NotFound)?),
+    };
+    check_unlocked(&a, &access)?;
+    if let Some(b) = &b {
+        check_unlocked(b, &access)?;
+    }
+    let a = read(&state, Some(a), &access).await?;
+    let a = a.ok_or(AppError::NotFound)?;
+    let b = match b {
+        Some(b) => {
+            let b = read(&state, Some(b), &access).await?;
+            Some(b.ok_or(AppError::NotFound)?)
+        }
+        None => None,
+    };
+    let (a, b) = (&a.content, b.as_ref().map_or(&a.content, |b| &b.content));
+
+    // The response depends on the Accept header, so caches must key on it too.
+    let vary = [(VARY, "accept")];
+    if accepts(&headers, "text/html") {
+        let html = diff::diff_html(a, b);
+        return Ok((vary, Html(html)).into_response());
+    }
+
+    Ok((vary, diff::unified_diff(a, b)).into_response())
+}
+
+/// The most pastes [list] responds with at once.
+const MAX_PAGE_SIZE: u32 = 100;
+
+/// Query parameters accepted by [list].
+#[derive(Debug, Deserialize)]
+pub struct ListParams {
+    /// How many pastes to respond with, at most [MAX_PAGE_SIZE].
+    pub limit: Option<u32>,
+    /// How many of the newest pastes to skip.
+    #[serde(default)]
+    pub offset: u32,
+    /// Whether to list every paste, rather than only the public ones.
+    #[serde(default)]
+    pub all: bool,
+}
+
+/// List metadata of the public pastes, or all of them if asked, newest first,
+/// a page at a time.
+pub async fn list(
+    _: Admin,
+    State(state): State<App>,
+    Query(params): Query<ListParams>,
+) -> Result<Json<Vec<PasteMeta>>> {
+    let limit = params.limit.unwrap_or(MAX_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    let pastes = state.pastes.list(limit, params.offset, !params.all).await?;
+
+    Ok(Json(pastes))
+}
+
+/// Query parameters accepted by [mine].
+#[derive(Debug, Deserialize)]
+pub struct MineParams {
+    /// How many pastes to respond with, at most [MAX_PAGE_SIZE].
+    pub limit: Option<u32>,
+    /// How many of the newest pastes to skip.
+    #[serde(default)]
+    pub offset: u32,
+}
+
+/// List metadata of the pastes uploaded with the caller's API key, public or
+/// not, newest first, a page at a time.
+pub async fn mine(
+    ApiKey(key): ApiKey,
+    State(state): State<App>,
+    Query(params): Query<MineParams>,
+) -> Result<Json<Vec<PasteMeta>>> {
+    let limit = params.limit.unwrap_or(MAX_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    let owner = paste::hash_api_key(&key);
+    let pastes = state
+        .pastes
+        .list_by_owner(&owner, limit, params.offset)
+        .await?;
+
+    Ok(Json(pastes))
+}
+
+/// Delete every paste, responding with how many were deleted.
+pub async fn clear(_: Admin, State(state): State<App>) -> Result<String> {
+    let removed = state.pastes.clear().await?;
+    state.highlight_cache.clear();
+    tracing::warn!("cleared {} pastes", removed);
+
+    Ok(removed.to_string())
+}
+
+/// What became of a paste [remove_many] was asked to delete.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Removal {
+    /// The paste was deleted.
+    Deleted,
+    /// There was no paste by that id, or it was given more than once.
+    NotFound,
+}
+
+/// The [Removal] of one of the pastes [remove_many] was asked to delete.
+#[derive(Debug, Serialize)]
+pub struct RemovalStatus {
+    /// The id the paste was asked for by.
+    pub id: Uuid,
+    /// What became of it.
+    pub status: Removal,
+}
+
+/// Delete many pastes at once, given a JSON array of their ids, responding
+/// with what became of each, in the order their ids were given.
+///
+/// The pastes are deleted together, so if any can't be, none are.
+pub async fn remove_many(
+    _: Admin,
+    State(state): State<App>,
+    Json(ids): Json<Vec<Uuid>>,
+) -> Result<Json<Vec<RemovalStatus>>> {
+    if ids.len() > MAX_BATCH_SIZE {
+        return Err(AppError::bad_request(format!(
+            "At most {} pastes can be deleted at once",
+            MAX_BATCH_SIZE
+        )));
+    }
+
+    let removed = state.pastes.remove_many(&ids).await?;
+    let mut removed: HashSet<_> = removed.into_iter().collect();
+    for id in &removed {
+        state.highlight_cache.evict(*id);
+    }
+    metrics::counter!(telemetry::DELETES, removed.len() as u64);
+    tracing::warn!("deleted {} of {} pastes", removed.len(), ids.len());
+
+    let statuses = ids
+        .into_iter()
+        .map(|id| RemovalStatus {
+            id,
+            // Each paste only goes once, however many times it's given.
+            status: if removed.remove(&id) {
+                Removal::Deleted
+            } else {
+                Removal::NotFound
+            },
+        })
+        .collect();
+
+    Ok(Json(statuses))
+}
+
+/// Export every paste as a zip archive, with each paste's content in a file
+/// named by its id, e.g. `<id>.txt`.
+///
+/// The archive is streamed as the pastes are fetched, a page at a time, so
+/// only a paste's worth of it is held at once. Password protected and burn
+/// after reading pastes are left out, as they're only for those with the
+/// password, or for reading once. Reading pastes for the export doesn't count
+/// as viewing them.
+pub async fn export(_: Admin, State(state): State<App>) -> Response {
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        if let Err(error) = write_export(&state, &mut sender).await {
+            // The response has already begun, so all that can be done is to
+            // cut it short, for the client to tell the archive is incomplete.
+            tracing::error!("failed to export pastes: {:#}", error);
+            sender.abort();
+        }
+    });
+
+    let headers = [
+        (CONTENT_TYPE, "application/zip"),
+        (CONTENT_DISPOSITION, "attachment; filename=\"pastes.zip\""),
+    ];
+
+    (headers, body::boxed(body)).into_response()
+}
+
+/// Write the archive [export] responds with to `sender`, each paste as soon as
+/// it's been written.
+async fn write_export(state: &App, sender: &mut Sender) -> anyhow::Result<()> {
+    // Zip archives are written by seeking back to finish each file's header
+    // once it's written, so only what's before the file being written is sent.
+    let spool = Spool::default();
+    let mut zip = ZipWriter::new(spool.clone());
+    let options =
+        FileOptions::default().compression_method(CompressionMethod::Deflated);
+    // Pastes created while paging shift the pages along, repeating some.
+    let mut exported = HashSet::new();
+    let mut offset = 0;
+    loop {
+        let page = state.pastes.list(MAX_PAGE_SIZE, offset, false).await?;
+        for meta in &page {
+            if meta.protected || !exported.insert(meta.id) {
+                continue;
+            }
+            // Pastes removed since being listed are simply left out.
+            let Some(paste) = state.pastes.peek(meta.id).await? else {
+                continue;
+            };
+            if paste.burn {
+                continue;
+            }
+            let written = spool.position();
+            zip.start_file(format!("{}.txt", paste.id), options)?;
+            zip.write_all(paste.content.as_bytes())?;
+            sender.send_data(spool.take(written)).await?;
+        }
+        if page.len() < MAX_PAGE_SIZE as usize {
+            break;
+        }
+        offset += MAX_PAGE_SIZE;
+    }
+    zip.finish()?;
+    sender.send_data(spool.take_all()).await?;
+
+    Ok(())
+}
+
+/// Query parameters accepted by [search].
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    /// What to search the content of pastes for.
+    pub q: String,
+    /// How many pastes to respond with, at most [MAX_PAGE_SIZE].
+    pub limit: Option<u32>,
+    /// Where the last search stopped, from its [NEXT_CURSOR] header.
+    pub after: Option<String>,
+}
+
+/// Search for pastes containing a term, responding with their metadata, newest
+/// first.
+///
+/// Only [App::search_window] pastes are searched at a time, since compressed
+/// ones have to be read and decompressed. When the search stops before the
+/// oldest paste, whether for that or for finding enough, where it did is given
+/// in [NEXT_CURSOR], for searching on with `?after=`.
+pub async fn search(
+    _: Admin,
+    State(state): State<App>,
+    Query(params): Query<SearchParams>,
+) -> Result<Response> {
+    // Every paste contains nothing.
+    if params.q.is_empty() {
+        return Err(AppError::bad_request("Search term must not be empty"));
+    }
+
+    let limit = params.limit.unwrap_or(MAX_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    let after = params.after.as_deref().map(str::parse).transpose()?;
+    let page = state
+        .pastes
+        .search(&params.q, limit, after, state.search_window)
+        .await?;
+    let next = page.next.map(|next| [(NEXT_CURSOR, next.to_string())]);
+
+    Ok((next, Json(page.pastes)).into_response())
+}
+
+/// The most pastes [batch] responds with, or [remove_many] deletes, at once.
+const MAX_BATCH_SIZE: usize = 100;
+
+/// Retrieve many pastes at once, as JSON, given a JSON array of their ids.
+///
+/// Pastes that don't exist are left out, as are those that can't be read in
+/// bulk (see [PasteStore::get_many](crate::paste::PasteStore::get_many)). The
+/// rest are in the order their ids were given.
+pub async fn batch(
+    State(state): State<App>,
+    Json(ids): Json<Vec<Uuid>>,
+) -> Result<Json<Vec<Paste>>> {
+    if ids.len() > MAX_BATCH_SIZE {
+        return Err(AppError::bad_request(format!(
+            "At most {} pastes can be retrieved at once",
+            MAX_BATCH_SIZE
+        )));
+    }
+
+    let mut pastes = state.pastes.get_many(&ids).await?;
+    pastes.sort_by_key(|paste| ids.iter().position(|id| *id == paste.id));
+
+    Ok(Json(pastes))
+}
+
+/// Whether the client listed `media_type` as acceptable in its `Accept`
+/// header.
+fn accepts(headers: &HeaderMap, media_type: &str) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media| media.split(';').next().unwrap_or_default().trim() == media_type)
+}
+
+/// Query parameters accepted by the syntax highlighting routes.
+#[derive(Debug, Deserialize)]
+pub struct HighlightParams {
+    /// Name of the theme to highlight with, rather than the default.
+    pub theme: Option<String>,
+    /// Whether to number the lines of HTML output.
+    #[serde(default)]
+    pub linenos: bool,
+    /// Whether to soft-wrap long lines of HTML output.
+    #[serde(default)]
+    pub wrap: bool,
+    /// Whether HTML output should be a complete document, rather than just the
+    /// code.
+    #[serde(default)]
+    pub full: bool,
+    /// Whether HTML output should be styled by classes, as in [stylesheet],
+    /// rather than inline.
+    #[serde(default)]
+    pub classes: bool,
+    /// Lines to draw attention to, e.g. `3,10-15`, which HTML output shows
+    /// only the [LineRanges::excerpt] of.
+    pub lines: Option<String>,
+    /// How many columns to expand tabs to.
+    pub tabs: Option<usize>,
+    /// Whether to respond with terminal output, whatever the client accepts,
+    /// made safe to print wherever by [highlight::for_terminal].
+    #[serde(default)]
+    pub term: bool,
+}
+
+impl HighlightParams {
+    /// Parse the lines to draw attention to, if any were given.
+    fn lines(&self) -> Result<Option<LineRanges>> {
+        self.lines.as_deref().map(str::parse).transpose()
+    }
+
+    /// The width to expand tabs to, which must be sensible.
+    fn tabs(&self) -> Result<usize> {
+        match self.tabs.unwrap_or(highlight::DEFAULT_TAB_WIDTH) {
+            tabs @ 1..=highlight::MAX_TAB_WIDTH => Ok(tabs),
+            _ => Err(AppError::bad_request(format!(
+                "Tab width must be between 1 and {}",
+                highlight::MAX_TAB_WIDTH
+            ))),
+        }
+    }
+
+    /// Parse and check the options for HTML output.
+    fn html_options(&self, state: &App) -> Result<HtmlOptions> {
+        let lines = self.lines()?;
+        let tabs = self.tabs()?;
+        if self.classes && (self.linenos || lines.is_some()) {
+            return Err(AppError::bad_request(
+                "Classes can't be combined with line numbers or selected lines",
+            ));
+        }
+        let theme = state.theme_name(self.theme.as_deref())?.to_string();
+
+        Ok(HtmlOptions { lines, tabs, theme })
+    }
+}
+
+/// The [HighlightParams] for HTML output that need checking, once checked.
+struct HtmlOptions {
+    lines: Option<LineRanges>,
+    tabs: usize,
+    /// Name of the theme to highlight with, which exists.
+    theme: String,
+}
+
+/// The rest of the path of the syntax highlighting routes, after the id.
+#[derive(Debug, Deserialize)]
+pub struct LangPath {
+    /// Extension of the language to highlight as.
+    pub lang: String,
+}
+
+/// Retrieve a paste, syntax highlighted as the language with extension `lang`.
+///
+/// Browsers (anything that accepts `text/html`) are sent HTML, everyone else
+/// gets 24-bit terminal escapes, as does anyone asking with
+/// [HighlightParams::term]. Languages there's no syntax for are dealt with as
+/// [App::unknown_lang] says.
+pub async fn retrieve_and_syntax_highlight(
+    PasteId(id): PasteId,
+    Path(LangPath { lang }): Path<LangPath>,
+    State(state): State<App>,
+    Query(params): Query<HighlightParams>,
+    Query(access): Query<AccessParams>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    // The response depends on the Accept header, so caches must key on it too.
+    let vary = [(VARY, "accept")];
+
+    if accepts(&headers, "text/html") && !params.term {
+        let html = retrieve_and_syntax_highlight_html(
+            PasteId(id),
+            Path(LangPath { lang }),
+            State(state),
+            Query(params),
+            Query(access),
+        );
+        return Ok((vary, html.await?).into_response());
+    }
+
+    let term = params.term;
+    let lines = params.lines()?;
+    let tabs = params.tabs()?;
+    let theme = state.theme_name(params.theme.as_deref())?.to_string();
+    let syntax = requested_syntax(&state, &lang)?;
+    let paste = state.pastes.get(id).await?;
+    let paste = read_checked(&state, paste, &access, |paste| {
+        check_lines(lines.as_ref(), paste)
+    });
+    let paste = paste.await?.ok_or(AppError::NotFound)?;
+    let Some(syntax) = syntax.resolve(&state, &paste) else {
+        return Ok((vary, terminal_output(paste.content, term)).into_response());
+    };
+
+    if highlight::too_many_lines(&paste.content, state.max_highlight_lines) {
+        return Ok((vary, terminal_output(paste.content, term)).into_response());
+    }
+    let key = HighlightKey {
+        id,
+        lang,
+        theme: theme.clone(),
+        format: Format::Terminal,
+        lines: lines.clone(),
+        tabs,
+    };
+    let content = highlight::expand_tabs(&paste.content, tabs);
+    let start = Instant::now();
+    let highlighted = highlight_cached(&state, key, syntax, move |app, syntax| {
+        highlight::highlight(
+            &content,
+            syntax,
+            &app.syntax_set,
+            app.theme(Some(&theme))?,
+            lines.as_ref(),
+            app.highlight_errors,
+        )
+    });
+    let highlighted = highlighted.await?;
+    let timing = HighlightTiming::since(start);
+    let Some(highlighted) = highlighted else {
+        let output = terminal_output(paste.content, term);
+        return Ok((vary, timing, output).into_response());
+    };
+    metrics::increment_counter!(telemetry::HIGHLIGHTS);
+
+    Ok((vary, timing, terminal_output(highlighted, term)).into_response())
+}
+
+/// Respond with terminal `output`, made safe to print wherever if `term` (see
+/// [HighlightParams::term]).
+fn terminal_output(output: String, term: bool) -> Response {
+    if !term {
+        return output.into_response();
+    }
+
+    let content_type = [(CONTENT_TYPE, "text/plain; charset=utf-8")];
+    (content_type, highlight::for_terminal(&output)).into_response()
+}
+
+/// How long it took to highlight a paste, or get it from the cache, responded
+/// with in `Server-Timing` for browsers' developer tools to show.
+struct HighlightTiming(std::time::Duration);
+
+impl HighlightTiming {
+    /// The time since highlighting began at `start`.
+    fn since(start: Instant) -> Self { Self(start.elapsed()) }
+}
+
+impl IntoResponseParts for HighlightTiming {
+    type Error = Infallible;
+
+    fn into_response_parts(
+        self,
+        mut res: ResponseParts,
+    ) -> std::result::Result<ResponseParts, Self::Error> {
+        let timing = format!("highlight;dur={:.3}", self.0.as_secs_f64() * 1000.0);
+        let timing = HeaderValue::try_from(timing).expect("timings are valid headers");
+        res.headers_mut().insert(SERVER_TIMING, timing);
+
+        Ok(res)
+    }
+}
+
+/// Run `highlight` with the app and `syntax` on a blocking thread, giving up
+/// after [App::highlight_timeout], as in [highlight::with_timeout].
+async fn highlight_in_background<T: Send + 'static>(
+    state: &App,
+    syntax: &SyntaxReference,
+    highlight: impl FnOnce(&App, &SyntaxReference) -> Result<T> + Send + 'static,
+) -> Result<Option<T>> {
+    // The thread can't borrow the syntax, so it finds it again in the app.
+    let syntaxes = state.syntax_set.syntaxes();
+    let index = syntaxes.iter().position(|s| std::ptr::eq(s, syntax));
+    let app = state.clone();
+
+    highlight::with_timeout(state.highlight_timeout, move || {
+        let syntax = match index {
+            Some(index) => &app.syntax_set.syntaxes()[index],
+            None => app.syntax_set.find_syntax_plain_text(),
+        };
+        highlight(&app, syntax)
+    })
+    .await
+}
+
+/// Get the output cached for `key`, or else highlight it in the background
+/// with [highlight_in_background] and cache it.
+///
+/// Gives `None` if highlighting took too long, in which case nothing is cached
+/// and the paste should be served as is.
+async fn highlight_cached(
+    state: &App,
+    key: HighlightKey,
+    syntax: &SyntaxReference,
+    highlight: impl FnOnce(&App, &SyntaxReference) -> Result<String> + Send + 'static,
+) -> Result<Option<String>> {
+    if let Some(output) = state.highlight_cache.get(&key) {
+        return Ok(Some(output));
+    }
+
+    let output = highlight_in_background(state, syntax, highlight).await?;
+    if let Some(output) = &output {
+        state.highlight_cache.insert(key, output.clone());
+    }
+
+    Ok(output)
+}
+
+/// Retrieve a paste, syntax highlighted as HTML for viewing in a browser.
+///
+/// Unlike the terminal variant, unknown languages are still rendered through
+/// the highlighter (as plain text, unless [App::unknown_lang] says otherwise)
+/// so that the content is always escaped.
+pub async fn retrieve_and_syntax_highlight_html(
+    PasteId(id): PasteId,
+    Path(LangPath { lang }): Path<LangPath>,
+    State(state): State<App>,
+    Query(params): Query<HighlightParams>,
+    Query(access): Query<AccessParams>,
+) -> Result<Response> {
+    let options = params.html_options(&state)?;
+    let syntax = requested_syntax(&state, &lang)?;
+    let paste = state.pastes.get(id).await?;
+    let paste = read_checked(&state, paste, &access, |paste| {
+        check_lines(options.lines.as_ref(), paste)
+    });
+    let paste = paste.await?.ok_or(AppError::NotFound)?;
+    let syntax = syntax
+        .resolve(&state, &paste)
+        .unwrap_or_else(|| state.syntax_set.find_syntax_plain_text());
+
+    let (html, timing) =
+        render_html(&state, &paste, syntax, lang, &params, options).await?;
+
+    Ok((timing, Html(html)).into_response())
+}
+
+/// Retrieve a paste, syntax highlighted as HTML as the language it was
+/// uploaded as.
+///
+/// Pastes uploaded without a language are highlighted as whatever their first
+/// line gives away, if anything, and as plain text otherwise.
+pub async fn retrieve_html(
+    PasteId(id): PasteId,
+    State(state): State<App>,
+    Query(params): Query<HighlightParams>,
+    Query(access): Query<AccessParams>,
+) -> Result<Response> {
+    let options = params.html_options(&state)?;
+    let paste = state.pastes.get(id).await?;
+    let paste = read_checked(&state, paste, &access, |paste| {
+        check_lines(options.lines.as_ref(), paste)
+    });
+    let paste = paste.await?.ok_or(AppError::NotFound)?;
+    let syntax = guess_syntax(&state.syntax_set, &paste);
+    let lang = syntax.name.clone();
+    let (html, timing) =
+        render_html(&state, &paste, syntax, lang, &params, options).await?;
+
+    Ok((timing, Html(html)).into_response())
+}
+
+/// What other sites may do with an embedded paste: frame it, and style it
+/// inline, but nothing else, in case the paste sneaks anything else in.
+const EMBED_POLICY: &str = "default-src 'none'; style-src 'unsafe-inline'; \
+                            frame-ancestors *";
+
+/// Retrieve a paste, syntax highlighted as HTML like [retrieve_html], as a
+/// fragment for embedding in other sites, whether directly or in an `<iframe>`.
+///
+/// The fragment is styled inline, so it doesn't need our stylesheet, and links
+/// back to the paste. `X-Frame-Options` can't allow every site to frame it, so
+/// it's left out for the policy's `frame-ancestors` to do so.
+pub async fn embed(
+    PasteId(id): PasteId,
+    State(state): State<App>,
+    Host(host): Host,
+    Extension(base): Extension<BasePath>,
+    Query(params): Query<HighlightParams>,
+    Query(access): Query<AccessParams>,
+) -> Result<Response> {
+    let params = HighlightParams {
+        classes: false,
+        full: false,
+        ..params
+    };
+    let options = params.html_options(&state)?;
+    let paste = state.pastes.get(id).await?;
+    let paste = read_checked(&state, paste, &access, |paste| {
+        check_lines(options.lines.as_ref(), paste)
+    });
+    let paste = paste.await?.ok_or(AppError::NotFound)?;
+    let syntax = guess_syntax(&state.syntax_set, &paste);
+    let lang = syntax.name.clone();
+    let (html, timing) =
+        render_html(&state, &paste, syntax, lang, &params, options).await?;
+    let url = paste_url(&host, &base, paste.id, paste.slug.as_deref());
+    let html = highlight::embed_html(&html, &url);
+    let headers = [
+        (CONTENT_SECURITY_POLICY, EMBED_POLICY),
+        (X_CONTENT_TYPE_OPTIONS, "nosniff"),
+    ];
+
+    Ok((headers, timing, Html(html)).into_response())
+}
+
+/// The syntax to highlight `paste` as when no language is asked for: the one
+/// it was uploaded as, or whatever its first line gives away, if anything.
+fn detect_syntax<'a>(
+    syntax_set: &'a SyntaxSet,
+    paste: &Paste,
+) -> Option<&'a SyntaxReference> {
+    paste
+        .language
+        .as_deref()
+        .and_then(|lang| highlight::find_syntax(syntax_set, lang))
+        .or_else(|| {
+            let first_line = paste.content.lines().next().unwrap_or_default();
+            syntax_set.find_syntax_by_first_line(first_line)
+        })
+}
+
+/// Like [detect_syntax], but plain text if nothing is detected.
+fn guess_syntax<'a>(syntax_set: &'a SyntaxSet, paste: &Paste) -> &'a SyntaxReference {
+    detect_syntax(syntax_set, paste)
+        .unwrap_or_else(|| syntax_set.find_syntax_plain_text())
+}
+
+/// The syntax a paste was asked for highlighted as, see [requested_syntax].
+enum RequestedSyntax<'a> {
+    Found(&'a SyntaxReference),
+    PlainText,
+    /// Whatever the paste turns out to be, once it's read.
+    Detect,
+}
+
+impl<'a> RequestedSyntax<'a> {
+    /// The syntax to highlight `paste` as, or none for plain text.
+    fn resolve(self, state: &'a App, paste: &Paste) -> Option<&'a SyntaxReference> {
+        match self {
+            Self::Found(syntax) => Some(syntax),
+            Self::PlainText => None,
+            Self::Detect => detect_syntax(&state.syntax_set, paste)
+                .filter(|syntax| state.allowed_langs.allows(syntax)),
+        }
+    }
+}
+
+/// The syntax to highlight a paste as when `lang` is asked for.
+///
+/// If there's no such language, or it isn't in [App::allowed_langs],
+/// [App::unknown_lang] says what to do instead. This is worked out before the
+/// paste is read, so that a burn after reading paste isn't burned by a request
+/// that's going to fail anyway.
+fn requested_syntax<'a>(state: &'a App, lang: &str) -> Result<RequestedSyntax<'a>> {
+    let message = match highlight::find_syntax(&state.syntax_set, lang) {
+        Some(syntax) if state.allowed_langs.allows(syntax) => {
+            return Ok(RequestedSyntax::Found(syntax))
+        }
+        Some(_) => format!("Highlighting as `{}` isn't allowed here", lang),
+        None => format!("Unknown language `{}`", lang),
+    };
+
+    match state.unknown_lang {
+        UnknownLangPolicy::PlainText => Ok(RequestedSyntax::PlainText),
+        UnknownLangPolicy::NotFound => {
+            Err(AppError::new(StatusCode::NOT_FOUND, message))
+        }
+        UnknownLangPolicy::Detect => Ok(RequestedSyntax::Detect),
+    }
+}
+
+/// Fail if any of the `lines` selected, if there are any, are past the end of
+/// `paste`.
+fn check_lines(lines: Option<&LineRanges>, paste: &Paste) -> Result<()> {
+    match lines {
+        Some(lines) => lines.check(paste.content.lines().count()),
+        None => Ok(()),
+    }
+}
+
+/// Highlight `paste` as HTML as `syntax`, cached under the name `lang`, with
+/// how long that took.
+///
+/// Only the [LineRanges::excerpt] of any selected lines is highlighted, still
+/// numbered as it is in the paste, so they must have been
+/// [checked](check_lines) against it already. Pastes (or excerpts) with too
+/// many lines to highlight are escaped as they are, with no timing.
+async fn render_html(
+    state: &App,
+    paste: &Paste,
+    syntax: &SyntaxReference,
+    lang: String,
+    params: &HighlightParams,
+    HtmlOptions { lines, tabs, theme }: HtmlOptions,
+) -> Result<(String, Option<HighlightTiming>)> {
+    let (first, excerpt, selected) = match &lines {
+        Some(lines) => {
+            let (first, excerpt) = lines.excerpt(&paste.content);
+            (first, excerpt, lines.within_excerpt())
+        }
+        None => (1, paste.content.clone(), None),
+    };
+    if highlight::too_many_lines(&excerpt, state.max_highlight_lines) {
+        return Ok((highlight::plain_html(&excerpt), None));
+    }
+    let format = match (params.classes, params.linenos) {
+        (true, _) => Format::HtmlClassed,
+        (false, true) => Format::HtmlWithLinenos,
+        (false, false) => Format::Html,
+    };
+    let key = HighlightKey {
+        id: paste.id,
+        lang,
+        theme: theme.clone(),
+        format,
+        lines: lines.clone(),
+        tabs,
+    };
+    let page_theme = state.theme(Some(&theme))?;
+    let content = highlight::expand_tabs(&excerpt, tabs);
+    let start = Instant::now();
+    let html = highlight_cached(state, key, syntax, move |app, syntax| {
+        let theme = app.theme(Some(&theme))?;
+        match format {
+            Format::HtmlClassed => highlight::highlight_html_classed(
+                &content,
+                syntax,
+                &app.syntax_set,
+                app.highlight_errors,
+            ),
+            Format::HtmlWithLinenos => highlight::highlight_html_with_linenos(
+                &content,
+                syntax,
+                &app.syntax_set,
+                theme,
+                selected.as_ref(),
+                first,
+                app.highlight_errors,
+            ),
+            _ => highlight::highlight_html(
+                &content,
+                syntax,
+                &app.syntax_set,
+                theme,
+                selected.as_ref(),
+                app.highlight_errors,
+            ),
+        }
+    });
+    let html = html.await?;
+    let timing = HighlightTiming::since(start);
+    let Some(html) = html else {
+        return Ok((highlight::plain_html(&excerpt), Some(timing)));
+    };
+    metrics::increment_counter!(telemetry::HIGHLIGHTS);
+    // Wrapping is cheap, so there's no need to cache it separately.
+    let html = match params.wrap {
+        true => highlight::wrap_html(&html),
+        false => html,
+    };
+    let html = match params.full {
+        true => highlight::wrap_full_html(&html, page_theme)?,
+        false => html,
+    };
+
+    Ok((html, Some(timing)))
+}
+
+/// Respond with the metrics recorded so far, in Prometheus' text format.
+pub async fn metrics() -> String { telemetry::install().render() }
+
+/// Information about the running build of the server.
+#[derive(Debug, Serialize)]
+pub struct Version {
+    /// The version of the crate.
+    pub version: &'static str,
+    /// The short hash of the commit built, or `unknown`.
+    pub git_sha: &'static str,
+    pub built_at: DateTime<Utc>,
+}
+
+/// Describe the running build of the server, for debugging deployments.
+pub async fn version() -> Json<Version> {
+    let built_at = env!("BUILT_AT").parse().unwrap_or_default();
+
+    Json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("GIT_SHA"),
+        built_at: Utc.timestamp_opt(built_at, 0).single().unwrap_or_default(),
+    })
+}
+
+/// Query parameters accepted by [stylesheet].
+#[derive(Debug, Deserialize)]
+pub struct StylesheetParams {
+    /// Name of the theme to color with, rather than the default.
+    pub theme: Option<String>,
+}
+
+/// Respond with the stylesheet coloring HTML highlighted with `?classes=true`.
+///
+/// The stylesheet is the same for every paste, so browsers may cache it.
+pub async fn stylesheet(
+    State(state): State<App>,
+    Query(params): Query<StylesheetParams>,
+) -> Result<Response> {
+    let css = highlight::theme_css(state.theme(params.theme.as_deref())?)?;
+    let headers = [
+        (CONTENT_TYPE, "text/css; charset=utf-8"),
+        (CACHE_CONTROL, "public, max-age=86400"),
+    ];
+
+    Ok((headers, css).into_response())
+}
+
+/// List the names of the themes available for syntax highlighting.
+pub async fn themes(State(state): State<App>) -> Json<Vec<String>> {
+    Json(state.theme_set.themes.keys().cloned().collect())
+}
+
+/// A language that pastes can be syntax highlighted as.
+#[derive(Debug, Serialize)]
+pub struct Language {
+    pub name: String,
+    /// File extensions that can be used as the `lang` to highlight with.
+    pub extensions: Vec<String>,
+}
+
+/// List the languages available for syntax highlighting.
+pub async fn languages(State(state): State<App>) -> Json<Vec<Language>> {
+    let languages = state
+        .syntax_set
+        .syntaxes()
+        .iter()
+        .map(|syntax| Language {
+            name: syntax.name.clone(),
+            extensions: syntax.file_extensions.clone(),
+        })
+        .collect();
+
+    Json(languages)
+}
+
+/// Replace the content of a paste, given the token issued when it was
+/// uploaded, as [remove] takes it.
+pub async fn update(
+    PasteId(id): PasteId,
+    State(state): State<App>,
+    Query(params): Query<TokenParams>,
+    headers: HeaderMap,
+    PasteContent(body): PasteContent,
+) -> Result<(StatusCode, String)> {
+    let paste = state.pastes.peek(id).await?.ok_or(AppError::NotFound)?;
+    check_delete_token(&paste, &params, &headers)?;
+
+    state.content_filter.check(&body)?;
+    let paste = state.pastes.update(id, body).await?;
+    state.highlight_cache.evict(id);
+
+    let paste = paste.ok_or(AppError::NotFound)?;
+
+    Ok((StatusCode::OK, paste.content))
+}
+
+/// The metadata of a paste to change, as a JSON body, leaving out what's to be
+/// left as is.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateMetaRequest {
+    /// What to call the paste.
+    pub title: Option<String>,
+    /// Extension or name of the language to highlight the paste as.
+    pub language: Option<String>,
+}
+
+/// Change some of the metadata of a paste, without touching its content, given
+/// the token issued when it was uploaded, responding with its metadata once
+/// changed.
+pub async fn update_meta(
+    PasteId(id): PasteId,
+    State(state): State<App>,
+    Query(params): Query<TokenParams>,
+    headers: HeaderMap,
+    Json(request): Json<UpdateMetaRequest>,
+) -> Result<Json<PasteMeta>> {
+    let paste = state.pastes.peek(id).await?.ok_or(AppError::NotFound)?;
+    check_delete_token(&paste, &params, &headers)?;
+
+    if let Some(title) = &request.title {
+        check_title(title)?;
+    }
+    if let Some(lang) = &request.language {
+        check_language(&state, lang)?;
+    }
+
+    let meta = state
+        .pastes
+        .update_meta(id, request.title.as_deref(), request.language.as_deref())
+        .await?;
+
+    Ok(Json(meta.ok_or(AppError::NotFound)?))
+}
+
+/// Add the body of the request onto the end of a paste, e.g. to stream a log
+/// into it, given the token issued when it was uploaded, responding with the
+/// paste's new size in bytes.
+///
+/// The paste as a whole must still fit within [App::max_paste_size]. Password
+/// protected and burn after reading pastes can't be appended to, since what's
+/// in them is only for those who read them the way they ask to be read.
+pub async fn append(
+    PasteId(id): PasteId,
+    State(state): State<App>,
+    Query(params): Query<TokenParams>,
+    headers: HeaderMap,
+    PasteContent(body): PasteContent,
+) -> Result<(StatusCode, String)> {
+    let paste = state.pastes.peek(id).await?.ok_or(AppError::NotFound)?;
+    check_delete_token(&paste, &params, &headers)?;
+    if paste.password_hash.is_some() || paste.burn {
+        return Err(AppError::Conflict(
+            "Password protected and burn after reading pastes can't be appended to"
+                .to_string(),
+        ));
+    }
+    if paste.content.len() + body.len() > state.max_paste_size {
+        return Err(AppError::PayloadTooLarge(format!(
+            "Paste would be too large, the maximum size is {} bytes",
+            state.max_paste_size
+        )));
+    }
+    // A pattern may straddle the join, so check the whole paste.
+    state
+        .content_filter
+        .check(&format!("{}{}", paste.content, body))?;
+
+    let paste = state.pastes.append(id, &body).await?;
+    state.highlight_cache.evict(id);
+
+    let paste = paste.ok_or(AppError::NotFound)?;
+
+    Ok((StatusCode::OK, paste.content.len().to_string()))
+}
+
+/// Copy a paste into a new one, for remixing it, responding with the new
+/// paste's URL like [upload] does.
+///
+/// The fork gets the paste's content and language, but none of its other
+/// options. Forking reads the paste, so needs its password, if any, and burns
+/// it if it's burn after reading.
+pub async fn fork(
+    PasteId(id): PasteId,
+    State(state): State<App>,
+    Host(host): Host,
+    Extension(base): Extension<BasePath>,
+    ClientIp(ip): ClientIp,
+    Query(access): Query<AccessParams>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    admit(&state, ip).await?;
+
+    let paste = state.pastes.get(id).await?;
+    let paste = read(&state, paste, &access).await?;
+    let paste = paste.ok_or(AppError::NotFound)?;
+    let fork = NewPaste {
+        content: paste.content,
+        language: paste.language,
+        fork: true,
+        ..NewPaste::default()
+    };
+
+    create(&state, &host, &base, &headers, fork).await
+}
+
+/// Query parameters accepted by [remove], and the other routes that change a
+/// paste rather than just read it.
+#[derive(Debug, Deserialize)]
+pub struct TokenParams {
+    /// The token issued when the paste was uploaded.
+    pub token: Option<String>,
+}
+
+/// Fail unless the token issued when `paste` was uploaded was given, in
+/// `params` or in an `Authorization: Bearer` header.
+fn check_delete_token(
+    paste: &Paste,
+    params: &TokenParams,
+    headers: &HeaderMap,
+) -> Result<()> {
+    let token = params.token.as_deref().or_else(|| {
+        headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+    });
+
+    if !token.is_some_and(|token| paste.deletable_by(token)) {
+        return Err(AppError::Forbidden(
+            "A valid delete token is required".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Delete a paste, given the token issued when it was uploaded.
+///
+/// The token may also be given in an `Authorization: Bearer` header.
+pub async fn remove(
+    PasteId(id): PasteId,
+    State(state): State<App>,
+    Query(params): Query<TokenParams>,
+    headers: HeaderMap,
+) -> Result<(StatusCode, &'static str)> {
+    let paste = state.pastes.peek(id).await?.ok_or(AppError::NotFound)?;
+    check_delete_token(&paste, &params, &headers)?;
+
+    state.pastes.remove(id).await?;
+    state.highlight_cache.evict(id);
+    metrics::increment_counter!(telemetry::DELETES);
+
+    Ok((StatusCode::OK, "Deleted!"))
+}
+
+/// A complete URL to the paste with `id`, or by its `slug` if it has one, so
+/// users can easily copy and save it.
+fn paste_url(
+    host: &str,
+    BasePath(base): &BasePath,
+    id: Uuid,
+    slug: Option<&str>,
+) -> String {
+    let path = match slug {
+        Some(slug) => format!("p/{}", slug),
+        None => id.to_string(),
+    };
+    format!("{}://{}{}/{}", util::scheme(host), host, base, path)
+}
+
+/// Check that the client at `ip` may create a paste right now.
+async fn admit(state: &App, ip: Option<IpAddr>) -> Result<()> {
+    // Clients we can't identify can't be told apart, so they go unlimited.
+    if let Some(ip) = ip {
+        state.upload_limiter.check(ip)?;
+    }
+
+    // Refuse new pastes once we're full, rather than running out of database.
+    if let Some(max_pastes) = state.max_pastes {
+        if state.pastes.count().await? >= max_pastes {
+            return Err(AppError::new(
+                StatusCode::INSUFFICIENT_STORAGE,
+                "There are too many pastes, try again later",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// A newly created paste, as responded with to clients that accept JSON.
+#[derive(Debug, Serialize)]
+pub struct Created {
+    pub id: Uuid,
+    /// The same URL responded with to everyone else.
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Create a paste, responding with `201 Created`, its URL, and a token for
+/// deleting it.
+///
+/// The URL is in `Location`, and the body too. Clients that accept
+/// `application/json` get it as part of a [Created] there.
+/// Identical content may be answered with an existing paste, whose token isn't
+/// ours to hand out, so no token is given then, and the status is `200 OK`, as
+/// nothing was created. A paste created with the same idempotency key is
+/// answered with as if it were new, token and all, as the client is retrying.
+async fn create(
+    state: &App,
+    host: &str,
+    base: &BasePath,
+    headers: &HeaderMap,
+    paste: NewPaste,
+) -> Result<Response> {
+    state.content_filter.check(&paste.content)?;
+
+    let token = match &paste.idempotency_key {
+        Some(key) => paste::idempotent_delete_token(key),
+        None => paste::new_delete_token(),
+    };
+    let paste = NewPaste {
+        delete_token_hash: Some(paste::hash_delete_token(&token)),
+        ..paste
+    };
+    let paste = state.pastes.create(paste).await?;
+    metrics::increment_counter!(telemetry::UPLOADS);
+
+    let created = paste.deletable_by(&token);
+    let status = match created {
+        true => StatusCode::CREATED,
+        false => StatusCode::OK,
+    };
+    let url = paste_url(host, base, paste.id, paste.slug.as_deref());
+    let location = [(LOCATION, url.clone())];
+    let body = match accepts(headers, "application/json") {
+        true => (
+            status,
+            location,
+            Json(Created {
+                id: paste.id,
+                url,
+                created_at: paste.created_at,
+            }),
+        )
+            .into_response(),
+        false => (status, location, url).into_response(),
+    };
+    let response = match created {
+        true => ([(DELETE_TOKEN, token)], body).into_response(),
+        false => body,
+    };
+
+    Ok(response)
+}
+
+/// The longest title a paste may have, in characters.
+const MAX_TITLE_LENGTH: usize = 200;
+
+/// Check that `title` isn't too long to give a paste.
+fn check_title(title: &str) -> Result<()> {
+    if title.chars().count() > MAX_TITLE_LENGTH {
+        return Err(AppError::bad_request(format!(
+            "Paste title must be at most {} characters",
+            MAX_TITLE_LENGTH
+        )));
+    }
+
+    Ok(())
+}
+
+/// Check that there's a syntax for `lang`, to catch typos when a paste is
+/// given a language, rather than silently never highlighting the paste.
+fn check_language(state: &App, lang: &str) -> Result<()> {
+    if highlight::find_syntax(&state.syntax_set, lang).is_none() {
+        return Err(AppError::bad_request(format!(
+            "Unknown language `{}`",
+            lang
+        )));
+    }
+
+    Ok(())
+}
+
+/// Query parameters accepted by [upload].
+#[derive(Debug, Deserialize)]
+pub struct UploadParams {
+    /// Number of seconds the paste should live for. Never expires if omitted.
+    pub ttl: Option<u32>,
+    /// Number of seconds the paste may go unread before it's removed. Never
+    /// removed for being idle if omitted.
+    pub idle_ttl: Option<u32>,
+    /// A human-readable name to retrieve the paste by, in addition to its id.
+    pub name: Option<String>,
+    /// A password that must be given to read the paste.
+    pub password: Option<String>,
+    /// Whether the paste should be deleted the first time it's read.
+    #[serde(default)]
+    pub burn: bool,
+    /// Extension of the language to highlight the paste as when it's read.
+    pub lang: Option<String>,
+    /// What to call the paste, as shown with its metadata.
+    pub title: Option<String>,
+    /// Whether the paste may be discovered, by listing or at random.
+    #[serde(default)]
+    pub public: bool,
+    /// Whether to accept a paste with nothing but whitespace, or nothing at
+    /// all, which is otherwise taken to be a mistake.
+    #[serde(default)]
+    pub allow_empty: bool,
+}
+
+/// Upload a paste.
+///
+/// Extracts the host url, body of the request, and a database connection from
+/// the application state. The options are given in the query, or alongside the
+/// content if the body is JSON (see [UploadBody]).
+pub async fn upload(
+    State(state): State<App>,
+    Host(host): Host,
+    Extension(base): Extension<BasePath>,
+    ClientIp(ip): ClientIp,
+    Query(params): Query<UploadParams>,
+    headers: HeaderMap,
+    body: UploadBody,
+) -> Result<Response> {
+    admit(&state, ip).await?;
+
+    let request = match body {
+        UploadBody::Json(request) => request,
+        UploadBody::Content(content) => CreatePasteRequest {
+            content,
+            language: params.lang,
+            ttl: params.ttl,
+            idle_ttl: params.idle_ttl,
+            name: params.name,
+            password: params.password,
+            burn: params.burn,
+            title: params.title,
+            public: params.public,
+        },
+    };
+
+    let idempotency_key = match headers.get(IDEMPOTENCY_KEY) {
+        Some(key) => match key.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH => {
+                Some(key.to_string())
+            }
+            _ => {
+                return Err(AppError::bad_request(format!(
+                    "Idempotency key must be between 1 and {} visible ASCII \
+                     characters",
+                    MAX_IDEMPOTENCY_KEY_LENGTH
+                )))
+            }
+        },
+        None => None,
+    };
+
+    if !params.allow_empty && request.content.trim().is_empty() {
+        return Err(AppError::bad_request("Paste content must not be empty"));
+    }
+
+    if let Some(title) = &request.title {
+        check_title(title)?;
+    }
+
+    // An empty name would give a URL that doesn't lead anywhere.
+    if request.name.as_deref() == Some("") {
+        return Err(AppError::bad_request("Paste name must not be empty"));
+    }
+
+    if let Some(lang) = &request.language {
+        check_language(&state, lang)?;
+    }
+
+    let expires_at = request
+        .ttl
+        .map(|ttl| Utc::now() + Duration::seconds(i64::from(ttl)));
+    let paste = NewPaste {
+        content: request.content,
+        expires_at,
+        idle_ttl: request
+            .idle_ttl
+            .map(|idle_ttl| Duration::seconds(i64::from(idle_ttl))),
+        slug: request.name,
+        password_hash: request
+            .password
+            .as_deref()
+            .map(password::hash)
+            .transpose()?,
+        burn: request.burn,
+        language: request.language,
+        title: request.title,
+        public: request.public,
+        idempotency_key,
+        owner_key_hash: ApiKey::from_headers(&headers, &state)
+            .map(|ApiKey(key)| paste::hash_api_key(&key)),
+        ..NewPaste::default()
+    };
+
+    create(&state, &host, &base, &headers, paste).await
+}
+
+/// Respond with a QR code of the URL to a paste, as a PNG.
+///
+/// Pastes that don't exist get a 404 rather than a code leading nowhere. Named
+/// pastes are linked to by name, which is all that's read of the paste besides
+/// that it exists, so getting the code doesn't count as a view or need the
+/// password.
+pub async fn qr(
+    PasteId(id): PasteId,
+    State(state): State<App>,
+    Host(host): Host,
+    Extension(base): Extension<BasePath>,
+) -> Result<Response> {
+    if !state.pastes.exists(id).await? {
+        return Err(AppError::NotFound);
+    }
+
+    let slug = state.pastes.slug(id).await?;
+    let url = paste_url(&host, &base, id, slug.as_deref());
+    let code = QrCode::new(url).map_err(anyhow::Error::from)?;
+    let image = code.render::<Luma<u8>>().build();
+    let mut png = Vec::new();
+    DynamicImage::ImageLuma8(image)
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .map_err(anyhow::Error::from)?;
+
+    Ok(([(CONTENT_TYPE, "image/png")], png).into_response())
+}
+
+/// Query parameters accepted by [import].
+#[derive(Debug, Deserialize)]
+pub struct ImportParams {
+    /// The URL of the content to paste.
+    pub url: String,
+}
+
+/// Create a paste from the content at a remote URL, fetched by the server.
+pub async fn import(
+    State(state): State<App>,
+    Host(host): Host,
+    Extension(base): Extension<BasePath>,
+    ClientIp(ip): ClientIp,
+    Query(params): Query<ImportParams>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    admit(&state, ip).await?;
+
+    let url: Url = params
+        .url
+        .parse()
+        .map_err(|_| AppError::bad_request("Invalid URL"))?;
+    let addr = import::check_url(&url).await?;
+    let content = import::fetch(&url, addr, state.max_paste_size).await?;
+    let paste = NewPaste {
+        content,
+        ..NewPaste::default()
+    };
+
+    create(&state, &host, &base, &headers, paste).await
+}
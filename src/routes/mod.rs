@@ -0,0 +1,287 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use axum::{
+    body::Body,
+    extract::MatchedPath,
+    http::{
+        header::{
+            ACCEPT, AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_TYPE,
+            LOCATION, RETRY_AFTER,
+        },
+        HeaderName, HeaderValue, Method, Request, StatusCode,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Router,
+};
+use tower_http::{
+    compression::CompressionLayer,
+    cors::{AllowOrigin, CorsLayer},
+    trace::TraceLayer,
+};
+use tracing::Span;
+use uuid::Uuid;
+
+use crate::{
+    app::App,
+    error::{AppError, CURRENT_REQUEST_ID},
+    extract, telemetry,
+};
+
+mod handlers;
+#[cfg(test)]
+mod tests;
+mod usage;
+
+/// Parse a comma-separated list of origins, e.g. `https://a.example,
+/// https://b.example`, for [make_router] to allow.
+pub fn parse_origins(list: &str) -> anyhow::Result<Vec<HeaderValue>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .map(|origin| {
+            HeaderValue::from_str(origin)
+                .with_context(|| format!("Invalid origin {:?}", origin))
+        })
+        .collect()
+}
+
+/// Let browser apps on other origins use the API.
+///
+/// Only `allowed_origins` may if given, otherwise any origin may, which is
+/// handy for local development.
+fn cors(allowed_origins: Option<Vec<HeaderValue>>) -> CorsLayer {
+    let origins = match allowed_origins {
+        Some(origins) => AllowOrigin::list(origins),
+        None => AllowOrigin::any(),
+    };
+
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+        ])
+        .allow_headers([
+            ACCEPT,
+            AUTHORIZATION,
+            CONTENT_ENCODING,
+            CONTENT_TYPE,
+            HeaderName::from_static(extract::API_KEY),
+            HeaderName::from_static(handlers::IDEMPOTENCY_KEY),
+            HeaderName::from_static(handlers::REQUEST_ID),
+        ])
+        // Scripts can only read the safelisted headers unless told otherwise.
+        .expose_headers([
+            CONTENT_DISPOSITION,
+            LOCATION,
+            RETRY_AFTER,
+            HeaderName::from_static(handlers::CONTENT_SHA256),
+            HeaderName::from_static(handlers::DELETE_TOKEN),
+            HeaderName::from_static(handlers::NEXT_CURSOR),
+            HeaderName::from_static(handlers::DETECTED_LANGUAGE),
+            HeaderName::from_static(handlers::REQUEST_ID),
+        ])
+}
+
+/// The longest request id taken from the client, which is plenty for a UUID or
+/// two.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Give the request an id, for correlating it with its logs and any error it's
+/// responded to with, and respond with it in `X-Request-Id`.
+///
+/// The id is the client's if it sent one in `X-Request-Id`, e.g. from a proxy
+/// further up, or a new UUID otherwise.
+async fn correlate(mut request: Request<Body>, next: Next<Body>) -> Response {
+    let id = match request.headers().get(handlers::REQUEST_ID) {
+        Some(id)
+            if id.to_str().is_ok_and(|id| {
+                !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH
+            }) =>
+        {
+            id.clone()
+        }
+        _ => HeaderValue::try_from(Uuid::new_v4().to_string())
+            .expect("UUIDs are valid header values"),
+    };
+    // For the trace span to pick up.
+    request
+        .headers_mut()
+        .insert(handlers::REQUEST_ID, id.clone());
+
+    let current = id.to_str().unwrap_or_default().to_string();
+    let mut response = CURRENT_REQUEST_ID.scope(current, next.run(request)).await;
+    response.headers_mut().insert(handlers::REQUEST_ID, id);
+
+    response
+}
+
+/// Record how long the request took to handle for the metrics.
+///
+/// Requests are told apart by the route they matched rather than their path,
+/// so that every paste doesn't get a series of its own.
+async fn record_latency(request: Request<Body>, next: Next<Body>) -> Response {
+    let method = request.method().clone();
+    let path = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        None => "unmatched".to_string(),
+    };
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+    telemetry::record_request(
+        method.as_str(),
+        &path,
+        response.status().as_u16(),
+        start.elapsed(),
+    );
+
+    response
+}
+
+/// Explain the 405 axum responds with when a path is requested with a method
+/// it has no route for, like our other errors are explained.
+///
+/// axum adds the `Allow` header listing the methods that do have a route once
+/// this has responded.
+async fn explain_method_not_allowed(
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let method = request.method().clone();
+    let response = next.run(request).await;
+    if response.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return response;
+    }
+
+    let message = format!("Method {} isn't allowed here, see `Allow`", method);
+    AppError::new(StatusCode::METHOD_NOT_ALLOWED, message).into_response()
+}
+
+/// The path the app is mounted under, e.g. `/paste`, or empty if it's at the
+/// root, for linking to pastes.
+#[derive(Debug, Clone)]
+pub struct BasePath(pub String);
+
+/// Build the router for our web app.
+///
+/// See [cors] for what `allowed_origins` means. If there's a `base_path`, every
+/// route is under it, e.g. `/paste/<id>` rather than `/<id>`, for serving
+/// alongside other apps behind a shared proxy.
+pub fn make_router(
+    allowed_origins: Option<Vec<HeaderValue>>,
+    base_path: Option<&str>,
+) -> Router<App> {
+    // Leading slash or not, trailing slash or not, it all means the same.
+    let base_path = match base_path.map(|path| path.trim_matches('/')) {
+        Some(path) if !path.is_empty() => format!("/{}", path),
+        _ => String::new(),
+    };
+    let router = match base_path.as_str() {
+        "" => routes(),
+        // Nesting only routes the base path itself to `/`, so route it with a
+        // trailing slash too.
+        base => Router::new().nest(base, routes()).route(
+            &format!("{}/", base),
+            get(handlers::index).post(handlers::upload),
+        ),
+    };
+
+    router
+        // Handlers need the base path to link to pastes.
+        .layer(Extension(BasePath(base_path)))
+        .layer(middleware::from_fn(explain_method_not_allowed))
+        .layer(middleware::from_fn(record_latency))
+        // Compress responses for clients that accept it, big pastes add up.
+        // Brotli wins over gzip when the client accepts both equally, since it
+        // compresses text better.
+        .layer(CompressionLayer::new().br(true).gzip(true))
+        .layer(cors(allowed_origins))
+        // Log every request and how long it took. Shuttle installs the
+        // subscriber for us, which is filtered by `RUST_LOG` (default `info`).
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(|request: &Request<Body>| {
+                    let request_id = request
+                        .headers()
+                        .get(handlers::REQUEST_ID)
+                        .and_then(|id| id.to_str().ok());
+                    tracing::info_span!(
+                        "request",
+                        method = %request.method(),
+                        path = %request.uri().path(),
+                        request_id,
+                    )
+                })
+                .on_response(|response: &Response, latency: Duration, _: &Span| {
+                    tracing::info!(
+                        status = response.status().as_u16(),
+                        latency_ms = latency.as_millis(),
+                        "finished processing request",
+                    );
+                }),
+        )
+        // Outside the trace, so that its span has the id.
+        .layer(middleware::from_fn(correlate))
+}
+
+/// The routes of our web app, relative to the base path.
+///
+/// Each version of the API is under its own prefix, e.g. `/api/v1`, so that it
+/// can change without breaking clients of the last. The current version is at
+/// the root too, where it was before the API was versioned.
+fn routes() -> Router<App> {
+    Router::new()
+        .nest("/api/v1", v1_router())
+        .merge(v1_router())
+}
+
+/// The routes of version 1 of the API, relative to where it's mounted.
+fn v1_router() -> Router<App> {
+    // Each path's methods are routed together, so that `Allow` lists them once.
+    Router::new()
+        .route("/", get(handlers::index).post(handlers::upload))
+        .route("/import", post(handlers::import))
+        .route("/batch", post(handlers::batch))
+        .route("/highlight.css", get(handlers::stylesheet))
+        .route("/themes", get(handlers::themes))
+        .route("/languages", get(handlers::languages))
+        .route("/version", get(handlers::version))
+        .route("/metrics", get(handlers::metrics))
+        .route("/admin/pastes", get(handlers::list).delete(handlers::clear))
+        .route("/admin/delete", post(handlers::remove_many))
+        .route("/mine", get(handlers::mine))
+        .route("/search", get(handlers::search))
+        .route("/export", get(handlers::export))
+        .route("/random", get(handlers::random))
+        .route("/diff/:id1/:id2", get(handlers::diff))
+        .route(
+            "/:id",
+            get(handlers::retrieve)
+                .head(handlers::retrieve_headers)
+                .put(handlers::update)
+                .patch(handlers::update_meta)
+                .delete(handlers::remove),
+        )
+        .route("/p/:slug", get(handlers::retrieve_by_slug))
+        .route("/raw/:id", get(handlers::retrieve_raw))
+        .route("/qr/:id", get(handlers::qr))
+        .route("/:id/meta", get(handlers::retrieve_meta))
+        .route("/:id/md", get(handlers::retrieve_markdown))
+        .route("/:id/download", get(handlers::download))
+        .route("/:id/html", get(handlers::retrieve_html))
+        .route("/:id/embed", get(handlers::embed))
+        .route("/:id/:lang", get(handlers::retrieve_and_syntax_highlight))
+        .route(
+            "/:id/:lang/html",
+            get(handlers::retrieve_and_syntax_highlight_html),
+        )
+        .route("/:id/append", post(handlers::append))
+        .route("/:id/fork", post(handlers::fork))
+}
@@ -0,0 +1,3657 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io::{Cursor, Read, Write},
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use axum::{
+    body::Body,
+    http::{
+        header::{
+            ACCEPT, ACCEPT_ENCODING, ACCESS_CONTROL_ALLOW_METHODS,
+            ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_METHOD, ALLOW,
+            AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_LENGTH,
+            CONTENT_SECURITY_POLICY, CONTENT_TYPE, LOCATION, ORIGIN, RETRY_AFTER,
+            X_CONTENT_TYPE_OPTIONS,
+        },
+        HeaderValue, Method, Request, StatusCode, Uri,
+    },
+    response::Response,
+    Router,
+};
+use axum_test_helper::TestClient;
+use chrono::{DateTime, Utc};
+use flate2::{write::GzEncoder, Compression};
+use image::Luma;
+use qrcode::QrCode;
+use serde_json::{json, Value};
+use syntect::parsing::SyntaxDefinition;
+use tower::ServiceExt;
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id},
+    Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, Layer};
+use uuid::Uuid;
+use zip::ZipArchive;
+
+use super::{
+    handlers::{
+        CONTENT_SHA256, DELETE_TOKEN, IDEMPOTENCY_KEY, NEXT_CURSOR, REQUEST_ID,
+        SERVER_TIMING,
+    },
+    make_router, parse_origins, usage,
+};
+use crate::{
+    app::{App, AppBuilder},
+    extract::API_KEY,
+    filter::ContentFilter,
+    highlight::{
+        HighlightErrorPolicy, UnknownLangPolicy, BROKEN_SYNTAX, DEFAULT_THEME,
+    },
+    markdown::MarkdownAllowlist,
+    password,
+    paste::{
+        self,
+        mock::{self, MockPasteStore},
+        NewPaste,
+    },
+    telemetry,
+};
+
+// Extend app to have a mock method that uses the Mock database.
+impl App {
+    pub fn mock() -> Self { AppBuilder::new(MockPasteStore::arc()).build() }
+}
+
+// Get a test client suitable for use within tests,
+// sans any infrastructural setup (Databases, services, etc.).
+fn get_client() -> TestClient { get_client_with(App::mock()) }
+
+// Get a test client for a specific application state.
+fn get_client_with(app: App) -> TestClient {
+    // Construct router with the given state.
+    let router = make_router(None, None).with_state(app);
+
+    // Create test client to router.
+    TestClient::new(router)
+}
+
+#[tokio::test]
+async fn test_index() -> Result<()> {
+    let client = get_client();
+
+    // Test that index succeeds.
+    let response = client.get("/").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let content_type = response.headers()[CONTENT_TYPE].to_str()?;
+    assert!(content_type.starts_with("text/plain"));
+    assert_eq!(response.text().await, usage::usage());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_landing_page() -> Result<()> {
+    let page = "<!DOCTYPE html>\n<h1>Welcome to pstrs</h1>\n";
+    let app = AppBuilder::new(MockPasteStore::arc())
+        .landing_page(page)
+        .build();
+    let client = get_client_with(app);
+
+    // Test that the landing page is served in place of the usage text.
+    let response = client.get("/").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let content_type = response.headers()[CONTENT_TYPE].to_str()?;
+    assert!(content_type.starts_with("text/html"));
+    assert_eq!(response.text().await, page);
+
+    // Test that it's served under the API version too.
+    let response = client.get("/api/v1").send().await;
+    assert_eq!(response.text().await, page);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_usage() -> Result<()> {
+    let usage = usage::usage();
+
+    // Test that routes are documented with their method.
+    assert!(usage.contains("      DELETE /<id>"));
+    assert!(usage.contains("      GET /<id>/<lang>/html"));
+
+    // Test that every documented route is actually routed. Unrouted paths get
+    // axum's bare 404, while ours say what's missing.
+    let router = make_router(None, None).with_state(App::mock());
+    for route in usage::ROUTES {
+        let path = route.path.split('?').next().unwrap_or_default();
+        let path = path
+            .replace("<id>", &Uuid::new_v4().to_string())
+            .replace("<other-id>", &Uuid::new_v4().to_string())
+            .replace("<lang>", "rs")
+            .replace("<name>", "nothing-here");
+        let request = Request::builder()
+            .method(route.method)
+            .uri(&path)
+            .body(Body::empty())?;
+        let response = router.clone().oneshot(request).await?;
+
+        let unrouted = response.status() == StatusCode::METHOD_NOT_ALLOWED
+            || (response.status() == StatusCode::NOT_FOUND
+                && !response.headers().contains_key(CONTENT_TYPE));
+        assert!(!unrouted, "{} {} isn't routed", route.method, route.path);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_api_version() -> Result<()> {
+    let client = get_client();
+
+    // Test that a paste uploaded to either the versioned or legacy routes can
+    // be read from both, the second upload being of the same paste.
+    for (upload, status) in [("/api/v1", StatusCode::CREATED), ("/", StatusCode::OK)] {
+        let response = client.post(upload).body("Versioned").send().await;
+        assert_eq!(response.status(), status);
+        let uri = response.text().await.parse::<Uri>()?;
+        for read in ["/api/v1", ""] {
+            let response = client.get(&format!("{}{}", read, uri.path())).send().await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.text().await, "Versioned");
+        }
+    }
+
+    // Test that routes with more to their path are versioned too.
+    let response = client.get("/api/v1/languages").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_base_path() -> Result<()> {
+    let router = make_router(None, Some("/paste")).with_state(App::mock());
+    let client = TestClient::new(router);
+
+    // Test that the routes are under the base path.
+    let response = client.get("/paste/").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, usage::usage());
+    let response = client.get("/").send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Test that uploads link to pastes under it.
+    let response = client.post("/paste/").body("Under a path").send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    assert!(uri.path().starts_with("/paste/"));
+    let response = client.get(uri.path()).send().await;
+    assert_eq!(response.text().await, "Under a path");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_add_get() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste to upload then retrieve.
+    let paste = "This is a paste!";
+
+    // Test that post succeeds.
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that get succeeds.
+    let response = client.get(id).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, paste);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_location() -> Result<()> {
+    let client = get_client();
+
+    // Test that the new paste is pointed to, as the body says.
+    let response = client.post("/").body("Look here").send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let location = response.headers()[LOCATION].to_str()?.to_string();
+    assert_eq!(location, response.text().await);
+
+    // Test that it is for JSON too.
+    let response = client
+        .post("/")
+        .header(ACCEPT, "application/json")
+        .body("Look over here")
+        .send()
+        .await;
+    let location = response.headers()[LOCATION].to_str()?.to_string();
+    assert_eq!(location, response.json::<Value>().await["url"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_add_empty() -> Result<()> {
+    let client = get_client();
+
+    // Test that pastes with nothing to them are taken to be a mistake.
+    for body in ["", " \n\t\r\n"] {
+        let response = client.post("/").body(body).send().await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error = response.json::<Value>().await;
+        assert_eq!(error["error"], "Paste content must not be empty");
+    }
+    let response = client
+        .post("/")
+        .json(&json!({ "content": "" }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Test that they're accepted when meant.
+    let response = client.post("/?allow_empty=true").body("").send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let uri = response.text().await.parse::<Uri>()?;
+    let response = client.get(uri.path()).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, "");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_add_json() -> Result<()> {
+    let client = get_client();
+
+    // Test that curl and friends get just the URL, as plain text.
+    let response = client.post("/").body("plain old URL").send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let content_type = response.headers()[CONTENT_TYPE].to_str()?;
+    assert!(content_type.starts_with("text/plain"));
+    let url = response.text().await;
+    assert!(url.parse::<Uri>()?.path().len() > 1);
+
+    // Test that clients that want JSON get the paste described as JSON.
+    let response = client
+        .post("/")
+        .header(ACCEPT, "application/json")
+        .body("URL and all")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert!(response.headers().contains_key(DELETE_TOKEN));
+    let created = response.json::<Value>().await;
+    let url = created["url"].as_str().unwrap_or_default().parse::<Uri>()?;
+    assert_eq!(url.path().trim_start_matches('/'), created["id"]);
+    let created_at = created["created_at"].as_str().unwrap_or_default();
+    assert!(created_at.parse::<DateTime<Utc>>()? >= mock::created_at());
+    let response = client.get(url.path()).send().await;
+    assert_eq!(response.text().await, "URL and all");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_add_json_body() -> Result<()> {
+    let client = get_client();
+
+    // Test that a JSON body with nothing but content is a plain paste.
+    let response = client
+        .post("/")
+        .json(&json!({ "content": "Just content" }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let uri = response.text().await.parse::<Uri>()?;
+    let response = client.get(uri.path()).send().await;
+    assert_eq!(response.text().await, "Just content");
+
+    // Test that the options in the body are applied, like those in the query.
+    let response = client
+        .post("/")
+        .header(ACCEPT, "application/json")
+        .json(&json!({
+            "content": "fn main() {}\n",
+            "language": "rs",
+            "ttl": 3600,
+            "name": "from-json",
+            "password": "hunter2",
+        }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created = response.json::<Value>().await;
+    assert!(created["url"]
+        .as_str()
+        .is_some_and(|url| url.ends_with("/p/from-json")));
+    let response = client.get("/p/from-json").send().await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let id = created["id"].as_str().unwrap_or_default();
+    let response = client
+        .get(&format!("/raw/{}?password=hunter2", id))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let disposition = format!("inline; filename=\"{}.rs\"", id);
+    assert_eq!(
+        response.headers()[CONTENT_DISPOSITION],
+        disposition.as_str()
+    );
+    assert_eq!(response.text().await, "fn main() {}\n");
+
+    // Test that bad bodies, and bad options, are rejected.
+    for body in [
+        json!({ "language": "rs" }),
+        json!({ "content": "Typo", "lang": "rs" }),
+    ] {
+        let response = client.post("/").json(&body).send().await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    for body in [
+        json!({ "content": "Unknown", "language": "notalanguage" }),
+        json!({ "content": "NUL\u{0}" }),
+    ] {
+        let response = client.post("/").json(&body).send().await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_content_sha256() -> Result<()> {
+    let client = get_client();
+
+    let response = client.post("/").body("hello world").send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that the digest of the content is given, as computed elsewhere.
+    let digest = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+    let response = client.get(id).send().await;
+    assert_eq!(response.headers()[CONTENT_SHA256], digest);
+
+    // Test that it's of the content as stored, even when highlighted.
+    let response = client.get(&format!("{}?highlight=auto", id)).send().await;
+    assert_eq!(response.headers()[CONTENT_SHA256], digest);
+    let response = client
+        .get(id)
+        .header(ACCEPT, "application/json")
+        .send()
+        .await;
+    assert_eq!(response.headers()[CONTENT_SHA256], digest);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_random() -> Result<()> {
+    let client = get_client();
+
+    // Test that there's nothing to pick from at first.
+    let response = client.get("/random").send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Test that private pastes aren't picked, even if public.
+    let response = client
+        .post("/?public=true&password=hunter2")
+        .body("secret")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = client
+        .post("/?public=true&burn=true")
+        .body("fleeting")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = client.get("/random").send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Test that pastes that aren't public aren't picked, though they can
+    // still be read by id.
+    let response = client.post("/").body("unlisted").send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let response = client.get("/random").send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client.get(uri.path()).send().await;
+    assert_eq!(response.text().await, "unlisted");
+
+    // Test that a public paste is redirected to.
+    let response = client.post("/?public=true").body("public").send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let response = client.get("/random").send().await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    let location = response.headers()[LOCATION].to_str()?;
+    assert_eq!(location, uri.path());
+    let response = client.get(location).send().await;
+    assert_eq!(response.text().await, "public");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_get_non_existent() -> Result<()> {
+    let client = get_client();
+
+    // Test that get fails the way we expect.
+    let id = Uuid::new_v4();
+    let response = client.get(&format!("/{}", id)).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_batch() -> Result<()> {
+    let client = get_client();
+
+    // Upload some pastes, one of them protected.
+    let mut ids = Vec::new();
+    for path in ["/", "/", "/?password=hunter2"] {
+        let body = format!("Paste number {}", ids.len());
+        let response = client.post(path).body(body).send().await;
+        let uri = response.text().await.parse::<Uri>()?;
+        ids.push(uri.path()[1..].parse::<Uuid>()?);
+    }
+    let missing = Uuid::new_v4();
+
+    // Test that only the readable pastes come back, in the order asked for.
+    let response = client
+        .post("/batch")
+        .json(&[ids[1], missing, ids[0], ids[2]])
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let pastes = response.json::<Value>().await;
+    let pastes = pastes.as_array().unwrap();
+    assert_eq!(pastes.len(), 2);
+    assert_eq!(pastes[0]["id"], ids[1].to_string());
+    assert_eq!(pastes[0]["content"], "Paste number 1");
+    assert_eq!(pastes[1]["id"], ids[0].to_string());
+
+    // Test that reading them in bulk counts as a view.
+    let response = client.get(&format!("/{}/meta", ids[0])).send().await;
+    assert_eq!(response.json::<Value>().await["views"], 1);
+
+    // Test that batches are capped.
+    let response = client.post("/batch").json(&vec![missing; 101]).send().await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_malformed_id() -> Result<()> {
+    let client = get_client();
+
+    // Test that ids that aren't UUIDs get an explanation, whatever the route.
+    for path in [
+        "/garbage",
+        "/garbage/meta",
+        "/garbage/rs/html",
+        "/raw/garbage",
+    ] {
+        let response = client.get(path).send().await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", path);
+        let body = response.json::<Value>().await;
+        let error = body["error"].as_str().unwrap_or_default();
+        assert!(error.contains("`garbage` isn't a paste id"), "{}", error);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_head() -> Result<()> {
+    let client = get_client();
+
+    // Upload a paste whose size in bytes differs from its length in chars.
+    let response = client.post("/").body("Größe zählt").send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that head gives the size of the paste, without its content.
+    let response = client.head(id).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[CONTENT_TYPE],
+        "text/plain; charset=utf-8"
+    );
+    let length = response.headers()[CONTENT_LENGTH]
+        .to_str()?
+        .parse::<usize>()?;
+    assert!(response.bytes().await.is_empty());
+
+    // Test that the size matches what get responds with.
+    let response = client.get(id).send().await;
+    assert_eq!(response.bytes().await.len(), length);
+
+    // Test that head fails for pastes that don't exist.
+    let response = client.head(&format!("/{}", Uuid::new_v4())).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_delete() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste to upload then retrieve.
+    let paste = "This is a paste!";
+
+    // Test that post succeeds.
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id and delete token from the response.
+    let token = response.headers()[DELETE_TOKEN].to_str()?.to_owned();
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that get succeeds.
+    let response = client.get(id).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, paste);
+
+    // Test that delete is forbidden without the token, or with the wrong one.
+    let response = client.delete(id).send().await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client.delete(&format!("{}?token=wrong", id)).send().await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .delete(id)
+        .header(AUTHORIZATION, "Bearer wrong")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Test that the paste survived.
+    let response = client.get(id).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Test that delete succeeds with the token.
+    let response = client
+        .delete(&format!("{}?token={}", id, token))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Test that get fails the way we expect.
+    let response = client.get(id).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_delete_with_header() -> Result<()> {
+    let client = get_client();
+
+    let response = client.post("/").body("Delete me!").send().await;
+    let token = response.headers()[DELETE_TOKEN].to_str()?.to_owned();
+    let uri = response.text().await.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that the token can also be given as a bearer token.
+    let response = client
+        .delete(id)
+        .header(AUTHORIZATION, format!("Bearer {}", token))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.get(id).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Test that uploading the same content again issues a new token, since the
+    // old paste is gone.
+    let response = client.post("/").body("Delete me!").send().await;
+    assert!(response.headers().contains_key(DELETE_TOKEN));
+
+    // Test that a duplicate upload isn't handed the existing paste's token.
+    let response = client.post("/").body("Delete me!").send().await;
+    assert!(!response.headers().contains_key(DELETE_TOKEN));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_delete_non_existent() -> Result<()> {
+    let client = get_client();
+
+    // Test that get fails the way we expect.
+    let id = Uuid::new_v4();
+    let response = client.delete(&format!("/{}", id)).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_expired_paste() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste that expires immediately.
+    let response = client
+        .post("/?ttl=0")
+        .body("This paste is gone!".to_string())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that the expired paste is treated as missing.
+    let response = client.get(id).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_meta() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste to retrieve metadata for.
+    let paste = "This is a paste!";
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that the metadata describes the paste.
+    let response = client.get(&format!("{}/meta", id)).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let meta = response.json::<Value>().await;
+    assert_eq!(meta["id"], id.trim_start_matches('/'));
+    let created_at = meta["created_at"].as_str().unwrap_or_default();
+    assert_eq!(created_at.parse::<DateTime<Utc>>()?, mock::created_at());
+    assert_eq!(meta["size"], paste.len());
+
+    // Test that only the metadata is sent, not the content.
+    let mut fields: Vec<_> = meta
+        .as_object()
+        .into_iter()
+        .flat_map(|o| o.keys())
+        .collect();
+    fields.sort();
+    assert_eq!(fields, ["created_at", "id", "size", "title", "views"]);
+    assert_eq!(meta["title"], Value::Null);
+
+    // Test that reading the metadata isn't reading the paste.
+    let response = client.get(&format!("{}/meta", id)).send().await;
+    assert_eq!(response.json::<Value>().await["views"], 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_title() -> Result<()> {
+    let client = get_client();
+
+    // Create titled pastes, by query and by JSON body.
+    let response = client
+        .post("/?title=Hello%20world")
+        .body("fn main() {}")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let by_query = response.text().await.parse::<Uri>()?;
+    let response = client
+        .post("/")
+        .json(&json!({ "content": "print('hi')", "title": "Héllo wörld" }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let by_json = response.text().await.parse::<Uri>()?;
+
+    // Test that the titles are given with the metadata.
+    for (uri, title) in [(by_query, "Hello world"), (by_json, "Héllo wörld")] {
+        let response = client.get(&format!("{}/meta", uri.path())).send().await;
+        assert_eq!(response.json::<Value>().await["title"], title);
+    }
+
+    // Test that titles are limited in characters, not bytes.
+    let title = "é".repeat(200);
+    let response = client
+        .post("/")
+        .json(&json!({ "content": "Long title", "title": title }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = client
+        .post("/")
+        .json(&json!({ "content": "Too long title", "title": title + "é" }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_meta_non_existent() -> Result<()> {
+    let client = get_client();
+
+    // Test that get fails the way we expect.
+    let id = Uuid::new_v4();
+    let response = client.get(&format!("/{}/meta", id)).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_highlight_html() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste to highlight.
+    let paste = "fn main() { println!(\"Hello, world!\"); }";
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that the paste is highlighted as HTML.
+    let response = client.get(&format!("{}/rs/html", id)).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let content_type = response.headers()[CONTENT_TYPE].to_str()?;
+    assert!(content_type.starts_with("text/html"));
+    assert!(response.text().await.contains("<span"));
+
+    // Test that line numbers are added only when asked for.
+    let path = format!("{}/rs/html?linenos=true", id);
+    let response = client.get(&path).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = response.text().await;
+    assert_eq!(
+        html.matches("class=\"lineno\"").count(),
+        paste.lines().count()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_highlight_html_default() -> Result<()> {
+    let client = &get_client();
+    let upload = |path: &'static str, body: &'static str| async move {
+        let response = client.post(path).body(body).send().await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        Ok::<_, anyhow::Error>(response.text().await.parse::<Uri>()?.path().to_string())
+    };
+    let html = |path: String| async move {
+        let response = client.get(&path).send().await;
+        assert_eq!(response.status(), StatusCode::OK);
+        response.text().await
+    };
+
+    // Test that the language a paste was uploaded as is used.
+    let id = upload("/?lang=rs", "fn main() {}").await?;
+    let stored = html(format!("{}/html", id)).await;
+    assert_eq!(stored, html(format!("{}/rs/html", id)).await);
+
+    // Test that an explicit language still overrides it.
+    assert_ne!(stored, html(format!("{}/py/html", id)).await);
+
+    // Test that otherwise the language is detected, falling back to plain text.
+    let id = upload("/", "#!/usr/bin/env python\nprint('hi')\n").await?;
+    let detected = html(format!("{}/html", id)).await;
+    assert_eq!(detected, html(format!("{}/py/html", id)).await);
+    let id = upload("/", "just some words").await?;
+    let plain = html(format!("{}/html", id)).await;
+    assert_eq!(plain, html(format!("{}/txt/html", id)).await);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_embed() -> Result<()> {
+    let client = get_client();
+
+    let response = client
+        .post("/?lang=rs")
+        .body("fn embedded() {}")
+        .send()
+        .await;
+    let url = response.text().await;
+    let id = url.parse::<Uri>()?.path().to_string();
+
+    // Test that the paste is highlighted, styled inline, as a fragment.
+    let response = client.get(&format!("{}/embed", id)).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers();
+    assert_eq!(headers[CONTENT_TYPE], "text/html; charset=utf-8");
+    assert!(!headers.contains_key("x-frame-options"));
+    let policy = headers[CONTENT_SECURITY_POLICY].to_str()?;
+    assert!(policy.contains("frame-ancestors *"));
+    assert!(policy.contains("default-src 'none'"));
+    let html = response.text().await;
+    assert!(html.starts_with("<div class=\"pstrs-embed\">"));
+    assert!(html.contains("<span style=\""));
+    assert!(!html.contains("<html"));
+
+    // Test that it links back to the paste.
+    let highlighted = client.get(&format!("{}/html", id)).send().await;
+    let highlighted = highlighted.text().await;
+    assert!(html.starts_with(&format!("<div class=\"pstrs-embed\">\n{}", highlighted)));
+    assert!(html.contains("<a href=\""));
+
+    // Test that it's never classed, since embedders don't have our stylesheet.
+    let response = client
+        .get(&format!("{}/embed?classes=true", id))
+        .send()
+        .await;
+    assert!(!response.text().await.contains("class=\"source"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_highlight_full() -> Result<()> {
+    let client = get_client();
+
+    let response = client.post("/").body("fn full() {}").send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that the code is wrapped in a page only when asked to be.
+    let path = format!("{}/rs/html", id);
+    let code = client.get(&path).send().await.text().await;
+    assert!(!code.contains("<!DOCTYPE html>"));
+    let path = format!("{}/rs/html?full=true&theme=InspiredGitHub", id);
+    let response = client.get(&path).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = response.text().await;
+    assert!(page.starts_with("<!DOCTYPE html>"));
+    assert!(page.contains("background-color: #ffffff;"));
+    assert!(page.contains("full"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_highlight_accept() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste to highlight.
+    let paste = "fn main() {}";
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let path = format!("{}/rs", uri.path());
+
+    // Test that browsers get HTML.
+    let response = client
+        .get(&path)
+        .header(ACCEPT, "text/html,application/xhtml+xml;q=0.9")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let content_type = response.headers()[CONTENT_TYPE].to_str()?;
+    assert!(content_type.starts_with("text/html"));
+    assert!(response.text().await.contains("<span"));
+
+    // Test that an explicit request for text gets terminal escapes.
+    let response = client.get(&path).header(ACCEPT, "text/plain").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let content_type = response.headers()[CONTENT_TYPE].to_str()?;
+    assert!(content_type.starts_with("text/plain"));
+    assert!(response.text().await.contains("\x1b["));
+
+    // Test that no Accept header at all gets terminal escapes.
+    let response = client.get(&path).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let content_type = response.headers()[CONTENT_TYPE].to_str()?;
+    assert!(content_type.starts_with("text/plain"));
+    assert!(response.text().await.contains("\x1b["));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_themes() -> Result<()> {
+    let client = get_client();
+
+    // Test that the loaded themes are listed.
+    let response = client.get("/themes").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let themes = response.json::<Vec<String>>().await;
+    assert!(themes.iter().any(|t| t == "InspiredGitHub"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_highlight_term() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste to highlight.
+    let paste = "fn main() {}\n";
+    let response = client.post("/").body(paste.to_string()).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let path = format!("{}/rs?term=true", uri.path());
+
+    // Test that output is reset at the start and the very end.
+    let response = client.get(&path).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let content_type = response.headers()[CONTENT_TYPE].to_str()?;
+    assert!(content_type.starts_with("text/plain"));
+    let output = response.text().await;
+    assert!(output.starts_with("\x1b[0m"));
+    assert!(output.ends_with("\n\x1b[0m"));
+
+    // Test that it's terminal output even for clients that accept HTML.
+    let response = client.get(&path).header(ACCEPT, "text/html").send().await;
+    let output = response.text().await;
+    assert!(output.starts_with("\x1b[0m"));
+    assert!(!output.contains("<pre"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_highlight_theme() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste to highlight.
+    let paste = "fn main() {}";
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that a known theme is used.
+    let path = format!("{}/rs/html?theme=InspiredGitHub", id);
+    let response = client.get(&path).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let inspired = response.text().await;
+
+    // Test that no theme means the default.
+    let response = client.get(&format!("{}/rs/html", id)).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(inspired, response.text().await);
+
+    // Test that an unknown theme is rejected, listing the ones there are, on
+    // every route that takes a theme.
+    for path in ["", "/rs", "/rs/html", "/html"] {
+        let path = format!("{}{}?theme=not-a-theme", id, path);
+        let response = client.get(&path).send().await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error = response.text().await;
+        assert!(error.contains("not-a-theme"));
+        assert!(error.contains("InspiredGitHub"));
+        assert!(error.contains(DEFAULT_THEME));
+    }
+    let response = client.get("/highlight.css?theme=not-a-theme").send().await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_languages() -> Result<()> {
+    let client = get_client();
+
+    // Test that Rust is among the listed languages.
+    let response = client.get("/languages").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let languages = response.json::<Vec<Value>>().await;
+    let rust = languages.iter().find(|l| {
+        l["extensions"]
+            .as_array()
+            .is_some_and(|e| e.contains(&"rs".into()))
+    });
+    assert_eq!(rust.map(|l| &l["name"]), Some(&"Rust".into()));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_add_get_slug() -> Result<()> {
+    let client = get_client();
+
+    // Create a named paste to upload then retrieve.
+    let paste = "This is a paste!";
+    let response = client
+        .post("/?name=mycoolpaste")
+        .body(paste.to_string())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Test that the URL uses the name.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    assert_eq!(uri.path(), "/p/mycoolpaste");
+
+    // Test that get by name succeeds.
+    let response = client.get("/p/mycoolpaste").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, paste);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_get_non_existent_slug() -> Result<()> {
+    let client = get_client();
+
+    // Test that get fails the way we expect.
+    let response = client.get("/p/nothing-here").send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_slug_conflict() -> Result<()> {
+    let client = get_client();
+
+    // Claim a name.
+    let response = client
+        .post("/?name=taken")
+        .body("First!".to_string())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Test that claiming it again conflicts.
+    let response = client
+        .post("/?name=taken")
+        .body("Second!".to_string())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let error = response.json::<Value>().await;
+    assert_eq!(error["status"], 409);
+    assert_eq!(error["error"], "A paste with that name already exists");
+
+    // Test that the original paste is untouched.
+    let response = client.get("/p/taken").send().await;
+    assert_eq!(response.text().await, "First!");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_add_too_large() -> Result<()> {
+    let client = get_client_with(App {
+        max_paste_size: 16,
+        ..App::mock()
+    });
+
+    // Test that a paste at the limit is fine.
+    let response = client.post("/").body("a".repeat(16)).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Test that a paste over the limit is rejected with a helpful message.
+    let response = client.post("/").body("a".repeat(17)).send().await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(response.text().await.contains("16 bytes"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_add_over_body_limit() -> Result<()> {
+    let client = get_client();
+
+    // Test that bodies too big to even buffer get the same treatment.
+    let response = client.post("/").body("a".repeat(4 << 20)).send().await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(response.text().await.contains("too large"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_add_streamed() -> Result<()> {
+    let client = get_client_with(App {
+        max_paste_size: 8 << 20,
+        ..App::mock()
+    });
+
+    // A paste bigger than axum would buffer, that's different all the way
+    // through, so that any chunk lost or out of order shows.
+    let paste: String = (0..500_000).map(|i| format!("{:x}\n", i)).collect();
+    assert!(paste.len() > 2 << 20);
+
+    // Test that a raw body is streamed in whole, however big, up to the limit.
+    let response = client.post("/").body(paste.clone()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let uri = response.text().await.parse::<Uri>()?;
+    let response = client.get(&format!("/raw{}", uri.path())).send().await;
+    let content = response.text().await;
+    assert_eq!(content.len(), paste.len());
+    assert!(content == paste);
+
+    // Test that it's still refused past the limit.
+    let response = client.post("/").body("a".repeat(9 << 20)).send().await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    Ok(())
+}
+
+// Gzip `content`, as a client uploading with `Content-Encoding: gzip` would.
+fn gzip(content: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content)?;
+
+    Ok(encoder.finish()?)
+}
+
+#[tokio::test]
+async fn test_add_gzip() -> Result<()> {
+    let client = get_client_with(App {
+        max_paste_size: 1024,
+        ..App::mock()
+    });
+    let paste = "Hello, world!\n".repeat(64);
+
+    // Test that a gzipped body is stored decompressed.
+    let response = client
+        .post("/")
+        .header(CONTENT_ENCODING, "gzip")
+        .body(gzip(paste.as_bytes())?)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let uri = response.text().await.parse::<Uri>()?;
+    let response = client.get(&format!("/raw{}", uri.path())).send().await;
+    assert_eq!(response.text().await, paste);
+
+    // Test that the limit applies to the decompressed size, however small the
+    // body is.
+    let response = client
+        .post("/")
+        .header(CONTENT_ENCODING, "gzip")
+        .body(gzip(&[b'a'; 1 << 20])?)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Test that a body that isn't really gzipped, or in an encoding we can't
+    // decode, is rejected.
+    let response = client
+        .post("/")
+        .header(CONTENT_ENCODING, "gzip")
+        .body(paste.clone())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client
+        .post("/")
+        .header(CONTENT_ENCODING, "br")
+        .body(paste)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_idempotency_key() -> Result<()> {
+    let client = get_client();
+    let upload = |key: &'static str, body: &'static str| {
+        client
+            .post("/")
+            .header(IDEMPOTENCY_KEY, key)
+            .body(body)
+            .send()
+    };
+
+    let response = upload("retry-me", "first try").await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let token = response.headers()[DELETE_TOKEN].clone();
+    let first = response.text().await;
+
+    // Test that a retry with the same key gets the same paste, not a new one.
+    let response = upload("retry-me", "first try").await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(response.headers()[DELETE_TOKEN], token);
+    assert_eq!(response.text().await, first);
+    let response = upload("retry-me", "second try").await;
+    assert_eq!(response.text().await, first);
+    let response = client.get(first.parse::<Uri>()?.path()).send().await;
+    assert_eq!(response.text().await, "first try");
+
+    // Test that another key gets another paste, even for the same content.
+    let response = upload("another", "first try").await;
+    assert_ne!(response.headers()[DELETE_TOKEN], token);
+    assert_ne!(response.text().await, first);
+
+    // Test that a retry after the first response was lost gets a token that
+    // deletes the paste.
+    drop(upload("dropped", "lost response").await);
+    let response = upload("dropped", "lost response").await;
+    let token = response.headers()[DELETE_TOKEN].to_str()?.to_owned();
+    let uri = response.text().await.parse::<Uri>()?;
+    let path = format!("{}?token={}", uri.path(), token);
+    let response = client.delete(&path).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.get(uri.path()).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Test that keys that can't be what a client meant are rejected.
+    let response = upload("", "first try").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = client
+        .post("/")
+        .header(IDEMPOTENCY_KEY, "k".repeat(256))
+        .body("first try")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_blocked_content() -> Result<()> {
+    let client = get_client_with(App {
+        content_filter: Arc::new(ContentFilter::new(["(?i)buy cheap \\w+"])?),
+        ..App::mock()
+    });
+
+    // Test that content matching a pattern is refused.
+    let response = client.post("/").body("Buy cheap watches!").send().await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    // Test that unrelated content passes.
+    let response = client.post("/").body("I bought a watch").send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let token = response.headers()[DELETE_TOKEN].to_str()?.to_owned();
+    let uri = response.text().await.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that it can't be snuck in afterwards either.
+    let response = client
+        .put(&format!("{}?token={}", id, token))
+        .body("buy cheap pills")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let response = client
+        .post(&format!("{}/append?token={}", id, token))
+        .body(", buy cheap")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .post(&format!("{}/append?token={}", id, token))
+        .body(" pills")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_add_binary() -> Result<()> {
+    let client = get_client();
+
+    // Test that content Postgres can't store is rejected with a helpful message.
+    let response = client.post("/").body(b"hello\0world".to_vec()).send().await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response.text().await.contains("NUL"));
+
+    // Test that so is content that isn't text at all, as a form too.
+    let response = client.post("/").body(vec![0xff, 0xfe, 0xfd]).send().await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response.text().await.contains("UTF-8"));
+    let mut form = b"--boundary\r\n\
+                     Content-Disposition: form-data; name=\"content\"\r\n\
+                     \r\n"
+        .to_vec();
+    form.extend_from_slice(&[0xff, 0xfe, 0xfd]);
+    form.extend_from_slice(b"\r\n--boundary--\r\n");
+    let response = client
+        .post("/")
+        .header(CONTENT_TYPE, "multipart/form-data; boundary=boundary")
+        .body(form)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response.text().await.contains("UTF-8"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_password() -> Result<()> {
+    let client = get_client();
+
+    // Create a password protected paste.
+    let paste = "This is a secret!";
+    let response = client
+        .post("/?password=hunter2")
+        .body(paste.to_string())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that the right password unlocks the paste.
+    let response = client.get(&format!("{}?password=hunter2", id)).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, paste);
+
+    // Test that the wrong password doesn't.
+    let response = client.get(&format!("{}?password=hunter3", id)).send().await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Test that no password doesn't either, highlighted or not.
+    let response = client.get(id).send().await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client.get(&format!("{}/txt", id)).send().await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client.get(&format!("{}/txt/html", id)).send().await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Test that the metadata needs the password too.
+    let response = client.get(&format!("{}/meta", id)).send().await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client
+        .get(&format!("{}/meta?password=hunter3", id))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client
+        .get(&format!("{}/meta?password=hunter2", id))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.json::<Value>().await["size"], paste.len());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_burn() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste that burns after reading.
+    let paste = "This message will self-destruct.";
+    let response = client
+        .post("/?burn=true")
+        .body(paste.to_string())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that the first read succeeds.
+    let response = client.get(id).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, paste);
+
+    // Test that the second read finds nothing.
+    let response = client.get(id).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_views() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste to view.
+    let response = client
+        .post("/")
+        .body("Look at me!".to_string())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Read the paste twice.
+    for _ in 0..2 {
+        let response = client.get(id).send().await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // Test that both reads were counted, and that the metadata isn't.
+    for _ in 0..2 {
+        let response = client.get(&format!("{}/meta", id)).send().await;
+        assert_eq!(response.json::<Value>().await["views"], 2);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_update() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste to update.
+    let response = client.post("/").body("Before".to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id and delete token from the response.
+    let token = response.headers()[DELETE_TOKEN].to_str()?.to_owned();
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that put is forbidden without the token, or with the wrong one.
+    let response = client.put(id).body("Vandalized").send().await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .put(&format!("{}?token=wrong", id))
+        .body("Vandalized")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client.get(id).send().await;
+    assert_eq!(response.text().await, "Before");
+
+    // Test that put succeeds with the token and responds with the new content.
+    let response = client
+        .put(&format!("{}?token={}", id, token))
+        .body("After".to_string())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, "After");
+
+    // Test that the token can also be given as a bearer token.
+    let response = client
+        .put(id)
+        .header(AUTHORIZATION, format!("Bearer {}", token))
+        .body("After")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Test that get sees the new content.
+    let response = client.get(id).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, "After");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_update_non_existent() -> Result<()> {
+    let client = get_client();
+
+    // Test that put fails the way we expect.
+    let id = Uuid::new_v4();
+    let response = client
+        .put(&format!("/{}", id))
+        .body("Anyone home?".to_string())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_update_meta() -> Result<()> {
+    let client = get_client();
+    let response = client.post("/?lang=rs").body("fn main() {}").send().await;
+    let token = response.headers()[DELETE_TOKEN].to_str()?.to_owned();
+    let uri = response.text().await.parse::<Uri>()?;
+    let id = uri.path();
+    let path = format!("{}?token={}", id, token);
+
+    // Test that changing the metadata needs the paste's delete token.
+    let response = client
+        .patch(id)
+        .json(&json!({ "title": "Hijacked" }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .patch(&format!("{}?token=wrong", id))
+        .json(&json!({ "title": "Hijacked" }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Test that only the title changes when only it is given.
+    let response = client
+        .patch(&path)
+        .json(&json!({ "title": "Hello" }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let meta = response.json::<Value>().await;
+    assert_eq!(meta["title"], "Hello");
+    let response = client
+        .get(id)
+        .header(ACCEPT, "application/json")
+        .send()
+        .await;
+    let paste = response.json::<Value>().await;
+    assert_eq!(paste["content"], "fn main() {}");
+    assert_eq!(paste["language"], "rs");
+    assert_eq!(paste["title"], "Hello");
+
+    // Test that the language can be changed too, but only to one there is, and
+    // that the token may be given as a bearer token instead.
+    let response = client
+        .patch(id)
+        .header(AUTHORIZATION, format!("Bearer {}", token))
+        .json(&json!({ "language": "py" }))
+        .send()
+        .await;
+    assert_eq!(response.json::<Value>().await["title"], "Hello");
+    let response = client
+        .patch(&path)
+        .json(&json!({ "language": "nope" }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Test that there has to be a paste to change.
+    let response = client
+        .patch(&format!("/{}", Uuid::new_v4()))
+        .json(&json!({ "title": "Anyone home?" }))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_append() -> Result<()> {
+    let client = get_client_with(App {
+        max_paste_size: 16,
+        ..App::mock()
+    });
+
+    // Create a paste to append to.
+    let response = client.post("/").body("one\n").send().await;
+    let token = response.headers()[DELETE_TOKEN].to_str()?.to_owned();
+    let uri = response.text().await.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that appending needs the paste's delete token.
+    let path = format!("{}/append", id);
+    let response = client.post(&path).body("two\n").send().await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .post(&format!("{}?token=wrong", path))
+        .body("two\n")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Test that appends add onto the end, in order, responding with the new size
+    // rather than the content.
+    let path = format!("{}/append?token={}", id, token);
+    let response = client.post(&path).body("two\n").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, "8");
+    let response = client
+        .post(&format!("{}/append", id))
+        .header(AUTHORIZATION, format!("Bearer {}", token))
+        .body("three\n")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.get(id).send().await;
+    assert_eq!(response.text().await, "one\ntwo\nthree\n");
+
+    // Test that the paste can't grow past the size limit.
+    let response = client.post(&path).body("four\n").send().await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Test that appending to a paste that doesn't exist fails.
+    let path = format!("/{}/append", Uuid::new_v4());
+    let response = client.post(&path).body("Anyone home?").send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Test that password protected and burn after reading pastes are refused,
+    // even with the token, and left as they were.
+    for options in ["password=hunter2", "burn=true"] {
+        let response = client
+            .post(&format!("/?{}", options))
+            .body("one\n")
+            .send()
+            .await;
+        let token = response.headers()[DELETE_TOKEN].to_str()?.to_owned();
+        let uri = response.text().await.parse::<Uri>()?;
+        let id = uri.path();
+
+        let response = client
+            .post(&format!("{}/append?token={}", id, token))
+            .body("two\n")
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert!(!response.text().await.contains("one"));
+        let response = client.get(&format!("{}?password=hunter2", id)).send().await;
+        assert_eq!(response.text().await, "one\n");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_fork() -> Result<()> {
+    let client = get_client();
+
+    // Create a plain paste, which identical uploads are deduplicated to.
+    let paste = "Fork me!";
+    let response = client.post("/").body(paste).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let original = response.text().await.parse::<Uri>()?;
+
+    // Test that the fork is a new paste, with identical content.
+    let response = client
+        .post(&format!("{}/fork", original.path()))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert!(response.headers().contains_key(DELETE_TOKEN));
+    let fork = response.text().await.parse::<Uri>()?;
+    assert_ne!(fork.path(), original.path());
+    let response = client.get(fork.path()).send().await;
+    assert_eq!(response.text().await, paste);
+
+    // Test that the fork keeps the language, so is highlighted the same.
+    let response = client.post("/?lang=rs").body("fn main() {}").send().await;
+    let rust = response.text().await.parse::<Uri>()?;
+    let response = client.post(&format!("{}/fork", rust.path())).send().await;
+    let fork = response.text().await.parse::<Uri>()?;
+    let response = client.get(&format!("/raw{}", fork.path())).send().await;
+    let disposition = format!("inline; filename=\"{}.rs\"", &fork.path()[1..]);
+    assert_eq!(
+        response.headers()[CONTENT_DISPOSITION],
+        disposition.as_str()
+    );
+
+    // Test that pastes that don't exist can't be forked.
+    let response = client
+        .post(&format!("/{}/fork", Uuid::new_v4()))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_empty_slug() -> Result<()> {
+    let client = get_client();
+
+    // Test that an empty name is rejected with a JSON error.
+    let response = client
+        .post("/?name=")
+        .body("Nameless".to_string())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error = response.json::<Value>().await;
+    assert_eq!(error["status"], 400);
+    assert_eq!(error["error"], "Paste name must not be empty");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gzip() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste big enough to be worth compressing.
+    let paste = "All work and no play makes Jack a dull boy.\n".repeat(1000);
+    let response = client.post("/").body(paste.clone()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that the paste is compressed when the client accepts gzip.
+    let response = client.get(id).header(ACCEPT_ENCODING, "gzip").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+    assert!(response.bytes().await.len() < paste.len());
+
+    // Test that it isn't when the client doesn't.
+    let response = client.get(id).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key(CONTENT_ENCODING));
+    assert_eq!(response.text().await, paste);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_brotli() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste big enough to be worth compressing.
+    let paste = "All work and no play makes Jack a dull boy.\n".repeat(1000);
+    let response = client.post("/").body(paste.clone()).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that the paste is compressed when the client accepts brotli.
+    let response = client.get(id).header(ACCEPT_ENCODING, "br").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_ENCODING], "br");
+    assert!(response.bytes().await.len() < paste.len());
+
+    // Test that brotli is preferred when the client accepts gzip too.
+    let response = client
+        .get(id)
+        .header(ACCEPT_ENCODING, "gzip, br")
+        .send()
+        .await;
+    assert_eq!(response.headers()[CONTENT_ENCODING], "br");
+
+    // Test that the client still gets its way when it prefers gzip.
+    let response = client
+        .get(id)
+        .header(ACCEPT_ENCODING, "gzip, br;q=0.5")
+        .send()
+        .await;
+    assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+
+    Ok(())
+}
+
+// The fields of a span or event, formatted as strings.
+#[derive(Debug, Default)]
+struct Fields(HashMap<&'static str, String>);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.insert(field.name(), format!("{:?}", value));
+    }
+}
+
+// A tracing layer that records the fields of every span and event.
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<Fields>>>);
+
+impl<S: Subscriber> Layer<S> for Recorder {
+    fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        self.0.lock().unwrap().push(fields);
+    }
+
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        self.0.lock().unwrap().push(fields);
+    }
+}
+
+#[tokio::test]
+async fn test_trace() -> Result<()> {
+    let recorder = Recorder::default();
+    let _guard = tracing_subscriber::registry()
+        .with(recorder.clone())
+        .set_default();
+    let client = get_client();
+
+    // Create a paste to retrieve.
+    let response = client.post("/").body("Trace me!".to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    let response = client.get(id).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Test that the request got a span, and its response an event.
+    let records = recorder.0.lock().unwrap();
+    let span = records.iter().find(|f| {
+        f.0.get("method").is_some_and(|m| m == "GET")
+            && f.0.get("path").is_some_and(|p| p == id)
+    });
+    assert!(span.is_some());
+    let event = records
+        .iter()
+        .find(|f| f.0.get("status").is_some_and(|s| s == "200"));
+    assert!(event.is_some_and(|e| e.0.contains_key("latency_ms")));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_highlight_auto() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste with a shebang.
+    let paste = "#!/usr/bin/env python\nprint('Hello, world!')\n";
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that the language is detected and highlighted.
+    let response = client.get(&format!("{}?highlight=auto", id)).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-detected-language"], "Python");
+    assert!(response.text().await.contains("\x1b["));
+
+    // Test that without asking, the paste comes back as is.
+    let response = client.get(id).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, paste);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stored_language() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste tagged as Rust.
+    let paste = "fn main() {}\n";
+    let response = client
+        .post("/?lang=rs")
+        .body(paste.to_string())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that a plain retrieval is highlighted without repeating the language.
+    let response = client.get(id).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.text().await;
+    assert_ne!(body, paste);
+    assert!(body.contains("\x1b["));
+
+    // Test that unknown languages are rejected at upload.
+    let response = client
+        .post("/?lang=nope")
+        .body(paste.to_string())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_highlight_errors() -> Result<()> {
+    let broken = SyntaxDefinition::load_from_str(BROKEN_SYNTAX, true, None)?;
+    let mut builder = (*App::mock().syntax_set).clone().into_builder();
+    builder.add(broken);
+    let syntax_set = Arc::new(builder.build());
+    let client_with = |policy| {
+        get_client_with(App {
+            syntax_set: syntax_set.clone(),
+            highlight_errors: policy,
+            ..App::mock()
+        })
+    };
+    let paste = "before\nbroken!\nafter\n";
+
+    // Test that by default, the line is served as it is among the others.
+    let client = client_with(HighlightErrorPolicy::default());
+    let response = client.post("/").body(paste).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let path = format!("{}/broken", uri.path());
+    let response = client.get(&path).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let output = response.text().await;
+    assert!(output.contains("broken!") && output.contains("after"));
+    assert!(!output.contains("highlighting failed"));
+    let response = client.get(&format!("{}/html", path)).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.text().await.contains("broken!"));
+    let response = client
+        .get(&format!("{}/html?classes=true", path))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.text().await.contains("broken!"));
+
+    // Test that it can be a 500 instead, for HTML too.
+    let client = client_with(HighlightErrorPolicy::Fail);
+    let response = client.post("/").body(paste).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let path = format!("{}/broken", uri.path());
+    let response = client.get(&path).send().await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let response = client.get(&format!("{}/html", path)).send().await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let response = client
+        .get(&format!("{}/html?classes=true", path))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    // Test that it can be served with a comment saying what went wrong.
+    let client = client_with(HighlightErrorPolicy::Comment);
+    let response = client.post("/").body(paste).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let path = format!("{}/broken", uri.path());
+    let response = client.get(&path).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let output = response.text().await;
+    let line = output.lines().nth(1).unwrap_or_default();
+    assert!(line.starts_with("broken!  \x1b[2m# highlighting failed: "));
+    let response = client.get(&format!("{}/html", path)).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response
+        .text()
+        .await
+        .contains("broken!<!-- highlighting failed: "));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unknown_lang() -> Result<()> {
+    let paste = "#!/usr/bin/env python\nprint('Hello, world!')\n";
+    let client_with = |policy| {
+        get_client_with(App {
+            unknown_lang: policy,
+            ..App::mock()
+        })
+    };
+
+    // Test that by default, an unknown language gets the paste as is.
+    let client = client_with(UnknownLangPolicy::default());
+    let response = client.post("/").body(paste).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let path = format!("{}/nope", uri.path());
+    let response = client.get(&path).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, paste);
+
+    // Test that it can be a 404 instead, for HTML too.
+    let client = client_with(UnknownLangPolicy::NotFound);
+    let response = client.post("/").body(paste).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let path = format!("{}/nope", uri.path());
+    let response = client.get(&path).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let error = response.json::<Value>().await;
+    assert_eq!(error["error"], "Unknown language `nope`");
+    let response = client.get(&format!("{}/html", path)).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Test that known languages are still highlighted then.
+    let response = client.get(&format!("{}/py", uri.path())).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.text().await.contains("\x1b["));
+
+    // Test that a burn after reading paste survives being asked for as one.
+    let response = client.post("/?burn=true").body(paste).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    for route in ["nope", "nope/html"] {
+        let path = format!("{}/{}", uri.path(), route);
+        let response = client.get(&path).send().await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+    let response = client.get(uri.path()).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, paste);
+
+    // Test that the language can be detected instead.
+    let client = client_with(UnknownLangPolicy::Detect);
+    let response = client.post("/").body(paste).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let path = format!("{}/nope", uri.path());
+    let response = client.get(&path).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.text().await;
+    assert!(body.contains("\x1b["));
+    let plain = client_with(UnknownLangPolicy::PlainText);
+    let response = plain.post("/").body(paste).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let path = format!("{}/py", uri.path());
+    assert_eq!(plain.get(&path).send().await.text().await, body);
+
+    // Test that what can't be detected is still served as is.
+    let response = client.post("/").body("Just some words.\n").send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let path = format!("{}/nope", uri.path());
+    let response = client.get(&path).send().await;
+    assert_eq!(response.text().await, "Just some words.\n");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_allowed_langs() -> Result<()> {
+    let paste = "print('Hello, world!')\n";
+    let client_with = |policy| -> Result<TestClient> {
+        Ok(get_client_with(App {
+            allowed_langs: Arc::new("py,md".parse()?),
+            unknown_lang: policy,
+            ..App::mock()
+        }))
+    };
+
+    // Test that an allowed language is highlighted.
+    let client = client_with(UnknownLangPolicy::default())?;
+    let response = client.post("/").body(paste).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let response = client.get(&format!("{}/py", uri.path())).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.text().await.contains("\x1b["));
+
+    // Test that one that isn't gets the paste as is.
+    let response = client.get(&format!("{}/rs", uri.path())).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, paste);
+
+    // Test that it's rejected when unknown languages are.
+    let client = client_with(UnknownLangPolicy::NotFound)?;
+    let response = client.post("/").body(paste).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let response = client.get(&format!("{}/rs/html", uri.path())).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let error = response.json::<Value>().await;
+    assert_eq!(error["error"], "Highlighting as `rs` isn't allowed here");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_highlight_lines() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste to highlight.
+    let paste = "fn main() {\n    println!(\"Hello, world!\");\n}\n";
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that the selected lines are marked in the terminal.
+    let response = client.get(&format!("{}/rs?lines=2-3", id)).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await.matches("> ").count(), 2);
+
+    // Test that the selected lines are marked in HTML.
+    let response = client
+        .get(&format!("{}/rs/html?lines=1,3", id))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.text().await.matches("class=\"selected\"").count(),
+        2
+    );
+
+    // Test that malformed specs and lines past the end are rejected.
+    for lines in ["two", "3-1", "4", "2-10"] {
+        let path = format!("{}/rs/html?lines={}", id, lines);
+        let response = client.get(&path).send().await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    // Test that rejecting lines past the end doesn't burn the paste.
+    let response = client.post("/?burn=true").body(paste).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let id = uri.path();
+    for route in ["rs", "rs/html", "html", "embed"] {
+        let path = format!("{}/{}?lines=4", id, route);
+        let response = client.get(&path).send().await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+    let response = client.get(id).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, paste);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_highlight_lines_html() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste of numbered lines to cut an excerpt out of.
+    let paste: String = (1..=30)
+        .map(|n| format!("let line_{} = {};\n", n, n))
+        .collect();
+    let response = client.post("/").body(paste).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let uri = response.text().await.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that only the requested lines are rendered, numbered from the first.
+    let path = format!("{}/rs/html?lines=10-20&linenos=true", id);
+    let response = client.get(&path).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = response.text().await;
+    let gutter: Vec<_> = html
+        .split("class=\"lineno\"")
+        .skip(1)
+        .filter_map(|cell| cell.split_once('>')?.1.split('<').next())
+        .collect();
+    let expected: Vec<_> = (10..=20).map(|n| n.to_string()).collect();
+    assert_eq!(gutter, expected);
+    assert!(html.contains("line_10") && html.contains("line_20"));
+    assert!(!html.contains("line_9<") && !html.contains("line_21"));
+
+    // Test that the excerpt isn't marked when all of it was asked for.
+    assert!(!html.contains("class=\"selected\""));
+
+    // Test that lines are cut out without line numbers too.
+    let path = format!("{}/rs/html?lines=10-20", id);
+    let html = client.get(&path).send().await.text().await;
+    assert!(html.contains("line_10") && html.contains("line_20"));
+    assert!(!html.contains("line_9<") && !html.contains("line_21"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_raw() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste, with a language, and one without.
+    let paste = "fn main() {}\n";
+    let response = client
+        .post("/?lang=rs")
+        .body(paste.to_string())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let rust = response.text().await.parse::<Uri>()?;
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let text = response.text().await.parse::<Uri>()?;
+
+    // Test that the content comes back unhighlighted, named for its language.
+    let response = client.get(&format!("/raw{}", rust.path())).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers();
+    assert_eq!(headers[CONTENT_TYPE], "text/plain; charset=utf-8");
+    let disposition = format!("inline; filename=\"{}.rs\"", &rust.path()[1..]);
+    assert_eq!(headers[CONTENT_DISPOSITION], disposition.as_str());
+    assert_eq!(response.bytes().await, paste.as_bytes());
+
+    // Test that pastes without a language are named as text.
+    let response = client.get(&format!("/raw{}", text.path())).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let disposition = format!("inline; filename=\"{}.txt\"", &text.path()[1..]);
+    assert_eq!(
+        response.headers()[CONTENT_DISPOSITION],
+        disposition.as_str()
+    );
+    assert_eq!(response.bytes().await, paste.as_bytes());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_raw_content_type() -> Result<()> {
+    let client = &get_client();
+
+    // Fetch the content type a paste is served as raw.
+    let content_type = |path: &'static str, body: &'static str| async move {
+        let response = client.post(path).body(body).send().await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let uri = response.text().await.parse::<Uri>().unwrap();
+        let response = client.get(&format!("/raw{}", uri.path())).send().await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[X_CONTENT_TYPE_OPTIONS], "nosniff");
+        response.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_string()
+    };
+
+    // Test that JSON is detected, by language or by content.
+    assert_eq!(content_type("/?lang=json", "1").await, "application/json");
+    assert_eq!(
+        content_type("/", "{\"hello\": [\"world\"]}").await,
+        "application/json"
+    );
+
+    // Test that things that only look like JSON aren't.
+    let plain = "text/plain; charset=utf-8";
+    assert_eq!(content_type("/", "{not json}").await, plain);
+    assert_eq!(content_type("/", "42").await, plain);
+
+    // Test that HTML, or anything else browsers run, is never served as such,
+    // whether uploaded as it or not.
+    let html = "<script>alert(1)</script>";
+    assert_eq!(content_type("/?lang=html", html).await, plain);
+    assert_eq!(content_type("/?lang=js", "alert(1)").await, plain);
+    assert_eq!(content_type("/", "<html>hello</html>").await, plain);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_download() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste, with a language, and one without.
+    let paste = "print('hello')\n";
+    let response = client.post("/?lang=py").body(paste).send().await;
+    let python = response.text().await.parse::<Uri>()?;
+    let response = client.post("/").body(paste).send().await;
+    let text = response.text().await.parse::<Uri>()?;
+
+    // Test that the content comes back as an attachment, named for its
+    // language.
+    let response = client
+        .get(&format!("{}/download", python.path()))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let disposition = format!("attachment; filename=\"{}.py\"", &python.path()[1..]);
+    assert_eq!(
+        response.headers()[CONTENT_DISPOSITION],
+        disposition.as_str()
+    );
+    assert_eq!(response.bytes().await, paste.as_bytes());
+
+    // Test that pastes without a language are named as text.
+    let response = client
+        .get(&format!("{}/download", text.path()))
+        .send()
+        .await;
+    let disposition = format!("attachment; filename=\"{}.txt\"", &text.path()[1..]);
+    assert_eq!(
+        response.headers()[CONTENT_DISPOSITION],
+        disposition.as_str()
+    );
+
+    // Test that pastes that don't exist can't be downloaded.
+    let response = client
+        .get(&format!("/{}/download", Uuid::new_v4()))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_deduplicate() -> Result<()> {
+    let client = get_client();
+
+    // Create the same paste twice.
+    let paste = "Hello, world!";
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let token = response.headers()[DELETE_TOKEN].to_str()?.to_owned();
+    let first = response.text().await.parse::<Uri>()?;
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let second = response.text().await.parse::<Uri>()?;
+
+    // Test that both lead to the same paste.
+    assert_eq!(first.path(), second.path());
+
+    // Test that pastes with options of their own aren't deduplicated.
+    let response = client
+        .post("/?burn=true")
+        .body(paste.to_string())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let burn = response.text().await.parse::<Uri>()?;
+    assert_ne!(first.path(), burn.path());
+
+    // Test that once edited, the paste no longer takes in new uploads.
+    let path = format!("{}?token={}", first.path(), token);
+    let response = client.put(&path).body("Edited").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let third = response.text().await.parse::<Uri>()?;
+    assert_ne!(first.path(), third.path());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rate_limit() -> Result<()> {
+    // Use a small limit, so the test doesn't have to upload much.
+    let limit = 3;
+    let app = AppBuilder::new(MockPasteStore::arc())
+        .upload_limit(limit)
+        .upload_window(Duration::from_secs(60))
+        .build();
+    let client = get_client_with(app);
+
+    // Test that uploads are accepted up to the limit.
+    for i in 0..limit {
+        let response = client
+            .post("/")
+            .header("x-forwarded-for", "203.0.113.7")
+            .body(format!("Paste {}", i))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    // Test that the next one is turned away, with when to try again.
+    let response = client
+        .post("/")
+        .header("x-forwarded-for", "203.0.113.7")
+        .body("One too many")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = response.headers()[RETRY_AFTER].to_str()?.parse()?;
+    assert!(0 < retry_after && retry_after <= 60);
+
+    // Test that the limit is per client, going by the address the proxy saw.
+    let response = client
+        .post("/")
+        .header("x-forwarded-for", "203.0.113.7, 198.51.100.1")
+        .body("Someone else")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_add_multipart() -> Result<()> {
+    let client = get_client();
+    let paste = "Hello, world!\n";
+
+    // Create a paste from a raw body, and another from a form.
+    let response = client
+        .post("/?lang=txt")
+        .body(paste.to_string())
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let raw = response.text().await.parse::<Uri>()?;
+    let form = format!(
+        "--boundary\r\n\
+         Content-Disposition: form-data; name=\"content\"\r\n\
+         \r\n\
+         {}\r\n\
+         --boundary--\r\n",
+        paste
+    );
+    let response = client
+        .post("/?lang=txt")
+        .header(CONTENT_TYPE, "multipart/form-data; boundary=boundary")
+        .body(form)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let multipart = response.text().await.parse::<Uri>()?;
+
+    // Test that both store the same content.
+    let response = client.get(&format!("/raw{}", raw.path())).send().await;
+    assert_eq!(response.text().await, paste);
+    let response = client
+        .get(&format!("/raw{}", multipart.path()))
+        .send()
+        .await;
+    assert_eq!(response.text().await, paste);
+
+    // Test that forms without content are rejected.
+    let form = "--boundary\r\n\
+                Content-Disposition: form-data; name=\"title\"\r\n\
+                \r\n\
+                Hello\r\n\
+                --boundary--\r\n";
+    let response = client
+        .post("/")
+        .header(CONTENT_TYPE, "multipart/form-data; boundary=boundary")
+        .body(form)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+// Send a CORS preflight for an upload from `origin`.
+async fn preflight(router: Router, origin: &str) -> Response {
+    let request = Request::builder()
+        .method(Method::OPTIONS)
+        .uri("/")
+        .header(ORIGIN, origin)
+        .header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .body(Body::empty())
+        .unwrap();
+
+    router.oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_cors() -> Result<()> {
+    // Test that a preflight from any origin is allowed by default.
+    let router = make_router(None, None).with_state(App::mock());
+    let response = preflight(router, "http://localhost:3000").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    let methods = response.headers()[ACCESS_CONTROL_ALLOW_METHODS].to_str()?;
+    assert!(methods.contains("POST") && methods.contains("DELETE"));
+
+    // Test that with an allowlist, only listed origins are allowed.
+    let origins = vec![HeaderValue::from_static("https://pstrs.example")];
+    let router = make_router(Some(origins), None).with_state(App::mock());
+    let response = preflight(router.clone(), "https://pstrs.example").await;
+    let origin = &response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN];
+    assert_eq!(origin, "https://pstrs.example");
+    let response = preflight(router, "https://evil.example").await;
+    assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+
+    // Test that the allowlist can be given as a comma-separated list.
+    let origins = parse_origins("https://a.example, https://b.example,")?;
+    assert_eq!(origins, ["https://a.example", "https://b.example"]);
+    assert!(parse_origins("https://a.example\nhttps://b.example").is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_list() -> Result<()> {
+    let app = App {
+        admin_token: Some("hunter2".to_string()),
+        ..App::mock()
+    };
+    let client = get_client_with(app);
+
+    // Create a few public pastes, oldest first, and one that isn't.
+    let mut ids = Vec::new();
+    for i in 0..5 {
+        let response = client
+            .post("/?public=true")
+            .body(format!("Paste {}", i))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let uri = response.text().await.parse::<Uri>()?;
+        ids.push(uri.path()[1..].to_string());
+    }
+    ids.reverse();
+    let response = client.post("/").body("Unlisted").send().await;
+    let unlisted = response.text().await.parse::<Uri>()?.path()[1..].to_string();
+
+    // List a page of pastes, returning their ids.
+    let list = |query: &str| {
+        let request = client
+            .get(&format!("/admin/pastes{}", query))
+            .header(AUTHORIZATION, "Bearer hunter2");
+        async move {
+            let response = request.send().await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let pastes = response.json::<Vec<Value>>().await;
+            pastes
+                .iter()
+                .map(|paste| paste["id"].as_str().unwrap_or_default().to_string())
+                .collect::<Vec<_>>()
+        }
+    };
+
+    // Test that public pastes are listed newest first, and only them.
+    assert_eq!(list("").await, ids);
+
+    // Test that every paste is listed when asked.
+    let all = list("?all=true").await;
+    assert_eq!(all[0], unlisted);
+    assert_eq!(all[1..], ids);
+
+    // Test that pages start and end where asked.
+    assert_eq!(list("?limit=2").await, ids[..2]);
+    assert_eq!(list("?limit=2&offset=2").await, ids[2..4]);
+    assert_eq!(list("?limit=2&offset=4").await, ids[4..]);
+    assert!(list("?offset=5").await.is_empty());
+
+    // Test that only admins can list pastes.
+    let response = client.get("/admin/pastes").send().await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .get("/admin/pastes")
+        .header(AUTHORIZATION, "Bearer hunter3")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mine() -> Result<()> {
+    let app = App {
+        admin_token: Some("hunter2".to_string()),
+        ..App::mock()
+    };
+    let pastes = app.pastes.clone();
+    let client = get_client_with(app);
+
+    // Create pastes with one key, oldest first, and one with another.
+    let mut ids = Vec::new();
+    for i in 0..2 {
+        let response = client
+            .post("/")
+            .header(AUTHORIZATION, "Bearer alice-key")
+            .body(format!("Alice's paste {}", i))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let uri = response.text().await.parse::<Uri>()?;
+        ids.push(uri.path()[1..].to_string());
+    }
+    ids.reverse();
+    let response = client
+        .post("/")
+        .header(API_KEY, "bob-key")
+        .body("Bob's paste")
+        .send()
+        .await;
+    let bobs = response.text().await.parse::<Uri>()?.path()[1..].to_string();
+    client.post("/").body("Nobody's paste").send().await;
+
+    // List the pastes of a key, returning their ids.
+    let mine = |key: &str| {
+        let request = client
+            .get("/mine")
+            .header(AUTHORIZATION, format!("Bearer {}", key));
+        async move {
+            let response = request.send().await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let pastes = response.json::<Vec<Value>>().await;
+            pastes
+                .iter()
+                .map(|paste| paste["id"].as_str().unwrap_or_default().to_string())
+                .collect::<Vec<_>>()
+        }
+    };
+
+    // Test that each key sees its own pastes, newest first, and only them.
+    assert_eq!(mine("alice-key").await, ids);
+    assert_eq!(mine("bob-key").await, vec![bobs]);
+    assert!(mine("carol-key").await.is_empty());
+
+    // Test that there's no listing without a key.
+    let response = client.get("/mine").send().await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Test that the key can be given in its own header instead.
+    let response = client.get("/mine").header(API_KEY, "bob-key").send().await;
+    assert_eq!(response.json::<Vec<Value>>().await.len(), 1);
+
+    // Test that the admin token isn't taken for a key, neither for uploading
+    // nor for listing.
+    let response = client
+        .post("/")
+        .header(AUTHORIZATION, "Bearer hunter2")
+        .body("Nobody's either")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = client
+        .get("/mine")
+        .header(AUTHORIZATION, "Bearer hunter2")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let owner = paste::hash_api_key("hunter2");
+    assert!(pastes.list_by_owner(&owner, 10, 0).await?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_clear() -> Result<()> {
+    let app = App {
+        admin_token: Some("hunter2".to_string()),
+        ..App::mock()
+    };
+    let pastes = app.pastes.clone();
+    let client = get_client_with(app);
+    for i in 0..3 {
+        let response = client.post("/").body(format!("Paste {}", i)).send().await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    // Test that only admins can clear the pastes, and that they're kept.
+    let response = client.delete("/admin/pastes").send().await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .delete("/admin/pastes")
+        .header(AUTHORIZATION, "Bearer hunter3")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(pastes.count().await?, 3);
+
+    // Test that clearing removes every paste, saying how many.
+    let response = client
+        .delete("/admin/pastes")
+        .header(AUTHORIZATION, "Bearer hunter2")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, "3");
+    assert_eq!(pastes.count().await?, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_remove_many() -> Result<()> {
+    let app = App {
+        admin_token: Some("hunter2".to_string()),
+        ..App::mock()
+    };
+    let pastes = app.pastes.clone();
+    let client = get_client_with(app);
+    let mut ids = Vec::new();
+    for i in 0..3 {
+        let response = client.post("/").body(format!("Paste {}", i)).send().await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let uri = response.text().await.parse::<Uri>()?;
+        ids.push(uri.path().trim_start_matches('/').parse::<Uuid>()?);
+    }
+    let missing = Uuid::new_v4();
+
+    // Test that only admins can delete pastes, and that they're kept.
+    let body = json!([ids[0], missing, ids[2]]);
+    let response = client.post("/admin/delete").json(&body).send().await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(pastes.count().await?, 3);
+
+    // Test that existing pastes are deleted, and missing ones reported, in order.
+    let response = client
+        .post("/admin/delete")
+        .header(AUTHORIZATION, "Bearer hunter2")
+        .json(&body)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.json::<Value>().await,
+        json!([
+            {"id": ids[0], "status": "deleted"},
+            {"id": missing, "status": "not_found"},
+            {"id": ids[2], "status": "deleted"},
+        ])
+    );
+    assert_eq!(pastes.count().await?, 1);
+    assert!(pastes.get_meta(ids[1]).await?.is_some());
+
+    // Test that deleted pastes are gone, so not found a second time.
+    let response = client
+        .post("/admin/delete")
+        .header(AUTHORIZATION, "Bearer hunter2")
+        .json(&json!([ids[0], ids[1], ids[1]]))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.json::<Value>().await,
+        json!([
+            {"id": ids[0], "status": "not_found"},
+            {"id": ids[1], "status": "deleted"},
+            {"id": ids[1], "status": "not_found"},
+        ])
+    );
+    assert_eq!(pastes.count().await?, 0);
+
+    // Test that too many ids at once are refused.
+    let body: Vec<_> = (0..101).map(|_| Uuid::new_v4()).collect();
+    let response = client
+        .post("/admin/delete")
+        .header(AUTHORIZATION, "Bearer hunter2")
+        .json(&body)
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_export() -> Result<()> {
+    let app = App {
+        admin_token: Some("hunter2".to_string()),
+        ..App::mock()
+    };
+    let pastes = app.pastes.clone();
+    let client = get_client_with(app);
+
+    // Create more pastes than fit in a page.
+    let mut contents = HashMap::new();
+    for i in 0..105 {
+        let content = format!("Paste {}", i);
+        let paste = NewPaste {
+            content: content.clone(),
+            ..Default::default()
+        };
+        contents.insert(format!("{}.txt", pastes.create(paste).await?.id), content);
+    }
+
+    // Create pastes that are only for those with the password, or reading once.
+    let protected = NewPaste {
+        content: "Secret".to_string(),
+        password_hash: Some(password::hash("hunter2")?),
+        ..Default::default()
+    };
+    pastes.create(protected).await?;
+    let burn = NewPaste {
+        content: "Read once".to_string(),
+        burn: true,
+        ..Default::default()
+    };
+    let burn = pastes.create(burn).await?.id;
+
+    // Test that only admins can export the pastes.
+    let response = client.get("/export").send().await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Test that every other paste is in the archive, once, named by its id, and
+    // that it's streamed rather than built up front.
+    let response = client
+        .get("/export")
+        .header(AUTHORIZATION, "Bearer hunter2")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/zip");
+    assert!(!response.headers().contains_key(CONTENT_LENGTH));
+    let mut zip = ZipArchive::new(Cursor::new(response.bytes().await))?;
+    assert_eq!(zip.len(), contents.len());
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        assert_eq!(contents.get(file.name()), Some(&content));
+    }
+
+    // Test that exporting doesn't count as viewing, or burn the burn paste.
+    let listed = pastes.list(200, 0, false).await?;
+    assert!(listed.iter().all(|meta| meta.views == 0));
+    let response = client.get(&format!("/{}", burn)).send().await;
+    assert_eq!(response.text().await, "Read once");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_search() -> Result<()> {
+    let app = App {
+        admin_token: Some("hunter2".to_string()),
+        ..App::mock()
+    };
+    let client = get_client_with(app);
+
+    // Create a few pastes, oldest first.
+    let mut ids = Vec::new();
+    for paste in ["Hello, World!", "hello again", "100% sure", "snake_case"] {
+        let response = client.post("/").body(paste).send().await;
+        let uri = response.text().await.parse::<Uri>()?;
+        ids.push(uri.path()[1..].to_string());
+    }
+
+    // Search for pastes containing a term, returning their ids, and where to
+    // carry on searching from.
+    let search_on = |query: &str| {
+        let request = client
+            .get(&format!("/search?{}", query))
+            .header(AUTHORIZATION, "Bearer hunter2");
+        async move {
+            let response = request.send().await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let next = response.headers().get(NEXT_CURSOR).cloned();
+            let pastes = response.json::<Vec<Value>>().await;
+            let ids = pastes
+                .iter()
+                .map(|paste| paste["id"].as_str().unwrap_or_default().to_string())
+                .collect::<Vec<_>>();
+            (
+                ids,
+                next.map(|next| next.to_str().unwrap_or_default().to_string()),
+            )
+        }
+    };
+    let search = |query: &str| {
+        let search = search_on(query);
+        async move { search.await.0 }
+    };
+
+    // Test that matches ignore case, and come newest first.
+    assert_eq!(search("q=hello").await, [ids[1].clone(), ids[0].clone()]);
+    assert_eq!(search("q=hello&limit=1").await, ids[1..2]);
+
+    // Test that nothing comes back when nothing matches.
+    assert!(search("q=goodbye").await.is_empty());
+
+    // Test that wildcards only match themselves.
+    assert_eq!(search("q=%25").await, ids[2..3]);
+    assert_eq!(search("q=_").await, ids[3..]);
+    assert!(search("q=hello_again").await.is_empty());
+
+    // Test that a search stopped by the limit says where to carry on from,
+    // until there's nothing left to search.
+    let (found, next) = search_on("q=hello&limit=1").await;
+    assert_eq!(found, ids[1..2]);
+    let next = next.expect("more to search");
+    let (found, next) = search_on(&format!("q=hello&limit=1&after={}", next)).await;
+    assert_eq!(found, ids[0..1]);
+    let next = next.expect("more to search");
+    let (found, next) = search_on(&format!("q=hello&limit=1&after={}", next)).await;
+    assert!(found.is_empty());
+    assert_eq!(next, None);
+    assert_eq!(search_on("q=hello").await.1, None);
+
+    // Test that cursors that can't have come from a search are refused.
+    let response = client
+        .get("/search?q=hello&after=nope")
+        .header(AUTHORIZATION, "Bearer hunter2")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Test that empty searches are refused.
+    let response = client
+        .get("/search?q=")
+        .header(AUTHORIZATION, "Bearer hunter2")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Test that only admins can search.
+    let response = client.get("/search?q=hello").send().await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_search_window() -> Result<()> {
+    let app = App {
+        admin_token: Some("hunter2".to_string()),
+        search_window: 2,
+        ..App::mock()
+    };
+    let client = get_client_with(app);
+
+    // Create a paste, then two newer ones that fill the window.
+    for paste in ["needle", "hay", "more hay"] {
+        client.post("/").body(paste).send().await;
+    }
+
+    // Test that only the newest pastes are searched, with where to carry on
+    // from to search the older ones.
+    let search = |query: &str| {
+        let request = client
+            .get(&format!("/search?{}", query))
+            .header(AUTHORIZATION, "Bearer hunter2");
+        async move {
+            let response = request.send().await;
+            let next = response.headers().get(NEXT_CURSOR).cloned();
+            let found = response.json::<Vec<Value>>().await.len();
+            (
+                found,
+                next.map(|next| next.to_str().unwrap_or_default().to_string()),
+            )
+        }
+    };
+    let (found, next) = search("q=needle").await;
+    assert_eq!(found, 0);
+    let next = next.expect("more to search");
+    let (found, next) = search(&format!("q=needle&after={}", next)).await;
+    assert_eq!((found, next), (1, None));
+    assert_eq!(search("q=hay").await.0, 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_diff() -> Result<()> {
+    let client = get_client();
+
+    // Create two pastes, one changing a line of the other.
+    let response = client.post("/").body("one\ntwo\nthree\n").send().await;
+    let before = response.text().await.parse::<Uri>()?;
+    let response = client.post("/").body("one\n2\nthree\n").send().await;
+    let after = response.text().await.parse::<Uri>()?;
+
+    // Test that the change is shown as a unified diff.
+    let uri = format!("/diff{}{}", before.path(), after.path());
+    let response = client.get(&uri).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.text().await,
+        "@@ -1,3 +1,3 @@\n one\n-two\n+2\n three\n"
+    );
+
+    // Test that a paste compared with itself has no differences.
+    let uri = format!("/diff{}{}", before.path(), before.path());
+    let response = client.get(&uri).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, "");
+
+    // Test that browsers get the change colored in.
+    let uri = format!("/diff{}{}", before.path(), after.path());
+    let response = client.get(&uri).header(ACCEPT, "text/html").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let content_type = response.headers()[CONTENT_TYPE].to_str()?;
+    assert!(content_type.starts_with("text/html"));
+    assert!(response.text().await.contains("-two</span>"));
+
+    // Test that comparing with a paste that doesn't exist is a 404.
+    let uri = format!("/diff{}/{}", before.path(), Uuid::new_v4());
+    let response = client.get(&uri).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Test that a burn after reading paste isn't burned when comparing it with
+    // a paste that's missing or locked.
+    let response = client.post("/?burn=true").body("burn\n").send().await;
+    let burn = response.text().await.parse::<Uri>()?;
+    let response = client.post("/?password=hunter2").body("one\n").send().await;
+    let locked = response.text().await.parse::<Uri>()?;
+    let uri = format!("/diff{}/{}", burn.path(), Uuid::new_v4());
+    let response = client.get(&uri).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let uri = format!("/diff{}{}?password=hunter3", burn.path(), locked.path());
+    let response = client.get(&uri).send().await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client.get(burn.path()).send().await;
+    assert_eq!(response.text().await, "burn\n");
+
+    // Test that a burn after reading paste compared with itself has no
+    // differences, rather than being gone the second time it's read.
+    let response = client.post("/?burn=true").body("burn\n").send().await;
+    let burn = response.text().await.parse::<Uri>()?;
+    let uri = format!("/diff{}{}", burn.path(), burn.path());
+    let response = client.get(&uri).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, "");
+    let response = client.get(burn.path()).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_markdown() -> Result<()> {
+    let client = get_client();
+
+    // Create a Markdown paste, with a sneaky script.
+    let paste = "# Title\n\n<script>alert('xss')</script>\n";
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that the paste is rendered as HTML, without the script.
+    let response = client.get(&format!("{}/md", id)).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let content_type = response.headers()[CONTENT_TYPE].to_str()?;
+    assert!(content_type.starts_with("text/html"));
+    let html = response.text().await;
+    assert!(html.contains("<h1>Title</h1>"));
+    assert!(!html.contains("<script"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_markdown_allowlist() -> Result<()> {
+    let paste = "# Title\n\n![]()\n";
+    let client_with = |images| {
+        let allowlist = MarkdownAllowlist {
+            images,
+            ..Default::default()
+        };
+        get_client_with(App {
+            markdown_sanitizer: Arc::new(allowlist.sanitizer()),
+            ..App::mock()
+        })
+    };
+
+    // Test that with images disabled, the image is stripped.
+    let client = client_with(false);
+    let response = client.post("/").body(paste).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let response = client.get(&format!("{}/md", uri.path())).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = response.text().await;
+    assert!(html.contains("<h1>Title</h1>"));
+    assert!(!html.contains("<img"));
+
+    // Test that with them enabled, it's kept.
+    let client = client_with(true);
+    let response = client.post("/").body(paste).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let response = client.get(&format!("{}/md", uri.path())).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = response.text().await;
+    assert!(html.contains("<h1>Title</h1>"));
+    assert!(html.contains("<img src=\"\" alt=\"\">"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_get_json() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste.
+    let paste = "Hello, world!";
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that asking for JSON gets the whole paste.
+    let response = client
+        .get(id)
+        .header(ACCEPT, "application/json")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let json = response.json::<Value>().await;
+    assert_eq!(json["id"], id[1..]);
+    assert_eq!(json["content"], paste);
+    assert!(json.get("password_hash").is_none());
+
+    // Test that the plain text route is unaffected.
+    let response = client.get(id).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, paste);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_default_theme() -> Result<()> {
+    let app = App {
+        default_theme: "InspiredGitHub".to_string(),
+        ..App::mock()
+    };
+    let client = get_client_with(app);
+
+    // Create a paste to highlight.
+    let paste = "fn main() { println!(\"Hello, world!\"); }";
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that the configured default is used when no theme is asked for.
+    let path = format!("{}/rs/html?theme=InspiredGitHub", id);
+    let explicit = client.get(&path).send().await.text().await;
+    let path = format!("{}/rs/html", id);
+    let default = client.get(&path).send().await.text().await;
+    assert_eq!(default, explicit);
+
+    // Test that asking for another theme still works.
+    let path = format!("{}/rs/html?theme={}", id, DEFAULT_THEME);
+    let other = client.get(&path).send().await.text().await;
+    assert_ne!(other, explicit);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_assets() -> Result<()> {
+    let assets = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/assets");
+    let client = get_client_with(
+        AppBuilder::new(MockPasteStore::arc())
+            .assets(assets, None)?
+            .build(),
+    );
+
+    // Test that the custom syntax is listed with its extension.
+    let response = client.get("/languages").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let languages = response.json::<Vec<Value>>().await;
+    let language = languages
+        .iter()
+        .find(|language| language["name"] == "Pstrs Test")
+        .expect("the custom syntax should be loaded");
+    assert_eq!(language["extensions"], serde_json::json!(["pstrs"]));
+
+    // Test that the custom theme is listed alongside the built in ones.
+    let response = client.get("/themes").send().await;
+    let themes = response.json::<Vec<String>>().await;
+    assert!(themes.contains(&"Pstrs".to_string()));
+    assert!(themes.contains(&DEFAULT_THEME.to_string()));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_qr() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste.
+    let response = client.post("/").body("Hello, world!").send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that the QR code is a PNG.
+    let response = client.get(&format!("/qr{}", id)).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "image/png");
+    let png = response.bytes().await;
+    assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+    // Test that a named paste's code links to it by name.
+    let response = client
+        .post("/?name=scan-me")
+        .header(ACCEPT, "application/json")
+        .body("Named")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created = response.json::<Value>().await;
+    let url = created["url"].as_str().unwrap_or_default();
+    assert!(url.ends_with("/p/scan-me"));
+    let id = created["id"].as_str().unwrap_or_default();
+    let response = client.get(&format!("/qr/{}", id)).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let png = image::load_from_memory(&response.bytes().await)?.into_luma8();
+    let expected = QrCode::new(url)?.render::<Luma<u8>>().build();
+    assert_eq!(png, expected);
+
+    // Test that pastes that don't exist don't get a QR code.
+    let response = client.get(&format!("/qr/{}", Uuid::new_v4())).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Test that getting the code doesn't read, and so burn, the paste.
+    let response = client.post("/?burn=true").body("Once").send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let response = client.get(&format!("/qr{}", uri.path())).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.get(uri.path()).send().await;
+    assert_eq!(response.text().await, "Once");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_highlight_cache() -> Result<()> {
+    let app = App::mock();
+    let cache = app.highlight_cache.clone();
+    let client = get_client_with(app);
+
+    // Create a paste to highlight.
+    let paste = "fn main() { println!(\"Hello, world!\"); }";
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id and delete token from the response.
+    let token = response.headers()[DELETE_TOKEN].to_str()?.to_owned();
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that highlighting twice only highlights once.
+    let path = format!("{}/rs", id);
+    let first = client.get(&path).send().await.text().await;
+    let second = client.get(&path).send().await.text().await;
+    assert_eq!(first, second);
+    assert_eq!(cache.misses(), 1);
+
+    // Test that HTML and other themes are cached separately.
+    client.get(&format!("{}/rs/html", id)).send().await;
+    client
+        .get(&format!("{}?theme=InspiredGitHub", path))
+        .send()
+        .await;
+    assert_eq!(cache.misses(), 3);
+
+    // Test that editing the paste throws away its stale highlighting.
+    let response = client
+        .put(&format!("{}?token={}", id, token))
+        .body("fn edited() {}")
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let edited = client.get(&path).send().await.text().await;
+    assert!(edited.contains("edited"));
+    assert_eq!(cache.misses(), 4);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_timing() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste to highlight.
+    let paste = "fn main() { println!(\"Hello, world!\"); }";
+    let response = client.post("/").body(paste.to_string()).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that every highlighting route says how long highlighting took.
+    for path in ["rs", "rs/html", "html", "embed"] {
+        let response = client.get(&format!("{}/{}", id, path)).send().await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let timing = response.headers()[SERVER_TIMING].to_str()?;
+        let duration = timing
+            .strip_prefix("highlight;dur=")
+            .expect("timing is of highlighting");
+        assert!(duration.parse::<f64>()? >= 0.0);
+    }
+
+    // Test that serving the paste as is doesn't.
+    let response = client.get(id).send().await;
+    assert!(!response.headers().contains_key(SERVER_TIMING));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_highlight_timeout() -> Result<()> {
+    // Nothing can be highlighted in no time at all.
+    let app = App {
+        highlight_timeout: Duration::ZERO,
+        ..App::mock()
+    };
+    let cache = app.highlight_cache.clone();
+    let client = get_client_with(app);
+
+    let paste = "fn lt(a: u8, b: u8) -> bool { a < b }\n".repeat(1000);
+    let response = client.post("/").body(paste.clone()).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that content that takes too long to highlight is served as is.
+    let response = client.get(&format!("{}/rs", id)).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, paste);
+
+    // Test that HTML is still escaped, just not highlighted.
+    let response = client.get(&format!("{}/rs/html", id)).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = response.text().await;
+    assert!(html.contains("&lt;"));
+    assert!(!html.contains("<span"));
+
+    // Test that giving up isn't mistaken for output worth caching.
+    client.get(&format!("{}/rs", id)).send().await;
+    assert_eq!(cache.misses(), 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_max_highlight_lines() -> Result<()> {
+    let app = App {
+        max_highlight_lines: 2,
+        ..App::mock()
+    };
+    let cache = app.highlight_cache.clone();
+    let client = get_client_with(app);
+
+    // Create a paste at the limit, and one past it.
+    let short = "fn lt(a: u8, b: u8) -> bool { a < b }\n".repeat(2);
+    let response = client.post("/").body(short).send().await;
+    let short = response.text().await.parse::<Uri>()?;
+    let long = "fn lt(a: u8, b: u8) -> bool { a < b }\n".repeat(3);
+    let response = client.post("/").body(long.clone()).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that a paste at the limit is still highlighted.
+    let response = client.get(&format!("{}/rs", short.path())).send().await;
+    assert!(response.text().await.contains("\x1b["));
+
+    // Test that one past it is served as is, without trying to highlight it.
+    let response = client.get(&format!("{}/rs", id)).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await, long);
+    assert_eq!(cache.misses(), 1);
+
+    // Test that HTML is still escaped, just not highlighted.
+    let response = client.get(&format!("{}/rs/html", id)).send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = response.text().await;
+    assert!(html.contains("&lt;"));
+    assert!(!html.contains("<span"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_max_pastes() -> Result<()> {
+    let app = App {
+        max_pastes: Some(2),
+        ..App::mock()
+    };
+    let client = get_client_with(app);
+
+    // Test that pastes are accepted up to the cap.
+    for i in 0..2 {
+        let response = client.post("/").body(format!("Paste {}", i)).send().await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    // Test that the next one is refused.
+    let response = client.post("/").body("One too many").send().await;
+    assert_eq!(response.status(), StatusCode::INSUFFICIENT_STORAGE);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_import_private() -> Result<()> {
+    let client = get_client();
+
+    // Test that URLs leading to our own network are refused.
+    for url in ["http://127.0.0.1/", "http://localhost/", "http://10.0.0.1/"] {
+        let path = format!("/import?url={}", url);
+        let response = client.post(&path).send().await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    // Test that nonsense is refused too.
+    let response = client.post("/import?url=not-a-url").send().await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_highlight_wrap() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste with a long line.
+    let paste = format!("let s = \"{}\";\n", "a".repeat(500));
+    let response = client.post("/").body(paste).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that lines only wrap when asked to.
+    let response = client.get(&format!("{}/rs/html", id)).send().await;
+    assert!(!response.text().await.contains("white-space:pre-wrap"));
+    let response = client
+        .get(&format!("{}/rs/html?wrap=true", id))
+        .send()
+        .await;
+    assert!(response.text().await.contains("white-space:pre-wrap"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_highlight_classes() -> Result<()> {
+    let client = get_client();
+
+    let paste = "fn main() {}\n";
+    let response = client.post("/").body(paste.to_string()).send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that classed HTML is styled by classes rather than inline.
+    let response = client
+        .get(&format!("{}/rs/html?classes=true", id))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = response.text().await;
+    assert!(html.starts_with("<pre class=\"hl-code\">"));
+    assert!(html.contains("class=\"hl-"));
+    assert!(!html.contains("style="));
+
+    // Test that it can't be combined with options that need inline styles.
+    let response = client
+        .get(&format!("{}/rs/html?classes=true&linenos=true", id))
+        .send()
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Test that the stylesheet has rules for the classes.
+    let response = client.get("/highlight.css").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "text/css; charset=utf-8");
+    let css = response.text().await;
+    assert!(css.contains(".hl-code {"));
+    assert!(css.contains(".hl-wrap {"));
+
+    // Test that the stylesheet depends on the theme.
+    let response = client
+        .get("/highlight.css?theme=InspiredGitHub")
+        .send()
+        .await;
+    assert_ne!(response.text().await, css);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_highlight_tabs() -> Result<()> {
+    let client = get_client();
+
+    // Create a paste indented with tabs.
+    let paste = "fn main() {\n\tprintln!(\"Hello, world!\");\n}\n";
+    let response = client.post("/").body(paste.to_string()).send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    // Get the paste id from the response.
+    let body = response.text().await;
+    let uri = body.parse::<Uri>()?;
+    let id = uri.path();
+
+    // Test that tabs are expanded, to 4 columns unless asked otherwise.
+    let response = client.get(&format!("{}/rs/html", id)).send().await;
+    let html = response.text().await;
+    assert!(!html.contains('\t') && html.contains("    println"));
+    let response = client.get(&format!("{}/rs/html?tabs=2", id)).send().await;
+    let html = response.text().await;
+    assert!(html.contains("  println") && !html.contains("   println"));
+
+    // Test that nonsense widths are rejected.
+    for tabs in ["0", "100", "four"] {
+        let path = format!("{}/rs?tabs={}", id, tabs);
+        let response = client.get(&path).send().await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_version() -> Result<()> {
+    let client = get_client();
+
+    // Test that the version is the crate's, alongside the build info.
+    let response = client.get("/version").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let version = response.json::<Value>().await;
+    assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+    assert!(version["git_sha"].is_string());
+    let built_at = version["built_at"].as_str().unwrap_or_default();
+    assert!(built_at.parse::<DateTime<Utc>>()? > mock::created_at());
+
+    Ok(())
+}
+
+/// The value of the unlabelled `metric` in a Prometheus scrape, or zero if it
+/// hasn't been recorded yet.
+fn scraped(scrape: &str, metric: &str) -> f64 {
+    scrape
+        .lines()
+        .filter_map(|line| line.strip_prefix(metric)?.strip_prefix(' '))
+        .find_map(|value| value.parse().ok())
+        .unwrap_or_default()
+}
+
+#[tokio::test]
+async fn test_metrics() -> Result<()> {
+    // Other tests run alongside, so metrics can only be compared, not pinned.
+    telemetry::install();
+    let client = get_client();
+
+    let response = client.get("/metrics").send().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let before = scraped(&response.text().await, telemetry::UPLOADS);
+
+    // Test that uploading counts, and that the request's latency is recorded.
+    let response = client.post("/").body("count me in").send().await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let scrape = client.get("/metrics").send().await.text().await;
+    assert!(scraped(&scrape, telemetry::UPLOADS) > before);
+    assert!(scrape.contains(&format!(
+        "{}_bucket{{method=\"POST\",path=\"/\",status=\"201\",le=\"+Inf\"}}",
+        telemetry::REQUEST_DURATION
+    )));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_method_not_allowed() -> Result<()> {
+    let client = get_client();
+    let response = client.post("/").body("Not to be posted to").send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+
+    // Test that methods without a route are refused, saying which have one.
+    let response = client.post(uri.path()).send().await;
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(response.headers()[ALLOW], "GET,HEAD,PUT,PATCH,DELETE");
+    let body = response.json::<Value>().await;
+    assert_eq!(body["status"], 405);
+    assert_eq!(body["error"], "Method POST isn't allowed here, see `Allow`");
+
+    // Test that each path lists its own methods.
+    let response = client.patch("/").send().await;
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(response.headers()[ALLOW], "GET,HEAD,POST");
+    let response = client.put("/admin/pastes").send().await;
+    assert_eq!(response.headers()[ALLOW], "GET,HEAD,DELETE");
+
+    // Test that paths without any route are still missing, not refused.
+    let response = client.patch("/no/such/route/here").send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_database_unavailable() -> Result<()> {
+    let pastes = MockPasteStore::arc();
+    let client = get_client_with(App {
+        pastes: pastes.clone(),
+        ..App::mock()
+    });
+    let response = client.post("/").body("Still here").send().await;
+    let uri = response.text().await.parse::<Uri>()?;
+
+    // Test that losing the database asks the client to come back later.
+    pastes.failures.store(1, Ordering::Relaxed);
+    let response = client.get(uri.path()).send().await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[RETRY_AFTER], "5");
+
+    // Test that coming back later works.
+    let response = client.get(uri.path()).send().await;
+    assert_eq!(response.text().await, "Still here");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_request_id() -> Result<()> {
+    let client = get_client();
+
+    // Test that every response says the id its request was given.
+    let response = client.get("/").send().await;
+    let id = response.headers()[REQUEST_ID].to_str()?;
+    assert!(id.parse::<Uuid>().is_ok());
+
+    // Test that errors say it too, so that it can be reported.
+    let response = client.get(&format!("/{}", Uuid::new_v4())).send().await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let id = response.headers()[REQUEST_ID].to_str()?.to_string();
+    let body = response.json::<Value>().await;
+    assert_eq!(body["request_id"], id);
+
+    // Test that ids from the client, e.g. a proxy, are kept.
+    let response = client
+        .get(&format!("/{}", Uuid::new_v4()))
+        .header(REQUEST_ID, "from-the-proxy")
+        .send()
+        .await;
+    assert_eq!(response.headers()[REQUEST_ID], "from-the-proxy");
+    let body = response.json::<Value>().await;
+    assert_eq!(body["request_id"], "from-the-proxy");
+
+    Ok(())
+}
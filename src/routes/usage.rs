@@ -0,0 +1,472 @@
+//! The usage text served at `/`, rendered from documentation of every route.
+
+use std::sync::OnceLock;
+
+/// Documentation of a route, for the usage text.
+pub struct RouteDoc {
+    pub method: &'static str,
+    /// The path, with parameters written as `<name>`.
+    pub path: &'static str,
+    /// What the route does and how to use it, wrapped when rendered.
+    pub paragraphs: &'static [&'static str],
+}
+
+/// Every route, in the order they're listed in the usage text.
+///
+/// Keep this in step with [make_router](super::make_router), the tests check
+/// that every route listed here is routed.
+pub const ROUTES: &[RouteDoc] = &[
+    RouteDoc {
+        method: "GET",
+        path: "/",
+        paragraphs: &[
+            "responds with this usage text, unless this server is set up to respond \
+             with a page of its own",
+            "every route is also served under `/api/v1`, e.g. `GET /api/v1/<id>`, \
+             which won't change, while those here may once there's a new version \
+             of the API",
+        ],
+    },
+    RouteDoc {
+        method: "POST",
+        path: "/",
+        paragraphs: &[
+            "accepts raw data in the body of the request and responds with `201 \
+             Created` and a URL of a page containing the body's content",
+            "also accepts a `multipart/form-data` body, as sent by HTML forms, in \
+             which case the paste's content is the form's `content` field",
+            "also accepts an `application/json` body like `{\"content\": \"...\", \
+             \"language\": \"rs\", \"ttl\": 3600}`, in which case the options below \
+             are given as its fields (`language` for `lang`) rather than in the query",
+            "a raw body may be gzipped, with a `Content-Encoding: gzip` header, in \
+             which case the paste's content is the decompressed body",
+            "optionally accepts `?ttl=<seconds>`, after which the paste expires",
+            "optionally accepts `?idle_ttl=<seconds>`, in which case the paste is \
+             removed once it goes that long without being read",
+            "pastes may be at most 1 MiB in size, and must not be empty, or only \
+             whitespace, unless `?allow_empty=true` is given",
+            "each client may upload at most 30 pastes a minute",
+            "uploading content that's already been uploaded responds with the URL of \
+             the existing paste, unless any other options are given",
+            "responds with `{\"id\", \"url\", \"created_at\"}` as JSON instead of just the \
+             URL if the `Accept` header includes `application/json`",
+            "responds with a token for deleting the paste in the `X-Delete-Token` \
+             header, except when responding with an existing paste, which is done \
+             with `200 OK` instead",
+            "optionally accepts an `Idempotency-Key: <key>` header, in which case \
+             uploading again with the same key responds with the URL of the paste \
+             first uploaded with it, and its delete token, so that retries don't \
+             make duplicates; keep the key as secret as the token, as it's derived \
+             from it",
+            "optionally accepts `?title=<title>`, of at most 200 characters, which \
+             is given with the paste's metadata",
+            "optionally accepts `?name=<name>`, in which case the URL responded with \
+             is `/p/<name>` rather than `/<id>`",
+            "optionally accepts `?password=<password>`, in which case the same \
+             `?password=<password>` must be given to read the paste",
+            "optionally accepts `?burn=true`, in which case the paste is deleted the \
+             first time it's read",
+            "optionally accepts `?public=true`, in which case the paste may be \
+             discovered with `GET /random` and `GET /admin/pastes`, rather than only \
+             by those given its URL",
+            "optionally accepts `?lang=<lang>`, in which case the paste is syntax \
+             highlighted as the language with extension `<lang>` whenever it's \
+             retrieved with `GET /<id>`",
+            "optionally accepts an `Authorization: Bearer <key>` header with an API \
+             key, any long random string of your choosing other than the admin \
+             token, in which case the paste is listed by `GET /mine` with the same \
+             key; it may be given as `X-Api-Key: <key>` instead",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/<id>",
+        paragraphs: &[
+            "retrieves the content for the paste with id `<id>`",
+            "optionally accepts `?highlight=auto`, in which case the language is \
+             detected from the first line of the paste (e.g. a shebang) and the \
+             content is syntax highlighted for a terminal",
+            "pastes uploaded with `?lang=<lang>` are always highlighted as `<lang>`",
+            "responds with the whole paste as JSON if the `Accept` header includes \
+             `application/json`",
+            "the hex SHA-256 digest of the paste's content is given in the \
+             `X-Content-SHA256` header, for checking it arrived intact",
+        ],
+    },
+    RouteDoc {
+        method: "HEAD",
+        path: "/<id>",
+        paragraphs: &[
+            "responds with the headers for the paste with id `<id>`, including its \
+             size in `Content-Length`, without its content",
+            "doesn't count as a view, nor burn a burn after reading paste",
+        ],
+    },
+    RouteDoc {
+        method: "POST",
+        path: "/import?url=<url>",
+        paragraphs: &[
+            "fetches the content at `<url>` and responds with a URL of a page \
+             containing it, as if it had been uploaded",
+            "only public HTTP(S) URLs can be imported, and the same limits apply as to \
+             uploads",
+        ],
+    },
+    RouteDoc {
+        method: "PUT",
+        path: "/<id>",
+        paragraphs: &[
+            "replaces the content for the paste with id `<id>` with the body of the \
+             request, responding with the new content",
+            "requires the token the paste was uploaded with, like `DELETE /<id>`",
+        ],
+    },
+    RouteDoc {
+        method: "PATCH",
+        path: "/<id>",
+        paragraphs: &[
+            "changes the metadata of the paste with id `<id>` to that in an \
+             `application/json` body like `{\"title\": \"...\", \"language\": \"rs\"}`, \
+             leaving anything not given, and the content, as is",
+            "responds with the paste's metadata, as `GET /<id>/meta` does",
+            "requires the token the paste was uploaded with, like `DELETE /<id>`",
+        ],
+    },
+    RouteDoc {
+        method: "POST",
+        path: "/<id>/append",
+        paragraphs: &[
+            "adds the body of the request onto the end of the content for the paste \
+             with id `<id>`, responding with its new size in bytes, as long as the \
+             paste stays within the size limit",
+            "requires the token the paste was uploaded with, like `DELETE /<id>`, and \
+             refuses password protected and burn after reading pastes",
+        ],
+    },
+    RouteDoc {
+        method: "POST",
+        path: "/<id>/fork",
+        paragraphs: &[
+            "copies the content and language of the paste with id `<id>` into a new \
+             paste, responding like `POST /` does",
+            "accepts `?password=<password>` for forking a password protected paste, \
+             and forking a burn after reading paste reads it, burning it",
+        ],
+    },
+    RouteDoc {
+        method: "DELETE",
+        path: "/<id>?token=<token>",
+        paragraphs: &[
+            "deletes the paste with id `<id>`, given the token it was uploaded with, \
+             either as `?token=<token>` or in an `Authorization: Bearer <token>` \
+             header",
+        ],
+    },
+    RouteDoc {
+        method: "POST",
+        path: "/batch",
+        paragraphs: &[
+            "accepts a JSON array of up to 100 ids and responds with the pastes with \
+             those ids as JSON, leaving out any that don't exist, are password \
+             protected, or are burned after reading",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/p/<name>",
+        paragraphs: &[
+            "retrieves the content for the paste uploaded with name `<name>`",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/mine",
+        paragraphs: &[
+            "lists the id, creation time, size, and number of views of every paste \
+             uploaded with the API key in the `Authorization: Bearer <key>` header (or \
+             `X-Api-Key: <key>`) as JSON, \
+             newest first",
+            "accepts `?limit=<n>` (at most 100) and `?offset=<n>` to page through \
+             the pastes",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/random",
+        paragraphs: &[
+            "redirects to a public paste picked at random, leaving out those that \
+             are password protected or burned after reading",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/diff/<id>/<other-id>",
+        paragraphs: &[
+            "responds with the unified diff turning the content of the paste with id \
+             `<id>` into that of the paste with id `<other-id>`, which is empty if \
+             they're the same",
+            "responds with HTML, with lines added and removed colored in, if the \
+             `Accept` header includes `text/html`",
+            "optionally accepts `?password=<password>`, for comparing pastes protected \
+             by it",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/raw/<id>",
+        paragraphs: &[
+            "retrieves the content for the paste with id `<id>` as plain text, never \
+             highlighted, with a filename to save it as",
+            "JSON, Markdown, and CSV pastes are served as such, by the language they \
+             were uploaded as, or for JSON by their content, but HTML and anything \
+             else browsers might run always as plain text",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/<id>/download",
+        paragraphs: &[
+            "the same, but as an attachment, so that browsers save the paste \
+             rather than show it",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/qr/<id>",
+        paragraphs: &[
+            "responds with a QR code of the URL to the paste with id `<id>`, as a PNG \
+             image",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/<id>/meta",
+        paragraphs: &[
+            "retrieves the id, creation time, size, number of views, and title (or \
+             `null`) of the paste with id `<id>` as JSON",
+            "accepts `?password=<password>` for a password protected paste, whose \
+             metadata needs it too",
+            "every read of a paste's content, highlighted or not, counts as a view",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/<id>/md",
+        paragraphs: &[
+            "retrieves the content for the paste with id `<id>`, rendered from \
+             Markdown as HTML",
+            "the HTML is sanitized of anything that could run script, and depending \
+             on the deployment may have its images or links stripped too",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/<id>/<lang>",
+        paragraphs: &[
+            "retrieves the content for the paste with id `<id>`, syntax highlighted as \
+             the language with extension `<lang>`",
+            "`<lang>` may also be the name of the language, e.g. `rust`, `python`, or \
+             `c++`, here and wherever else a language is given",
+            "responds with HTML if the `Accept` header includes `text/html`, and with \
+             terminal escape codes otherwise",
+            "a `<lang>` there's no syntax for is served as plain text, unless this \
+             server is set up to respond with a 404 or to detect the language instead",
+            "optionally accepts `?term=true`, in which case it responds with terminal \
+             escape codes whatever the `Accept` header says, starting with a reset \
+             and ending with one, so that nothing printed before or after bleeds into \
+             it",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/<id>/<lang>/html",
+        paragraphs: &[
+            "the same, but syntax highlighted as HTML for viewing in a browser",
+            "both highlighting routes accept `?theme=<name>` to pick the theme they \
+             highlight with, one of those listed by `GET /themes`",
+            "HTML output optionally accepts `?linenos=true`, in which case each line \
+             is numbered, and `?wrap=true`, in which case long lines wrap rather than \
+             scroll",
+            "HTML output optionally accepts `?full=true`, in which case it's a complete \
+             page in the theme's colors rather than just the code",
+            "HTML output optionally accepts `?classes=true`, in which case it's styled \
+             by classes rather than inline, for use with the stylesheet from `GET \
+             /highlight.css`, though then neither `?linenos` nor `?lines` may be given",
+            "both highlighting routes expand tabs to 4 columns, or as many as given by \
+             `?tabs=<n>` (up to 16)",
+            "both highlighting routes accept `?lines=<lines>` to draw attention to \
+             some of the lines, given as a line (`10`), a range (`10-15`), or a list \
+             of either (`3,10-15`)",
+            "HTML output shows only the lines from the first of those to the last, \
+             numbered as they are in the paste, so `?lines=10-20` renders lines 10 to \
+             20 alone",
+            "both highlighting routes say how long highlighting took, in \
+             milliseconds, in a `Server-Timing: highlight;dur=<ms>` header",
+            "pastes of more than 50,000 lines are served as they are by both \
+             highlighting routes, escaped as HTML by the HTML one, rather than \
+             highlighted",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/<id>/html",
+        paragraphs: &[
+            "the same, but syntax highlighted as the language the paste was uploaded \
+             as, or if it wasn't, whatever its first line (e.g. a shebang) gives away, \
+             or plain text if nothing does",
+            "this means `html` can't be given as `<lang>` above, use `htm` instead",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/<id>/embed",
+        paragraphs: &[
+            "the same, but as a fragment of HTML that any site may embed, whether \
+             directly or in an `<iframe>`, styled inline and linking back to the \
+             paste",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/highlight.css",
+        paragraphs: &[
+            "responds with the stylesheet for HTML highlighted with `?classes=true`, \
+             which optionally accepts `?theme=<name>`",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/themes",
+        paragraphs: &[
+            "lists the names of the available themes as JSON",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/languages",
+        paragraphs: &[
+            "lists the available languages and their extensions as JSON",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/version",
+        paragraphs: &[
+            "responds with the version, commit, and build time of the server as JSON",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/metrics",
+        paragraphs: &[
+            "responds with counts of uploads, reads, deletes, and highlights, and how \
+             long requests took, in Prometheus' text format",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/admin/pastes",
+        paragraphs: &[
+            "lists the id, creation time, size, and number of views of every public \
+             paste as JSON, newest first, or of every paste at all if `?all=true` is \
+             given",
+            "requires an `Authorization: Bearer <token>` header with the admin token, \
+             responding with `403 Forbidden` without it, and accepts `?limit=<n>` (at \
+             most 100) and `?offset=<n>` to page through the pastes",
+        ],
+    },
+    RouteDoc {
+        method: "DELETE",
+        path: "/admin/pastes",
+        paragraphs: &[
+            "deletes every paste, for resetting test deployments, and responds with \
+             how many were deleted",
+            "requires the admin token like `GET /admin/pastes`",
+        ],
+    },
+    RouteDoc {
+        method: "POST",
+        path: "/admin/delete",
+        paragraphs: &[
+            "accepts a JSON array of up to 100 ids and deletes the pastes with those \
+             ids, all together or not at all, responding with a JSON array of \
+             `{\"id\": <id>, \"status\": <status>}` in the same order, the status \
+             being `deleted`, or `not_found` if there was no such paste",
+            "requires the admin token like `GET /admin/pastes`",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/export",
+        paragraphs: &[
+            "responds with a zip archive of every paste, each as a file named \
+             `<id>.txt`, except password protected and burn after reading ones",
+            "requires the admin token like `GET /admin/pastes`",
+        ],
+    },
+    RouteDoc {
+        method: "GET",
+        path: "/search?q=<term>",
+        paragraphs: &[
+            "lists the id, creation time, size, and number of views of the pastes \
+             containing `<term>`, ignoring case, as JSON, newest first",
+            "only searches 10000 pastes at a time, newest first, as each one may \
+             have to be read to search it; when a search stops before the oldest \
+             paste, for that or for finding enough, where it did is given in the \
+             `X-Next-Cursor` header, and `?after=<cursor>` searches on from there",
+            "requires the admin token like `GET /admin/pastes`, and accepts \
+             `?limit=<n>` (at most 100)",
+        ],
+    },
+];
+
+/// Column that the usage text is wrapped at.
+const WIDTH: usize = 80;
+
+/// Indent of each route's method and path.
+const ROUTE_INDENT: &str = "      ";
+
+/// Indent of each route's paragraphs.
+const PARAGRAPH_INDENT: &str = "          ";
+
+/// Render `routes` as usage text.
+fn render(routes: &[RouteDoc]) -> String {
+    let mut usage = String::from("\n    USAGE\n");
+    for route in routes {
+        usage.push_str(&format!(
+            "\n{}{} {}\n",
+            ROUTE_INDENT, route.method, route.path
+        ));
+        for paragraph in route.paragraphs {
+            usage.push('\n');
+            push_wrapped(&mut usage, paragraph);
+        }
+    }
+
+    usage
+}
+
+/// Push `paragraph` onto `usage` indented, breaking lines between words so
+/// they don't run past [WIDTH].
+fn push_wrapped(usage: &mut String, paragraph: &str) {
+    let mut line = String::new();
+    for word in paragraph.split_whitespace() {
+        if !line.is_empty()
+            && PARAGRAPH_INDENT.len() + line.len() + 1 + word.len() > WIDTH
+        {
+            usage.push_str(&format!("{}{}\n", PARAGRAPH_INDENT, line));
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    usage.push_str(&format!("{}{}\n", PARAGRAPH_INDENT, line));
+}
+
+/// The usage text for our web app, rendered once from [ROUTES].
+pub fn usage() -> &'static str {
+    static USAGE: OnceLock<String> = OnceLock::new();
+    USAGE.get_or_init(|| render(ROUTES))
+}
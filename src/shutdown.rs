@@ -0,0 +1,103 @@
+use std::{future::Future, net::SocketAddr, sync::Arc};
+
+use axum::Router;
+use shuttle_runtime::CustomError;
+use tokio::task::JoinHandle;
+
+use crate::{paste::PasteStore, sweeper};
+
+/// The app, served until it's asked to shut down, when it stops taking new
+/// connections, lets the requests in flight finish, and then sweeps expired
+/// pastes one last time.
+///
+/// Shuttle's own axum service serves until the process is killed, so this
+/// takes its place to drain requests with axum's `with_graceful_shutdown`.
+pub struct GracefulService {
+    /// The routes, with their state.
+    pub router: Router,
+    /// Where the pastes to sweep are.
+    pub pastes: Arc<dyn PasteStore>,
+    /// The background sweeper, stopped once serving has.
+    pub sweeper: JoinHandle<()>,
+}
+
+#[shuttle_runtime::async_trait]
+impl shuttle_runtime::Service for GracefulService {
+    async fn bind(mut self, addr: SocketAddr) -> Result<(), shuttle_runtime::Error> {
+        axum::Server::bind(&addr)
+            .serve(self.router.into_make_service())
+            .with_graceful_shutdown(shutdown_signal())
+            .await
+            .map_err(CustomError::new)?;
+
+        tracing::info!("shutting down");
+        self.sweeper.abort();
+        if let Err(err) = sweeper::sweep(self.pastes.as_ref()).await {
+            tracing::error!("failed to sweep expired pastes: {:?}", err);
+        }
+
+        Ok(())
+    }
+}
+
+/// Resolve once the process is asked to shut down, by Ctrl+C or, on unix, by
+/// `SIGTERM`.
+///
+/// Note that handling a signal replaces the default of exiting on it, so
+/// whoever awaits this is responsible for winding down, as [GracefulService]
+/// does.
+pub async fn shutdown_signal() { first(ctrl_c(), terminate()).await }
+
+async fn ctrl_c() {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("failed to listen for Ctrl+C");
+}
+
+#[cfg(unix)]
+async fn terminate() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    signal(SignalKind::terminate())
+        .expect("failed to listen for SIGTERM")
+        .recv()
+        .await;
+}
+
+#[cfg(not(unix))]
+async fn terminate() { std::future::pending().await }
+
+/// Resolve once either of `a` or `b` does.
+async fn first(a: impl Future<Output = ()>, b: impl Future<Output = ()>) {
+    tokio::select! {
+        _ = a => {},
+        _ = b => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::pending, time::Duration};
+
+    use tokio::{sync::oneshot, time::timeout};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_first() {
+        // Test that either signal on its own is enough.
+        let (tx, rx) = oneshot::channel::<()>();
+        tx.send(()).unwrap();
+        let either = first(async { rx.await.unwrap() }, pending());
+        assert!(timeout(Duration::from_secs(1), either).await.is_ok());
+
+        let (tx, rx) = oneshot::channel::<()>();
+        tx.send(()).unwrap();
+        let either = first(pending(), async { rx.await.unwrap() });
+        assert!(timeout(Duration::from_secs(1), either).await.is_ok());
+
+        // Test that without a signal, it keeps waiting.
+        let neither = first(pending(), pending());
+        assert!(timeout(Duration::from_millis(50), neither).await.is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    io::{self, Seek, SeekFrom, Write},
+    sync::{Arc, Mutex},
+};
+
+use hyper::body::Bytes;
+
+/// A seekable writer that hands off what's written to it a piece at a time,
+/// for streaming formats written by seeking back to patch earlier bytes, like
+/// zip archives.
+///
+/// Only what's written since the last [Spool::take] is kept, so it's an error
+/// to seek back before that. Clones share the same bytes, so that they can be
+/// taken from one while the other is being written to.
+#[derive(Debug, Clone, Default)]
+pub struct Spool(Arc<Mutex<Inner>>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// How many bytes have been taken, which `buf` starts after.
+    taken: u64,
+    buf: Vec<u8>,
+    position: u64,
+}
+
+impl Spool {
+    /// How far into the stream the next write goes.
+    pub fn position(&self) -> u64 { self.0.lock().unwrap().position }
+
+    /// Take the bytes before `end`, which mustn't be written to again.
+    pub fn take(&self, end: u64) -> Bytes {
+        let mut inner = self.0.lock().unwrap();
+        let len = (end.saturating_sub(inner.taken) as usize).min(inner.buf.len());
+        inner.taken += len as u64;
+        inner.buf.drain(..len).collect::<Vec<_>>().into()
+    }
+
+    /// Take every byte written so far.
+    pub fn take_all(&self) -> Bytes { self.take(u64::MAX) }
+}
+
+impl Write for Spool {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let mut inner = self.0.lock().unwrap();
+        let start = (inner.position - inner.taken) as usize;
+        let end = start + bytes.len();
+        if end > inner.buf.len() {
+            inner.buf.resize(end, 0);
+        }
+        inner.buf[start..end].copy_from_slice(bytes);
+        inner.position += bytes.len() as u64;
+
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+impl Seek for Spool {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let mut inner = self.0.lock().unwrap();
+        let end = inner.taken + inner.buf.len() as u64;
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => end.checked_add_signed(offset),
+            SeekFrom::Current(offset) => inner.position.checked_add_signed(offset),
+        };
+        match position {
+            Some(position) if (inner.taken..=end).contains(&position) => {
+                inner.position = position;
+                Ok(position)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Can't seek outside what's been written and not yet taken",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spool() -> io::Result<()> {
+        let spool = Spool::default();
+        let mut writer = spool.clone();
+
+        // Test that what's written can be patched until it's taken.
+        writer.write_all(b"hello world")?;
+        writer.seek(SeekFrom::Start(0))?;
+        writer.write_all(b"J")?;
+        writer.seek(SeekFrom::End(0))?;
+        assert_eq!(spool.position(), 11);
+        assert_eq!(spool.take(6), "Jello ");
+
+        // Test that what's been taken can't be seeked back to, but the rest can.
+        assert!(writer.seek(SeekFrom::Start(5)).is_err());
+        assert!(writer.seek(SeekFrom::Current(-5)).is_ok());
+        writer.write_all(b"W")?;
+        writer.seek(SeekFrom::End(0))?;
+        writer.write_all(b"!")?;
+        assert!(writer.seek(SeekFrom::End(1)).is_err());
+        assert_eq!(spool.take_all(), "World!");
+        assert_eq!(spool.take_all(), "");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::task::JoinHandle;
+
+use crate::{error::Result, paste::PasteStore};
+
+/// Spawn a background task that removes expired pastes every `period`.
+///
+/// Expired pastes are already hidden from readers, this just makes sure they
+/// don't sit around in the database forever.
+pub fn spawn_sweeper(pastes: Arc<dyn PasteStore>, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if let Err(err) = sweep(pastes.as_ref()).await {
+                tracing::error!("failed to sweep expired pastes: {:?}", err);
+            }
+        }
+    })
+}
+
+/// Do a single pass over the store, removing expired pastes, and idle ones
+/// (see [PasteStore::remove_expired]).
+pub async fn sweep(pastes: &dyn PasteStore) -> Result<u64> {
+    let removed = pastes.remove_expired().await?;
+    tracing::info!("swept {} expired paste(s)", removed);
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use super::*;
+    use crate::paste::{mock::MockPasteStore, NewPaste};
+
+    #[tokio::test]
+    async fn test_sweep() -> Result<()> {
+        let store = MockPasteStore::default();
+
+        // Create one paste that has already expired, and one that never will.
+        let expired = NewPaste {
+            content: "Old news".to_string(),
+            expires_at: Some(Utc::now() - Duration::seconds(1)),
+            ..Default::default()
+        };
+        store.create(expired).await?;
+        let live = NewPaste {
+            content: "Evergreen".to_string(),
+            ..Default::default()
+        };
+        let live = store.create(live).await?;
+
+        // Test that only the expired paste is removed.
+        assert_eq!(sweep(&store).await?, 1);
+        let entries = store.entries.lock().await;
+        assert_eq!(entries.len(), 1);
+        assert!(entries.contains_key(&live.id));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sweep_idle() -> Result<()> {
+        let store = MockPasteStore::default();
+
+        // Create two pastes that are removed after a minute unread, and one
+        // that never is.
+        let mut ids = Vec::new();
+        for content in ["Forgotten", "Popular"] {
+            let paste = NewPaste {
+                content: content.to_string(),
+                idle_ttl: Some(Duration::minutes(1)),
+                ..Default::default()
+            };
+            ids.push(store.create(paste).await?.id);
+        }
+        let live = NewPaste {
+            content: "Evergreen".to_string(),
+            ..Default::default()
+        };
+        let live = store.create(live).await?;
+
+        // Pretend nobody has read any of them in two minutes.
+        for entry in store.entries.lock().await.values_mut() {
+            entry.last_accessed_at = Utc::now() - Duration::minutes(2);
+        }
+
+        // Test that reading a paste keeps it, while the idle one is removed.
+        assert!(store.get(ids[1]).await?.is_some());
+        assert_eq!(sweep(&store).await?, 1);
+        let entries = store.entries.lock().await;
+        assert_eq!(entries.len(), 2);
+        assert!(!entries.contains_key(&ids[0]));
+        assert!(entries.contains_key(&ids[1]));
+        assert!(entries.contains_key(&live.id));
+
+        Ok(())
+    }
+}
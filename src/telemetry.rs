@@ -0,0 +1,68 @@
+use std::{sync::OnceLock, time::Duration};
+
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+/// Counter of pastes uploaded, whether by body or by import.
+pub const UPLOADS: &str = "pstrs_uploads_total";
+
+/// Counter of pastes read, in any form, highlighted or not.
+pub const RETRIEVALS: &str = "pstrs_retrievals_total";
+
+/// Counter of pastes deleted.
+pub const DELETES: &str = "pstrs_deletes_total";
+
+/// Counter of pastes highlighted by language, whether the output was cached or
+/// not.
+pub const HIGHLIGHTS: &str = "pstrs_highlights_total";
+
+/// Histogram of how long requests took to handle, in seconds.
+pub const REQUEST_DURATION: &str = "pstrs_request_duration_seconds";
+
+/// Upper bounds of the [REQUEST_DURATION] buckets, in seconds.
+const REQUEST_DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Install the Prometheus recorder, giving a handle for rendering what it has
+/// recorded.
+///
+/// Metrics recorded before this is first called are lost, so call it on start
+/// up. Only one recorder can be installed per process, so later calls give the
+/// same handle.
+pub fn install() -> &'static PrometheusHandle {
+    HANDLE.get_or_init(|| {
+        let handle = PrometheusBuilder::new()
+            .set_buckets_for_metric(
+                Matcher::Full(REQUEST_DURATION.to_string()),
+                REQUEST_DURATION_BUCKETS,
+            )
+            .expect("request duration buckets are not empty")
+            .install_recorder()
+            .expect("failed to install the Prometheus recorder");
+
+        metrics::describe_counter!(UPLOADS, "Pastes uploaded");
+        metrics::describe_counter!(RETRIEVALS, "Pastes retrieved");
+        metrics::describe_counter!(DELETES, "Pastes deleted");
+        metrics::describe_counter!(HIGHLIGHTS, "Pastes highlighted");
+        metrics::describe_histogram!(
+            REQUEST_DURATION,
+            metrics::Unit::Seconds,
+            "How long requests took to handle"
+        );
+
+        handle
+    })
+}
+
+/// Record that a request to `path` was handled with `status` in `latency`.
+pub fn record_request(method: &str, path: &str, status: u16, latency: Duration) {
+    metrics::histogram!(
+        REQUEST_DURATION,
+        latency.as_secs_f64(),
+        "method" => method.to_string(),
+        "path" => path.to_string(),
+        "status" => status.to_string(),
+    );
+}
@@ -0,0 +1,17 @@
+/// The scheme to link to `host` with.
+///
+/// Locally we're served over plain HTTP, but once deployed, Shuttle serves us
+/// over HTTPS.
+pub fn scheme(host: &str) -> &'static str {
+    if host.contains("127.0.0.1") || host.contains("localhost") {
+        "http"
+    } else {
+        "https"
+    }
+}
+
+/// Compare two byte strings in time independent of where they differ, so
+/// secrets can't be guessed a byte at a time.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}